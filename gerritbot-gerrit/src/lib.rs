@@ -11,6 +11,10 @@ use futures::{future, Future, Sink, Stream};
 use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 
+mod rest;
+
+pub use rest::{parse_comments, CommentInfo, RestClient};
+
 /// Gerrit username
 pub type Username = String;
 
@@ -50,6 +54,17 @@ pub struct Patchset {
     pub size_deletions: i32,
     pub comments: Option<Vec<InlineComment>>,
     pub approvals: Option<Vec<Approval>>,
+    pub files: Option<Vec<PatchsetFile>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PatchsetFile {
+    pub file: String,
+    #[serde(rename = "type")]
+    pub file_type: String,
+    pub insertions: i32,
+    pub deletions: i32,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -59,6 +74,18 @@ pub struct InlineComment {
     pub line: u32,
     pub reviewer: User,
     pub message: String,
+    /// lines of the file around the commented line; not provided by Gerrit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<ContextLine>>,
+}
+
+/// A line of a file shown around an inline comment.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ContextLine {
+    pub line: u32,
+    pub text: String,
+    /// the comment refers to this line
+    pub commented: bool,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -92,6 +119,12 @@ pub struct Change {
     pub number: u32,
     pub subject: String,
     pub topic: Option<String>,
+    pub hashtags: Option<Vec<String>>,
+    #[serde(default)]
+    pub wip: bool,
+    /// private changes are only visible to their owner and reviewers
+    #[serde(default)]
+    pub private: bool,
     pub owner: User,
     pub url: String,
     pub commit_message: String,
@@ -100,6 +133,9 @@ pub struct Change {
     pub patch_sets: Option<Vec<Patchset>>,
     pub comments: Option<Vec<Comment>>,
     pub submit_records: Option<Vec<SubmitRecord>>,
+    /// only present when queried with `--all-reviewers`
+    pub all_reviewers: Option<Vec<User>>,
+    pub last_updated: Option<u64>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -160,6 +196,122 @@ pub struct ChangeAbandonedEvent {
     pub created_on: u32,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PatchsetCreatedEvent {
+    pub change: Change,
+    #[serde(rename = "patchSet")]
+    pub patchset: Patchset,
+    pub uploader: User,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WipStateChangedEvent {
+    pub change: Change,
+    #[serde(rename = "patchSet")]
+    pub patchset: Patchset,
+    pub changer: User,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PrivateStateChangedEvent {
+    pub change: Change,
+    #[serde(rename = "patchSet")]
+    pub patchset: Patchset,
+    pub changer: User,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TopicChangedEvent {
+    pub change: Change,
+    pub changer: User,
+    #[serde(rename = "oldTopic")]
+    pub old_topic: Option<String>,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HashtagsChangedEvent {
+    pub change: Change,
+    pub editor: User,
+    #[serde(default)]
+    pub added: Vec<String>,
+    #[serde(default)]
+    pub removed: Vec<String>,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct VoteDeletedEvent {
+    pub change: Change,
+    #[serde(rename = "patchSet")]
+    pub patchset: Patchset,
+    /// reviewer whose vote was removed
+    pub reviewer: User,
+    pub remover: User,
+    /// the removed votes, with the value they had before as old value
+    #[serde(default)]
+    pub approvals: Vec<Approval>,
+    pub comment: Option<String>,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReviewerDeletedEvent {
+    pub change: Change,
+    #[serde(rename = "patchSet")]
+    pub patchset: Patchset,
+    pub reviewer: User,
+    /// missing in events of older Gerrit versions
+    pub remover: Option<User>,
+    /// the votes of the reviewer, which are removed together with them
+    #[serde(default)]
+    pub approvals: Vec<Approval>,
+    pub comment: Option<String>,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
+/// Revision of a ref before it was created or after it was deleted.
+const NULL_REVISION: &str = "0000000000000000000000000000000000000000";
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RefUpdate {
+    pub old_rev: String,
+    pub new_rev: String,
+    pub ref_name: String,
+    pub project: String,
+}
+
+impl RefUpdate {
+    pub fn is_creation(&self) -> bool {
+        self.old_rev == NULL_REVISION
+    }
+
+    pub fn is_deletion(&self) -> bool {
+        self.new_rev == NULL_REVISION
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RefUpdatedEvent {
+    /// missing for updates done by Gerrit itself
+    pub submitter: Option<User>,
+    #[serde(rename = "refUpdate")]
+    pub ref_update: RefUpdate,
+    #[serde(rename = "eventCreatedOn")]
+    pub created_on: u32,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum Event {
@@ -171,15 +323,229 @@ pub enum Event {
     ChangeMerged(ChangeMergedEvent),
     #[serde(rename = "change-abandoned")]
     ChangeAbandoned(ChangeAbandonedEvent),
+    #[serde(rename = "patchset-created")]
+    PatchsetCreated(PatchsetCreatedEvent),
+    #[serde(rename = "ref-updated")]
+    RefUpdated(RefUpdatedEvent),
+    #[serde(rename = "wip-state-changed")]
+    WipStateChanged(WipStateChangedEvent),
+    #[serde(rename = "private-state-changed")]
+    PrivateStateChanged(PrivateStateChangedEvent),
+    #[serde(rename = "topic-changed")]
+    TopicChanged(TopicChangedEvent),
+    #[serde(rename = "hashtags-changed")]
+    HashtagsChanged(HashtagsChangedEvent),
+    #[serde(rename = "vote-deleted")]
+    VoteDeleted(VoteDeletedEvent),
+    #[serde(rename = "reviewer-deleted")]
+    ReviewerDeleted(ReviewerDeletedEvent),
+}
+
+/// Common accessors for events concerning a patchset of a change.
+pub trait ChangeEvent {
+    /// Event type as used in the stream events, e.g. `comment-added`.
+    fn event_type(&self) -> &'static str;
+    fn change(&self) -> &Change;
+    /// The patchset of the event; unknown for events of the whole change like
+    /// topic changes, unless the change includes its current patchset.
+    fn patchset(&self) -> Option<&Patchset>;
+    /// User the event originates from: the comment author, the added
+    /// reviewer, the submitter, the abandoner or the uploader.
+    fn actor(&self) -> &User;
+    /// Approvals that were added with this event.
+    fn approvals(&self) -> &[Approval] {
+        &[]
+    }
+}
+
+impl ChangeEvent for CommentAddedEvent {
+    fn event_type(&self) -> &'static str {
+        "comment-added"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.author
+    }
+    fn approvals(&self) -> &[Approval] {
+        self.approvals.as_deref().unwrap_or(&[])
+    }
+}
+
+impl ChangeEvent for ReviewerAddedEvent {
+    fn event_type(&self) -> &'static str {
+        "reviewer-added"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.reviewer
+    }
+}
+
+impl ChangeEvent for ChangeMergedEvent {
+    fn event_type(&self) -> &'static str {
+        "change-merged"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.submitter
+    }
+}
+
+impl ChangeEvent for ChangeAbandonedEvent {
+    fn event_type(&self) -> &'static str {
+        "change-abandoned"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.abandoner
+    }
+}
+
+impl ChangeEvent for PatchsetCreatedEvent {
+    fn event_type(&self) -> &'static str {
+        "patchset-created"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.uploader
+    }
+}
+
+impl ChangeEvent for WipStateChangedEvent {
+    fn event_type(&self) -> &'static str {
+        "wip-state-changed"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.changer
+    }
+}
+
+impl ChangeEvent for PrivateStateChangedEvent {
+    fn event_type(&self) -> &'static str {
+        "private-state-changed"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.changer
+    }
+}
+
+impl ChangeEvent for VoteDeletedEvent {
+    fn event_type(&self) -> &'static str {
+        "vote-deleted"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    fn actor(&self) -> &User {
+        &self.remover
+    }
+    fn approvals(&self) -> &[Approval] {
+        &self.approvals
+    }
+}
+
+impl ChangeEvent for ReviewerDeletedEvent {
+    fn event_type(&self) -> &'static str {
+        "reviewer-deleted"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        Some(&self.patchset)
+    }
+    /// The remover, or the change owner if the event doesn't tell.
+    fn actor(&self) -> &User {
+        self.remover.as_ref().unwrap_or(&self.change.owner)
+    }
+}
+
+impl ChangeEvent for TopicChangedEvent {
+    fn event_type(&self) -> &'static str {
+        "topic-changed"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        self.change.current_patch_set.as_ref()
+    }
+    fn actor(&self) -> &User {
+        &self.changer
+    }
+}
+
+impl ChangeEvent for HashtagsChangedEvent {
+    fn event_type(&self) -> &'static str {
+        "hashtags-changed"
+    }
+    fn change(&self) -> &Change {
+        &self.change
+    }
+    fn patchset(&self) -> Option<&Patchset> {
+        self.change.current_patch_set.as_ref()
+    }
+    fn actor(&self) -> &User {
+        &self.editor
+    }
 }
 
 impl Event {
-    fn change_and_patchset_mut(&mut self) -> Option<(&mut Change, &mut Patchset)> {
+    /// The change of the event, and its patchset if the event has one.
+    fn change_and_patchset_mut(&mut self) -> Option<(&mut Change, Option<&mut Patchset>)> {
         Some(match self {
-            Event::CommentAdded(event) => (&mut event.change, &mut event.patchset),
-            Event::ReviewerAdded(event) => (&mut event.change, &mut event.patchset),
-            Event::ChangeMerged(event) => (&mut event.change, &mut event.patchset),
-            Event::ChangeAbandoned(event) => (&mut event.change, &mut event.patchset),
+            Event::CommentAdded(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::ReviewerAdded(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::ChangeMerged(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::ChangeAbandoned(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::PatchsetCreated(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::WipStateChanged(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::PrivateStateChanged(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::TopicChanged(event) => (&mut event.change, None),
+            Event::HashtagsChanged(event) => (&mut event.change, None),
+            Event::VoteDeleted(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::ReviewerDeleted(event) => (&mut event.change, Some(&mut event.patchset)),
+            Event::RefUpdated(_) => return None,
         })
     }
 }
@@ -377,6 +743,69 @@ impl CommandRunner {
     }
 }
 
+/// Parse the tab separated output of `gerrit ls-members`. The first line is a
+/// header, missing values are reported as "n/a".
+fn parse_group_members(output: &str) -> Vec<User> {
+    let value = |s: Option<&str>| s.filter(|s| !s.is_empty() && *s != "n/a").map(String::from);
+
+    output
+        .lines()
+        .skip(1)
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut columns = line.split('\t').skip(1);
+            let username = value(columns.next());
+            let name = value(columns.next());
+            let email = value(columns.next());
+            User {
+                name,
+                username,
+                email,
+            }
+        })
+        .collect()
+}
+
+/// Parse the JSON output of `gerrit query`. The last line contains statistics
+/// instead of a change.
+fn parse_query_result(output: &str) -> Result<Vec<Change>, String> {
+    output
+        .lines()
+        .filter(|line| !line.contains(r#""type":"stats""#))
+        .map(|line| {
+            serde_json::from_str(line).map_err(|e| format!("failed to decode change: {}", e))
+        })
+        .collect()
+}
+
+/// Query changes including all of their reviewers and the current patchset
+/// with its approvals.
+pub fn query_changes(
+    command_runner: &mut CommandRunner,
+    query: &str,
+) -> impl Future<Item = Vec<Change>, Error = String> {
+    command_runner
+        .run_command(format!(
+            "gerrit query --format=JSON --all-reviewers --current-patch-set {}",
+            query
+        ))
+        .and_then(|output| parse_query_result(&output))
+}
+
+/// List the members of a Gerrit group, including members of included groups.
+pub fn list_group_members(
+    command_runner: &mut CommandRunner,
+    group: &str,
+) -> impl Future<Item = Vec<User>, Error = String> {
+    let command = format!(
+        "gerrit ls-members --recursive \"{}\"",
+        group.replace('"', "\\\"")
+    );
+    command_runner
+        .run_command(command)
+        .map(|output| parse_group_members(&output))
+}
+
 fn receiver_into_event_stream(rx: Receiver<String>) -> impl Stream<Item = Event, Error = ()> {
     rx.filter_map(|event_data| {
         serde_json::from_str(&event_data)
@@ -390,7 +819,15 @@ const GERRIT_STREAM_EVENTS_COMMAND: &str = "gerrit stream-events \
                                             -s comment-added \
                                             -s reviewer-added \
                                             -s change-abandoned \
-                                            -s change-merged";
+                                            -s change-merged \
+                                            -s patchset-created \
+                                            -s ref-updated \
+                                            -s wip-state-changed \
+                                            -s private-state-changed \
+                                            -s topic-changed \
+                                            -s hashtags-changed \
+                                            -s vote-deleted \
+                                            -s reviewer-deleted";
 
 pub fn event_stream(connection: Connection) -> impl Stream<Item = Event, Error = ()> {
     let (main_tx, rx) = channel(1);
@@ -444,6 +881,8 @@ pub enum ExtendedInfo {
     SubmitRecords,
     InlineComments,
     AllApprovals,
+    Files,
+    AllReviewers,
 }
 
 /// Fetch extended event info. On error the original event and an error message
@@ -472,6 +911,14 @@ fn fetch_extended_info(
         query += " --all-approvals";
     }
 
+    if extended_info.contains(&ExtendedInfo::Files) {
+        query += " --current-patch-set --files";
+    }
+
+    if extended_info.contains(&ExtendedInfo::AllReviewers) {
+        query += " --all-reviewers";
+    }
+
     let change_id = if let Some((change, _)) = event.change_and_patchset_mut() {
         &change.id
     } else {
@@ -498,18 +945,31 @@ fn fetch_extended_info(
                 Err(e) => return Err((event, format!("failed to decode result: {}", e))),
             };
 
-            // copy patchset from change for the comments
-            if let Some(patchsets) = new_change.patch_sets.take() {
-                if let Some(new_patchset) = patchsets
-                    .into_iter()
-                    .find(|new_patchset| new_patchset.number == patchset.number)
-                {
-                    *patchset = new_patchset;
+            if let Some(patchset) = patchset {
+                // copy patchset from change for the comments
+                if let Some(patchsets) = new_change.patch_sets.take() {
+                    if let Some(new_patchset) = patchsets
+                        .into_iter()
+                        .find(|new_patchset| new_patchset.number == patchset.number)
+                    {
+                        *patchset = new_patchset;
+                    }
+                }
+
+                // copy over the files of the current patchset, unless they
+                // were already copied with the patchset above
+                if let Some(current_patchset) = new_change.current_patch_set.take() {
+                    if current_patchset.number == patchset.number && patchset.files.is_none() {
+                        patchset.files = current_patchset.files;
+                    }
                 }
             }
 
-            // copy over submit records
+            // copy over submit records and reviewers
             change.submit_records = new_change.submit_records.take();
+            if new_change.all_reviewers.is_some() {
+                change.all_reviewers = new_change.all_reviewers.take();
+            }
 
             Ok(event)
         },
@@ -558,6 +1018,32 @@ mod test {
 {"reviewer":{"name":"jdoe","email":"john.doe@localhost","username":"jdoe"},"patchSet":{"number":1,"revision":"c4f7d43450e366f9c8e4dcb94fbd91573cd40766","parents":["20332c6ee056bdf3f814c8cff9905154d443d2f0"],"ref":"refs/changes/01/1/1","uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"createdOn":1553631812,"author":{"name":"Frank Benkstein","email":"frank@benkstein.net","username":""},"isDraft":false,"kind":"REWORK","sizeInsertions":0,"sizeDeletions":-18},"change":{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"assignee":{"name":"jdoe","email":"john.doe@localhost","username":"jdoe"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n\nChange-Id: I5e53df227fd2739ddd65c3034b2f9f789200bd89\n","createdOn":1553631812,"status":"NEW"},"project":"gerritbot-rs","refName":"refs/heads/master","changeKey":{"id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89"},"type":"reviewer-added","eventCreatedOn":1553632329}
 "#;
 
+    const PATCHSET_CREATED_JSON: &str = r#"
+{"uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"patchSet":{"number":2,"revision":"a8a2bbc4ee1bf9b6e8ba2a74ad9e2ee0a2dbc6a9","parents":["20332c6ee056bdf3f814c8cff9905154d443d2f0"],"ref":"refs/changes/01/1/2","uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"createdOn":1553632812,"author":{"name":"Frank Benkstein","email":"frank@benkstein.net","username":""},"kind":"REWORK","sizeInsertions":3,"sizeDeletions":-1},"change":{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n\nChange-Id: I5e53df227fd2739ddd65c3034b2f9f789200bd89\n","createdOn":1553631812,"status":"NEW"},"project":"gerritbot-rs","refName":"refs/heads/master","changeKey":{"id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89"},"type":"patchset-created","eventCreatedOn":1553632812}
+"#;
+
+    const REF_UPDATED_JSON: &str = r#"
+{"submitter":{"name":"Administrator","email":"admin@example.com","username":"admin"},"refUpdate":{"oldRev":"0000000000000000000000000000000000000000","newRev":"c4f7d43450e366f9c8e4dcb94fbd91573cd40766","refName":"refs/tags/v1.0","project":"gerritbot-rs"},"type":"ref-updated","eventCreatedOn":1553633000}
+"#;
+
+    const HASHTAGS_CHANGED_JSON: &str = r#"
+{"change":{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n","hashtags":["Refactoring"],"createdOn":1553631812,"status":"NEW"},"editor":{"name":"Administrator","email":"admin@example.com","username":"admin"},"added":["Refactoring"],"hashtags":["Refactoring"],"project":"gerritbot-rs","refName":"refs/heads/master","changeKey":{"id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89"},"type":"hashtags-changed","eventCreatedOn":1553633100}
+"#;
+
+    const VOTE_DELETED_JSON: &str = r#"
+{"change":{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n","createdOn":1553631812,"status":"NEW"},"patchSet":{"number":2,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":["fb1909b4eda306985d2bbce769310e5a50a98cf5"],"ref":"refs/changes/01/1/2","uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"createdOn":1553631812,"author":{"name":"Administrator","email":"admin@example.com","username":"admin"},"kind":"REWORK","sizeInsertions":3,"sizeDeletions":-1},"reviewer":{"name":"Reviewer","email":"reviewer@example.com","username":"reviewer"},"remover":{"name":"Administrator","email":"admin@example.com","username":"admin"},"approvals":[{"type":"Code-Review","description":"Code-Review","value":"0","oldValue":"-2"}],"comment":"Removed Code-Review-2 by Reviewer \u003creviewer@example.com\u003e\n","project":"gerritbot-rs","refName":"refs/heads/master","changeKey":{"id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89"},"type":"vote-deleted","eventCreatedOn":1553633300}
+"#;
+
+    const REVIEWER_DELETED_JSON: &str = r#"
+{"change":{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n","createdOn":1553631812,"status":"NEW"},"patchSet":{"number":2,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":["fb1909b4eda306985d2bbce769310e5a50a98cf5"],"ref":"refs/changes/01/1/2","uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"createdOn":1553631812,"author":{"name":"Administrator","email":"admin@example.com","username":"admin"},"kind":"REWORK","sizeInsertions":3,"sizeDeletions":-1},"reviewer":{"name":"Reviewer","email":"reviewer@example.com","username":"reviewer"},"approvals":[{"type":"Code-Review","description":"Code-Review","value":"0"}],"comment":"Removed reviewer Reviewer \u003creviewer@example.com\u003e.","project":"gerritbot-rs","refName":"refs/heads/master","changeKey":{"id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89"},"type":"reviewer-deleted","eventCreatedOn":1553633400}
+"#;
+
+    const TOPIC_CHANGED_JSON: &str = r#"
+{"change":{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n","topic":"networking","createdOn":1553631812,"status":"NEW"},"changer":{"name":"Administrator","email":"admin@example.com","username":"admin"},"oldTopic":"sockets","project":"gerritbot-rs","refName":"refs/heads/master","changeKey":{"id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89"},"type":"topic-changed","eventCreatedOn":1553633200}
+"#;
+
+    const CHANGE_WITH_FILES_JSON: &str = r#"{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n","createdOn":1553631812,"status":"NEW","currentPatchSet":{"number":2,"revision":"a8a2bbc4ee1bf9b6e8ba2a74ad9e2ee0a2dbc6a9","parents":["20332c6ee056bdf3f814c8cff9905154d443d2f0"],"ref":"refs/changes/01/1/2","uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"createdOn":1553632812,"author":{"name":"Frank Benkstein","email":"frank@benkstein.net","username":""},"kind":"REWORK","sizeInsertions":3,"sizeDeletions":-1,"files":[{"file":"/COMMIT_MSG","type":"ADDED","insertions":7,"deletions":0},{"file":"src/main.rs","type":"MODIFIED","insertions":3,"deletions":-1}]}}"#;
+
     #[test]
     fn test_deserialize_comment_added() {
         let event: Event =
@@ -586,4 +1072,116 @@ mod test {
             _ => panic!("unexpected_event: {:?}", event),
         }
     }
+
+    #[test]
+    fn test_patchset_created() {
+        let event: Event =
+            serde_json::from_str(PATCHSET_CREATED_JSON).expect("failed to deserialize event");
+        match event {
+            Event::PatchsetCreated(event) => {
+                assert_that!(event.patchset.number).is_equal_to(2);
+                assert_that!(event.event_type()).is_equal_to("patchset-created");
+            }
+            _ => panic!("unexpected_event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_ref_updated() {
+        let event: Event =
+            serde_json::from_str(REF_UPDATED_JSON).expect("failed to deserialize event");
+        match event {
+            Event::RefUpdated(event) => {
+                assert_that!(event.ref_update.ref_name.as_str()).is_equal_to("refs/tags/v1.0");
+                assert_that!(event.ref_update.is_creation()).is_true();
+                assert_that!(event.ref_update.is_deletion()).is_false();
+                assert_that!(event.submitter).is_some();
+            }
+            _ => panic!("unexpected_event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_state_changes() {
+        let event: Event =
+            serde_json::from_str(HASHTAGS_CHANGED_JSON).expect("failed to deserialize event");
+        match event {
+            Event::HashtagsChanged(event) => {
+                assert_that!(event.added).is_equal_to(vec!["Refactoring".to_string()]);
+                assert_that!(event.removed).is_empty();
+            }
+            _ => panic!("unexpected_event: {:?}", event),
+        }
+
+        let event: Event =
+            serde_json::from_str(TOPIC_CHANGED_JSON).expect("failed to deserialize event");
+        match event {
+            Event::TopicChanged(event) => {
+                assert_that!(event.old_topic.as_deref()).is_equal_to(Some("sockets"));
+                assert_that!(event.change.topic.as_deref()).is_equal_to(Some("networking"));
+            }
+            _ => panic!("unexpected_event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_removals() {
+        let event: Event =
+            serde_json::from_str(VOTE_DELETED_JSON).expect("failed to deserialize event");
+        match event {
+            Event::VoteDeleted(event) => {
+                assert_that!(event.approvals).has_length(1);
+                assert_that!(event.approvals[0].old_value.as_deref()).is_equal_to(Some("-2"));
+                assert_that!(event.actor().username.as_deref()).is_equal_to(Some("admin"));
+            }
+            _ => panic!("unexpected_event: {:?}", event),
+        }
+
+        let event: Event =
+            serde_json::from_str(REVIEWER_DELETED_JSON).expect("failed to deserialize event");
+        match event {
+            Event::ReviewerDeleted(event) => {
+                assert_that!(event.remover).is_none();
+                // falls back to the owner
+                assert_that!(event.actor().username.as_deref()).is_equal_to(Some("admin"));
+                assert_that!(event.reviewer.username.as_deref()).is_equal_to(Some("reviewer"));
+            }
+            _ => panic!("unexpected_event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_deserialize_files() {
+        let change: Change =
+            serde_json::from_str(CHANGE_WITH_FILES_JSON).expect("failed to deserialize change");
+        let files = change.current_patch_set.and_then(|patchset| patchset.files);
+        assert_that!(files).is_some().has_length(2);
+    }
+
+    #[test]
+    fn test_parse_group_members() {
+        let output = "id\tusername\tfull name\temail\n\
+                      1000001\tjenkins\tJenkins CI\tjenkins@example.com\n\
+                      1000002\tlinter\tn/a\tn/a\n";
+        let members = parse_group_members(output);
+        assert_that!(members).has_length(2);
+        assert_that!(members[0].username.as_deref()).is_equal_to(Some("jenkins"));
+        assert_that!(members[0].name.as_deref()).is_equal_to(Some("Jenkins CI"));
+        assert_that!(members[0].email.as_deref()).is_equal_to(Some("jenkins@example.com"));
+        assert_that!(members[1].username.as_deref()).is_equal_to(Some("linter"));
+        assert_that!(members[1].email).is_none();
+    }
+
+    #[test]
+    fn test_parse_query_result() {
+        let output = format!(
+            "{}\n{}\n",
+            r#"{"project":"gerritbot-rs","branch":"master","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"get rid of non-macro extern crate","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"get rid of non-macro extern crate\n","createdOn":1553631812,"lastUpdated":1553632440,"status":"NEW","allReviewers":[{"name":"jdoe","email":"john.doe@localhost","username":"jdoe"}]}"#,
+            r#"{"type":"stats","rowCount":1,"runTimeMilliseconds":5,"moreChanges":false}"#
+        );
+        let changes = parse_query_result(&output).expect("parsing failed");
+        assert_that!(changes).has_length(1);
+        assert_that!(changes[0].last_updated).is_equal_to(Some(1553632440));
+        assert_that!(changes[0].all_reviewers.as_ref().map(Vec::len)).is_equal_to(Some(1));
+    }
 }
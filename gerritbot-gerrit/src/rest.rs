@@ -0,0 +1,227 @@
+//! Minimal client for the Gerrit REST API, for information that is not
+//! available via SSH.
+
+use std::collections::BTreeMap;
+
+use futures::{Future, Stream};
+use serde::{Deserialize, Serialize};
+
+use crate::User;
+
+/// Prefix of all JSON responses of Gerrit, to prevent XSSI.
+const XSSI_PREFIX: &str = ")]}'";
+
+/// A published inline comment as returned by `GET /changes/{id}/comments`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CommentInfo {
+    pub id: String,
+    /// file of the comment; filled in from the key of the response
+    #[serde(default)]
+    pub path: String,
+    pub patch_set: Option<u32>,
+    pub line: Option<u32>,
+    /// id of the comment this one replies to
+    pub in_reply_to: Option<String>,
+    pub message: String,
+    /// timestamp in the format `YYYY-MM-DD hh:mm:ss.fffffffff` (UTC)
+    pub updated: String,
+    pub author: Option<User>,
+    #[serde(default)]
+    pub unresolved: bool,
+}
+
+/// Response of `GET /changes/{id}/revisions/{revision}/mergeable`.
+#[derive(Deserialize, Debug)]
+struct MergeableInfo {
+    mergeable: bool,
+}
+
+fn strip_xssi_prefix(body: &str) -> &str {
+    let body = body.trim_start();
+    body.strip_prefix(XSSI_PREFIX).unwrap_or(body)
+}
+
+/// Parse the response of `GET /changes/{id}/comments`. Comments are sorted
+/// by time.
+pub fn parse_comments(body: &str) -> Result<Vec<CommentInfo>, String> {
+    let body = strip_xssi_prefix(body);
+    let files: BTreeMap<String, Vec<CommentInfo>> =
+        serde_json::from_str(body).map_err(|e| format!("failed to decode comments: {}", e))?;
+
+    let mut comments: Vec<CommentInfo> = files
+        .into_iter()
+        .flat_map(|(path, comments)| {
+            comments.into_iter().map(move |mut comment| {
+                comment.path = path.clone();
+                comment
+            })
+        })
+        .collect();
+    comments.sort_by(|c1, c2| c1.updated.cmp(&c2.updated));
+    Ok(comments)
+}
+
+/// Parse the response of `GET /changes/{id}/revisions/{revision}/mergeable`.
+fn parse_mergeable(body: &str) -> Result<bool, String> {
+    serde_json::from_str(strip_xssi_prefix(body))
+        .map(|info: MergeableInfo| info.mergeable)
+        .map_err(|e| format!("failed to decode mergeability: {}", e))
+}
+
+/// Percent-encode everything except unreserved characters, including slashes.
+fn encode_path_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct RestClient {
+    client: reqwest::r#async::Client,
+    url: String,
+    username: String,
+    password: String,
+}
+
+impl RestClient {
+    /// Create a client for the Gerrit instance at the given URL, authenticated
+    /// with the HTTP password of the user.
+    pub fn new(url: String, username: String, password: String) -> Self {
+        Self {
+            client: reqwest::r#async::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            username,
+            password,
+        }
+    }
+
+    fn get(&self, resource: &str) -> impl Future<Item = String, Error = String> {
+        self.client
+            .get(&format!("{}/a/{}", self.url, resource))
+            .basic_auth(&self.username, Some(&self.password))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.into_body().concat2())
+            .map(|body| String::from_utf8_lossy(&body).into_owned())
+            .map_err(|e| format!("request to gerrit failed: {}", e))
+    }
+
+    /// Get the content of a file at the given revision of a change.
+    pub fn get_file_content(
+        &self,
+        change_number: u32,
+        revision: &str,
+        file: &str,
+    ) -> impl Future<Item = String, Error = String> {
+        self.get(&format!(
+            "changes/{}/revisions/{}/files/{}/content",
+            change_number,
+            revision,
+            encode_path_segment(file)
+        ))
+        .and_then(|body| {
+            base64::decode(body.trim())
+                .map(|content| String::from_utf8_lossy(&content).into_owned())
+                .map_err(|e| format!("failed to decode file content: {}", e))
+        })
+    }
+
+    /// Check if the current revision of a change can be merged into its
+    /// target branch.
+    pub fn get_mergeable(&self, change_number: u32) -> impl Future<Item = bool, Error = String> {
+        self.get(&format!(
+            "changes/{}/revisions/current/mergeable",
+            change_number
+        ))
+        .and_then(|body| parse_mergeable(&body))
+    }
+
+    /// Get the published inline comments of all patchsets of a change.
+    pub fn get_comments(
+        &self,
+        change_number: u32,
+    ) -> impl Future<Item = Vec<CommentInfo>, Error = String> {
+        self.get(&format!("changes/{}/comments", change_number))
+            .and_then(|body| parse_comments(&body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_file_paths() {
+        assert_eq!(
+            encode_path_segment("src/net/my socket.rs"),
+            "src%2Fnet%2Fmy%20socket.rs"
+        );
+    }
+
+    #[test]
+    fn parse_mergeable_response() {
+        let body = ")]}'\n{\n  \"submit_type\": \"MERGE_IF_NECESSARY\",\n  \"strategy\": \"recursive\",\n  \"mergeable\": false\n}";
+        assert_eq!(parse_mergeable(body), Ok(false));
+        assert!(parse_mergeable(")]}'\n{}").is_err());
+    }
+
+    #[test]
+    fn parse_comments_response() {
+        let body = r#")]}'
+{
+  "src/main.rs": [
+    {
+      "id": "b2a3b8e4_02fe4c5f",
+      "patch_set": 2,
+      "line": 3,
+      "in_reply_to": "a1b2c3d4_5e6f7a8b",
+      "message": "Done",
+      "updated": "2019-04-02 10:00:00.000000000",
+      "author": {"_account_id": 1000096, "name": "Author", "email": "author@example.com", "username": "author"},
+      "unresolved": false
+    },
+    {
+      "id": "a1b2c3d4_5e6f7a8b",
+      "patch_set": 1,
+      "line": 3,
+      "message": "Please rename this.",
+      "updated": "2019-04-01 10:00:00.000000000",
+      "author": {"_account_id": 1000097, "name": "Reviewer", "email": "reviewer@example.com", "username": "reviewer"},
+      "unresolved": true
+    }
+  ],
+  "/COMMIT_MSG": [
+    {
+      "id": "c3d4e5f6_7a8b9c0d",
+      "patch_set": 1,
+      "message": "Typo",
+      "updated": "2019-04-01 11:00:00.000000000"
+    }
+  ]
+}"#;
+        let comments = parse_comments(body).unwrap();
+        let ids: Vec<_> = comments.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            vec![
+                "a1b2c3d4_5e6f7a8b",
+                "c3d4e5f6_7a8b9c0d",
+                "b2a3b8e4_02fe4c5f"
+            ]
+        );
+        assert_eq!(comments[0].path, "src/main.rs");
+        assert!(comments[0].unresolved);
+        assert_eq!(comments[1].path, "/COMMIT_MSG");
+        assert!(!comments[1].unresolved);
+        assert_eq!(
+            comments[2].in_reply_to.as_deref(),
+            Some("a1b2c3d4_5e6f7a8b")
+        );
+    }
+}
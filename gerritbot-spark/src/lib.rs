@@ -3,8 +3,10 @@
 use std::convert::identity;
 use std::io;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use futures::future::{self, Future};
+use futures::future::{self, Future, Loop};
 use futures::sync::mpsc::channel;
 use futures::{IntoFuture as _, Sink, Stream};
 use log::{debug, error, info, warn};
@@ -54,6 +56,10 @@ macro_rules! newtype_string {
             pub fn new(s: &str) -> &Self {
                 unsafe { &*(s as *const str as *const Self) }
             }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
         }
 
         impl std::fmt::Display for $type_ref_name {
@@ -318,16 +324,73 @@ struct Webhooks {
     items: Vec<Webhook>,
 }
 
+/// Error response of the Webex API.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct ErrorBody {
+    message: Option<String>,
+    tracking_id: Option<String>,
+}
+
 //
 // Client
 //
 
+/// Waiting time after a rate limited request without a valid `Retry-After`
+/// header.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Maximum number of retries of GET requests.
+const MAX_GET_RETRIES: u32 = 3;
+
+/// Delay before the first retry of a failed GET request, doubled for every
+/// further retry. Rate limited requests wait for the throttle instead.
+const GET_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// No requests are sent before the stored instant, after Webex rate limited
+/// one of them. Shared by all clones of a client.
+#[derive(Debug, Clone, Default)]
+struct Throttle(Arc<Mutex<Option<Instant>>>);
+
+impl Throttle {
+    /// Time left until requests can be sent again.
+    fn remaining(&self) -> Option<Duration> {
+        let until = (*self.0.lock().unwrap())?;
+        let now = Instant::now();
+        if until > now {
+            Some(until - now)
+        } else {
+            None
+        }
+    }
+
+    fn block_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut blocked_until = self.0.lock().unwrap();
+        match *blocked_until {
+            Some(blocked_until) if blocked_until >= until => (),
+            _ => *blocked_until = Some(until),
+        }
+    }
+
+    /// Wait until requests can be sent again.
+    fn wait(&self) -> impl Future<Item = (), Error = Error> {
+        match self.remaining() {
+            Some(remaining) => {
+                future::Either::A(tokio::timer::Delay::new(Instant::now() + remaining).from_err())
+            }
+            None => future::Either::B(future::ok(())),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Client {
     client: reqwest::r#async::Client,
     url: String,
     bot_token: String,
     bot_id: PersonId,
+    throttle: Throttle,
 }
 
 #[derive(Debug, Error)]
@@ -345,6 +408,112 @@ pub enum Error {
     DeleteWebhook(String),
     #[error(transparent)]
     IoError(#[from] io::Error),
+    #[error(transparent)]
+    TimerError(#[from] tokio::timer::Error),
+    #[error("Webex API request failed with {status}: {message} (tracking id: {})", .tracking_id.as_deref().unwrap_or("none"))]
+    Api {
+        status: http::StatusCode,
+        message: String,
+        tracking_id: Option<String>,
+    },
+    #[error("rate limited by Webex API, retry after {} s (tracking id: {})", .retry_after.as_secs(), .tracking_id.as_deref().unwrap_or("none"))]
+    RateLimited {
+        retry_after: Duration,
+        tracking_id: Option<String>,
+    },
+}
+
+impl Error {
+    /// Decode the error response of a request.
+    fn from_response(status: http::StatusCode, retry_after: Option<Duration>, body: &[u8]) -> Self {
+        let ErrorBody {
+            message,
+            tracking_id,
+        } = serde_json::from_slice(body).unwrap_or_default();
+        match retry_after {
+            Some(retry_after) => Error::RateLimited {
+                retry_after,
+                tracking_id,
+            },
+            None => Error::Api {
+                status,
+                message: message.unwrap_or_else(|| String::from_utf8_lossy(body).into_owned()),
+                tracking_id,
+            },
+        }
+    }
+
+    /// Time to wait before trying again, if the request was rate limited.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::RateLimited { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    /// Whether the request cannot succeed when it is sent again, e.g. because
+    /// it is invalid or the recipient doesn't exist.
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Error::Api { status, .. } => matches!(
+                *status,
+                http::StatusCode::BAD_REQUEST
+                    | http::StatusCode::FORBIDDEN
+                    | http::StatusCode::NOT_FOUND
+                    | http::StatusCode::PAYLOAD_TOO_LARGE
+            ),
+            _ => false,
+        }
+    }
+
+    /// Whether sending the same request again later might succeed.
+    fn is_transient(&self) -> bool {
+        match self {
+            Error::ReqwestError(_) | Error::HyperError(_) | Error::IoError(_) => true,
+            Error::RateLimited { .. } => true,
+            Error::Api { status, .. } => status.is_server_error(),
+            _ => false,
+        }
+    }
+}
+
+/// Parse the `Retry-After` header in seconds. Dates are not supported.
+fn parse_retry_after(headers: &http::HeaderMap) -> Duration {
+    headers
+        .get(http::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+}
+
+/// Turn error responses into errors. Rate limited responses block further
+/// requests until the time given by Webex.
+fn check_response(
+    response: reqwest::r#async::Response,
+    throttle: &Throttle,
+) -> impl Future<Item = reqwest::r#async::Response, Error = Error> {
+    let status = response.status();
+    if status.is_success() {
+        return future::Either::A(future::ok(response));
+    }
+
+    let retry_after = if status == http::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = parse_retry_after(response.headers());
+        warn!("rate limited by Webex API for {} s", retry_after.as_secs());
+        throttle.block_for(retry_after);
+        Some(retry_after)
+    } else {
+        None
+    };
+
+    future::Either::B(
+        response
+            .into_body()
+            .concat2()
+            .from_err()
+            .and_then(move |body| Err(Error::from_response(status, retry_after, &body))),
+    )
 }
 
 impl Client {
@@ -357,6 +526,7 @@ impl Client {
             url: spark_api_url,
             bot_token,
             bot_id: PersonId(String::new()),
+            throttle: Default::default(),
         };
 
         bootstrap_client.get_bot_id().map(|bot_id| Client {
@@ -365,43 +535,94 @@ impl Client {
         })
     }
 
+    fn api_request(
+        &self,
+        method: http::Method,
+        resource: &str,
+    ) -> reqwest::r#async::RequestBuilder {
+        self.client
+            .request(method, &format!("{}/{}", self.url, resource))
+            .bearer_auth(&self.bot_token)
+            .header(http::header::ACCEPT, "application/json")
+    }
+
+    /// Send a request unless the client is rate limited, and check the
+    /// response.
+    fn api_send(
+        &self,
+        request: reqwest::r#async::RequestBuilder,
+    ) -> impl Future<Item = reqwest::r#async::Response, Error = Error> {
+        if let Some(retry_after) = self.throttle.remaining() {
+            return future::Either::A(future::err(Error::RateLimited {
+                retry_after,
+                tracking_id: None,
+            }));
+        }
+        let throttle = self.throttle.clone();
+        future::Either::B(
+            request
+                .send()
+                .from_err()
+                .and_then(move |response| check_response(response, &throttle)),
+        )
+    }
+
     /// Try to get json from the given url with basic token authorization.
+    /// Failed requests are retried after a delay or when the client is not
+    /// rate limited anymore.
     fn api_get_json<T>(&self, resource: &str) -> impl Future<Item = T, Error = Error>
     where
         for<'a> T: Deserialize<'a>,
     {
-        reqwest::r#async::Client::new()
-            .get(&format!("{}/{}", self.url, resource))
-            .bearer_auth(&self.bot_token)
-            .header(http::header::ACCEPT, "application/json")
-            .send()
-            .from_err()
-            .and_then(|response| decode_json_body(response.into_body()))
+        let client = self.clone();
+        let resource = resource.to_string();
+        future::loop_fn(0, move |retries| {
+            let request = client.api_request(http::Method::GET, &resource);
+            let send_client = client.clone();
+            let throttle = client.throttle.clone();
+            client
+                .throttle
+                .wait()
+                .and_then(move |()| send_client.api_send(request))
+                .then(
+                    move |result| -> Box<dyn Future<Item = _, Error = Error> + Send> {
+                        match result {
+                            Err(e) if retries < MAX_GET_RETRIES && e.is_transient() => {
+                                debug!("retrying request after error: {}", e);
+                                if e.retry_after().is_some() {
+                                    // the throttle is waited for before sending
+                                    return Box::new(
+                                        throttle.wait().map(move |()| Loop::Continue(retries + 1)),
+                                    );
+                                }
+                                let delay = GET_RETRY_DELAY * 2u32.pow(retries);
+                                Box::new(
+                                    tokio::timer::Delay::new(Instant::now() + delay)
+                                        .from_err()
+                                        .map(move |()| Loop::Continue(retries + 1)),
+                                )
+                            }
+                            result => Box::new(future::result(result.map(Loop::Break))),
+                        }
+                    },
+                )
+        })
+        .and_then(|response| decode_json_body(response.into_body()))
     }
 
     /// Try to post json to the given url with basic token authorization.
+    /// Posts are not retried, as they are not idempotent.
     fn api_post_json<T>(&self, resource: &str, data: &T) -> impl Future<Item = (), Error = Error>
     where
         T: Serialize,
     {
-        self.client
-            .post(&format!("{}/{}", self.url, resource))
-            .bearer_auth(&self.bot_token)
-            .header(http::header::ACCEPT, "application/json")
-            .json(data)
-            .send()
-            .from_err()
+        self.api_send(self.api_request(http::Method::POST, resource).json(data))
             .map(|_| ())
     }
 
-    /// Try to post json to the given url with basic token authorization.
+    /// Try to delete the given url with basic token authorization.
     fn api_delete(&self, resource: &str) -> impl Future<Item = (), Error = Error> {
-        self.client
-            .delete(&format!("{}/{}", self.url, resource))
-            .bearer_auth(&self.bot_token)
-            .header(http::header::ACCEPT, "application/json")
-            .send()
-            .from_err()
+        self.api_send(self.api_request(http::Method::DELETE, resource))
             .map(|_| ())
     }
 
@@ -431,12 +652,8 @@ impl Client {
     fn delete_webhook(&self, id: &WebhookId) -> impl Future<Item = (), Error = Error> {
         self.api_delete(&format!("webhooks/{}", id))
             .or_else(|e| match e {
-                Error::ReqwestError(ref e)
-                    if e.status() == Some(http::StatusCode::NO_CONTENT)
-                        || e.status() == Some(http::StatusCode::NOT_FOUND) =>
-                {
-                    Ok(())
-                }
+                // already deleted
+                Error::Api { status, .. } if status == http::StatusCode::NOT_FOUND => Ok(()),
                 _ => Err(Error::DeleteWebhook(format!(
                     "Could not delete webhook: {}",
                     e
@@ -705,4 +922,60 @@ mod test {
         let ref_p: &PersonIdRef = &p;
         assert_eq!(p, ref_p);
     }
+
+    #[test]
+    fn decode_error_responses() {
+        let body = br#"{"message":"Failed to get person","errors":[{"description":"Failed to get person"}],"trackingId":"ROUTER_5CB5EB30-9B5A-01BB-00BD-AC1239E300BD"}"#;
+        let error = Error::from_response(http::StatusCode::NOT_FOUND, None, body);
+        assert!(error.is_permanent());
+        assert!(!error.is_transient());
+        assert_eq!(
+            error.to_string(),
+            "Webex API request failed with 404 Not Found: Failed to get person \
+             (tracking id: ROUTER_5CB5EB30-9B5A-01BB-00BD-AC1239E300BD)"
+        );
+
+        let error = Error::from_response(http::StatusCode::BAD_GATEWAY, None, b"Bad Gateway");
+        assert!(error.is_transient());
+        assert_eq!(
+            error.to_string(),
+            "Webex API request failed with 502 Bad Gateway: Bad Gateway (tracking id: none)"
+        );
+
+        let error = Error::from_response(
+            http::StatusCode::TOO_MANY_REQUESTS,
+            Some(Duration::from_secs(30)),
+            b"",
+        );
+        assert!(error.is_transient());
+        assert!(!error.is_permanent());
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(parse_retry_after(&headers), DEFAULT_RETRY_AFTER);
+        headers.insert(
+            http::header::RETRY_AFTER,
+            http::HeaderValue::from_static("120"),
+        );
+        assert_eq!(parse_retry_after(&headers), Duration::from_secs(120));
+        headers.insert(
+            http::header::RETRY_AFTER,
+            http::HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(parse_retry_after(&headers), DEFAULT_RETRY_AFTER);
+    }
+
+    #[test]
+    fn throttle_is_shared() {
+        let throttle = Throttle::default();
+        let other = throttle.clone();
+        assert!(other.remaining().is_none());
+        throttle.block_for(Duration::from_secs(60));
+        // a shorter block doesn't shorten an existing one
+        throttle.block_for(Duration::from_secs(1));
+        assert!(other.remaining().unwrap() > Duration::from_secs(50));
+    }
 }
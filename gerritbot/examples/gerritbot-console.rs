@@ -120,6 +120,22 @@ impl bot::SparkClient for ConsoleSparkClient {
         }
         future::ok(())
     }
+
+    fn send_room_message(&self, room_id: &spark::RoomIdRef, msg: &str) -> Self::ReplyFuture {
+        match self {
+            ConsoleSparkClient::Plain => writeln!(std::io::stdout(), "room {}: {}", room_id, msg)
+                .expect("writing to stdout failed"),
+            ConsoleSparkClient::Json => {
+                let message = serde_json::json!({ "roomId": room_id, "text": msg });
+                serde_json::to_writer(std::io::stdout(), &message)
+                    .expect("writing JSON to stdout failed");
+                std::io::stdout()
+                    .write_all(b"\n")
+                    .expect("writing to stdout failed");
+            }
+        }
+        future::ok(())
+    }
 }
 
 fn main() {
@@ -149,17 +165,11 @@ fn main() {
             std::process::exit(1);
         })
     };
-    let gerrit_event_stream = gerrit::extended_event_stream(
-        connect_to_gerrit(),
-        connect_to_gerrit(),
-        bot::request_extended_gerrit_info,
-    );
-    let gerrit_command_runner = gerrit::CommandRunner::new(connect_to_gerrit());
     let bot_builder = bot::Builder::new(bot::State::new());
     let bot_builder = {
-        if let Some(format_script) = args.format_script {
+        if let Some(format_script) = &args.format_script {
             bot_builder
-                .with_format_script(&format_script)
+                .with_format_script(format_script)
                 .unwrap_or_else(|err| {
                     error!("Failed to set format script: {:?}", err);
                     std::process::exit(1);
@@ -168,6 +178,12 @@ fn main() {
             bot_builder
         }
     };
+    let gerrit_event_stream = gerrit::extended_event_stream(
+        connect_to_gerrit(),
+        connect_to_gerrit(),
+        bot_builder.extended_gerrit_info_selector(),
+    );
+    let gerrit_command_runner = gerrit::CommandRunner::new(connect_to_gerrit());
     let (stdin_lines_sender, stdin_lines) = channel(1);
     std::thread::spawn(move || {
         stream::iter_ok::<_, ()>(
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::path::PathBuf;
 
@@ -18,6 +19,17 @@ pub struct GerritConfig {
     pub host: String,
     pub username: String,
     pub priv_key_path: PathBuf,
+    /// Access to the REST API, needed for inline comment threads.
+    pub rest: Option<GerritRestConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct GerritRestConfig {
+    /// base URL of the web interface
+    pub url: String,
+    pub username: String,
+    /// HTTP password generated in the user settings
+    pub password: String,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -39,6 +51,95 @@ pub struct BotConfig {
     pub msg_expiration: u64,
     pub msg_capacity: usize,
     pub format_script: Option<String>,
+    /// Accounts considered to be machines, e.g. CI. Defaults to all usernames
+    /// containing "bot".
+    pub service_accounts: Option<ServiceAccountsConfig>,
+    /// Recognition of CI results for the `notify_ci_results` flag.
+    pub ci: Option<CiConfig>,
+    /// Reminders about changes waiting for reviews.
+    pub reminders: Option<RemindersConfig>,
+    /// Rooms of teams which get review requests for their members.
+    pub teams: Option<TeamsConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TeamsConfig {
+    /// Webex Teams room id per Gerrit group
+    pub rooms: BTreeMap<String, String>,
+    /// seconds between fetching the group members again
+    #[serde(default = "default_group_refresh_interval")]
+    pub group_refresh_interval: u64,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct RemindersConfig {
+    /// Gerrit query selecting the changes to check
+    #[serde(default = "default_reminders_query")]
+    pub query: String,
+    /// working days without activity before reviewers are reminded
+    pub after_days: u32,
+    /// days between repeated reminders of the same change
+    #[serde(default = "default_reminders_repeat_days")]
+    pub repeat_days: u32,
+    /// remind the owner as well
+    #[serde(default)]
+    pub notify_owner: bool,
+    /// working days without activity before the change is escalated
+    pub escalate_after_days: Option<u32>,
+    /// Spark room to escalate to
+    pub escalation_room: Option<String>,
+    /// seconds between checks
+    #[serde(default = "default_reminders_check_interval")]
+    pub check_interval: u64,
+}
+
+fn default_reminders_query() -> String {
+    "status:open -is:wip".to_string()
+}
+
+fn default_reminders_repeat_days() -> u32 {
+    1
+}
+
+fn default_reminders_check_interval() -> u64 {
+    3600
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CiConfig {
+    /// labels voted on by CI accounts
+    #[serde(default = "default_ci_labels")]
+    pub labels: Vec<String>,
+    /// regexes matching build result URLs in CI comments
+    #[serde(default = "default_ci_url_regexes")]
+    pub url_regexes: Vec<String>,
+}
+
+fn default_ci_labels() -> Vec<String> {
+    vec!["Verified".to_string()]
+}
+
+fn default_ci_url_regexes() -> Vec<String> {
+    vec![r"https?://\S+".to_string()]
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ServiceAccountsConfig {
+    /// usernames or emails
+    #[serde(default)]
+    pub accounts: Vec<String>,
+    /// regexes matching usernames
+    #[serde(default)]
+    pub regexes: Vec<String>,
+    /// Gerrit group whose members are service accounts
+    pub group: Option<String>,
+    /// seconds between fetching the group members again
+    #[serde(default = "default_group_refresh_interval")]
+    pub group_refresh_interval: u64,
+}
+
+fn default_group_refresh_interval() -> u64 {
+    3600
 }
 
 /// Cisco Webex Teams <> Gerrit Bot
@@ -51,6 +152,13 @@ pub struct Args {
     /// Dump default format script and exit
     #[structopt(long)]
     pub dump_format_script: bool,
+    /// List the messages that could not be sent and exit
+    #[structopt(long)]
+    pub dead_letters: bool,
+    /// Send the messages that could not be sent again at the next start and
+    /// exit; only use while the bot is stopped
+    #[structopt(long)]
+    pub replay_dead_letters: bool,
 }
 
 pub fn parse_args() -> Args {
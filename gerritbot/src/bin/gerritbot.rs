@@ -2,7 +2,7 @@
 #![recursion_limit = "128"]
 #![deny(bare_trait_objects)]
 
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use futures::{future, future::lazy, Future, Stream};
 use log::{debug, error, info, warn};
@@ -54,6 +54,47 @@ fn main() {
         return;
     }
 
+    // load the messages which were not sent yet
+    let mut outbox = bot::Outbox::load("outbox.json").unwrap_or_else(|err| {
+        error!("Could not load outbox from 'outbox.json': {:?}", err);
+        std::process::exit(1);
+    });
+
+    if args.dead_letters {
+        for entry in outbox.dead_letters() {
+            println!(
+                "{}\t{}\t{} attempt(s)\t{}\n{}\n",
+                entry.id,
+                entry.recipient,
+                entry.attempts,
+                entry.last_error.as_deref().unwrap_or("-"),
+                entry.message
+            );
+        }
+        return;
+    }
+
+    if args.replay_dead_letters {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        let count = outbox.replay_dead_letters(now);
+        if let Err(err) = outbox.save() {
+            error!("Could not save outbox to 'outbox.json': {:?}", err);
+            std::process::exit(1);
+        }
+        println!("{} message(s) will be sent again at the next start.", count);
+        return;
+    }
+
+    if !outbox.is_empty() {
+        info!(
+            "Loaded {} unsent message(s) from 'outbox.json'.",
+            outbox.len()
+        );
+    }
+
     let args::Config {
         gerrit: gerrit_config,
         bot: bot_config,
@@ -74,7 +115,7 @@ fn main() {
             bot::State::new()
         });
 
-    let bot_builder = bot::Builder::new(bot_state);
+    let bot_builder = bot::Builder::new(bot_state).with_outbox(outbox);
     let bot_builder = {
         if bot_config.msg_expiration != 0 && bot_config.msg_capacity != 0 {
             debug!(
@@ -101,6 +142,44 @@ fn main() {
             bot_builder
         }
     };
+    let service_accounts = bot_config
+        .service_accounts
+        .as_ref()
+        .map(|config| {
+            bot::ServiceAccounts::from_config(config).unwrap_or_else(|err| {
+                error!("Failed to configure service accounts: {}", err);
+                std::process::exit(1);
+            })
+        })
+        .unwrap_or_default();
+    let bot_builder = bot_builder.with_service_accounts(service_accounts.clone());
+    let bot_builder = match bot_config.ci {
+        Some(ref ci_config) => bot_builder.with_ci_tracker(
+            bot::CiTracker::from_config(ci_config).unwrap_or_else(|err| {
+                error!("Failed to configure CI results: {}", err);
+                std::process::exit(1);
+            }),
+        ),
+        None => bot_builder,
+    };
+    let bot_builder = match bot_config.reminders {
+        Some(ref reminders_config) => bot_builder.with_reminders(reminders_config.clone()),
+        None => bot_builder,
+    };
+    let teams = bot_config
+        .teams
+        .as_ref()
+        .map(bot::Teams::from_config)
+        .unwrap_or_default();
+    let bot_builder = bot_builder.with_teams(teams.clone());
+    let bot_builder = match gerrit_config.rest {
+        Some(ref rest_config) => bot_builder.with_gerrit_rest_client(gerrit::RestClient::new(
+            rest_config.url.clone(),
+            rest_config.username.clone(),
+            rest_config.password.clone(),
+        )),
+        None => bot_builder,
+    };
     let connect_to_gerrit = || {
         info!(
             "Connecting to gerrit with username {} at {}",
@@ -119,12 +198,103 @@ fn main() {
     let gerrit_event_stream = gerrit::extended_event_stream(
         connect_to_gerrit(),
         connect_to_gerrit(),
-        bot::request_extended_gerrit_info,
+        bot_builder.extended_gerrit_info_selector(),
     );
     let gerrit_command_runner = gerrit::CommandRunner::new(connect_to_gerrit());
+    let service_group = bot_config
+        .service_accounts
+        .as_ref()
+        .and_then(|config| {
+            config
+                .group
+                .clone()
+                .map(|group| (group, config.group_refresh_interval))
+        })
+        .map(|(group, refresh_interval)| {
+            (
+                group,
+                refresh_interval,
+                gerrit::CommandRunner::new(connect_to_gerrit()),
+            )
+        });
+
+    let team_groups = bot_config.teams.as_ref().map(|config| {
+        (
+            config.group_refresh_interval,
+            gerrit::CommandRunner::new(connect_to_gerrit()),
+        )
+    });
 
     // run rest of the logic while the tokio runtime is running
     tokio::run(lazy(move || {
+        if let Some((group, refresh_interval, mut command_runner)) = service_group {
+            // fetch members of the service group at startup and then regularly
+            tokio::spawn(
+                tokio::timer::Interval::new(Instant::now(), Duration::from_secs(refresh_interval))
+                    .map_err(|e| error!("service group refresh timer failed: {}", e))
+                    .for_each(move |_| {
+                        let service_accounts = service_accounts.clone();
+                        let group_for_log = group.clone();
+                        gerrit::list_group_members(&mut command_runner, &group).then(
+                            move |result| {
+                                match result {
+                                    Ok(members) => {
+                                        info!(
+                                            "Fetched {} member(s) of service group {}",
+                                            members.len(),
+                                            group_for_log
+                                        );
+                                        service_accounts.set_group_members(members);
+                                    }
+                                    Err(e) => error!(
+                                        "failed to fetch members of service group {}: {}",
+                                        group_for_log, e
+                                    ),
+                                }
+                                Ok(())
+                            },
+                        )
+                    }),
+            );
+        }
+
+        if let Some((refresh_interval, mut command_runner)) = team_groups {
+            // fetch members of the groups of teams at startup and then regularly
+            tokio::spawn(
+                tokio::timer::Interval::new(Instant::now(), Duration::from_secs(refresh_interval))
+                    .map_err(|e| error!("team refresh timer failed: {}", e))
+                    .for_each(move |_| {
+                        let updates: Vec<_> = teams
+                            .groups()
+                            .map(|group| {
+                                let teams = teams.clone();
+                                let group = group.to_string();
+                                gerrit::list_group_members(&mut command_runner, &group).then(
+                                    move |result| {
+                                        match result {
+                                            Ok(members) => {
+                                                info!(
+                                                    "Fetched {} member(s) of team {}",
+                                                    members.len(),
+                                                    group
+                                                );
+                                                teams.set_group_members(&group, members);
+                                            }
+                                            Err(e) => error!(
+                                                "failed to fetch members of team {}: {}",
+                                                group, e
+                                            ),
+                                        }
+                                        Ok(())
+                                    },
+                                )
+                            })
+                            .collect();
+                        future::join_all(updates).map(|_| ())
+                    }),
+            );
+        }
+
         let webhook_url = spark_config.webhook_url.clone();
 
         spark::Client::new(spark_config.api_uri.clone(), spark_config.bot_token.clone())
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+use serde::Serialize;
+
+use gerritbot_gerrit as gerrit;
+
+use crate::args::CiConfig;
+
+/// How a CI vote relates to the previous vote of the same label on the change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CiOutcome {
+    /// Passed without a failure before.
+    Passed,
+    /// Passed after a previous failure.
+    Fixed,
+    /// Failed without a previous result.
+    Failed,
+    /// Failed after passing before.
+    NewFailure,
+    /// Failed again.
+    StillFailing,
+}
+
+/// A CI vote extracted from a comment of a service account.
+#[derive(Debug, Clone, Serialize)]
+pub struct CiResult {
+    pub label: String,
+    pub value: i32,
+    pub outcome: CiOutcome,
+    /// Build result URLs found in the comment.
+    pub urls: Vec<String>,
+}
+
+/// Recognizes CI results and remembers the last result per change and label
+/// to tell new failures from fixes.
+#[derive(Debug)]
+pub struct CiTracker {
+    labels: Vec<String>,
+    url_regexes: Vec<Regex>,
+    last_results: HashMap<(u32, String), bool>,
+}
+
+impl Default for CiTracker {
+    fn default() -> Self {
+        Self::new(
+            vec!["Verified".to_string()],
+            vec![Regex::new(r"https?://\S+").unwrap()],
+        )
+    }
+}
+
+impl CiTracker {
+    pub fn new(labels: Vec<String>, url_regexes: Vec<Regex>) -> Self {
+        Self {
+            labels,
+            url_regexes,
+            last_results: HashMap::new(),
+        }
+    }
+
+    pub fn from_config(config: &CiConfig) -> Result<Self, String> {
+        let url_regexes = config
+            .url_regexes
+            .iter()
+            .map(|regex| Regex::new(regex).map_err(|e| format!("invalid regex `{}`: {}", regex, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(config.labels.clone(), url_regexes))
+    }
+
+    /// Extract the CI result of a comment. The caller is responsible for
+    /// checking that the comment was written by a service account.
+    pub fn update(&mut self, event: &gerrit::CommentAddedEvent) -> Option<CiResult> {
+        let approval = event.approvals.iter().flatten().find(|approval| {
+            self.labels
+                .iter()
+                .any(|label| label.eq_ignore_ascii_case(&approval.approval_type))
+        })?;
+        let value: i32 = approval.value.parse().ok().filter(|value| *value != 0)?;
+        let passed = value > 0;
+        let previous = self.last_results.insert(
+            (event.change.number, approval.approval_type.clone()),
+            passed,
+        );
+
+        let outcome = match (previous, passed) {
+            (Some(false), true) => CiOutcome::Fixed,
+            (_, true) => CiOutcome::Passed,
+            (None, false) => CiOutcome::Failed,
+            (Some(true), false) => CiOutcome::NewFailure,
+            (Some(false), false) => CiOutcome::StillFailing,
+        };
+
+        Some(CiResult {
+            label: approval.approval_type.clone(),
+            value,
+            outcome,
+            urls: self.find_urls(&event.comment),
+        })
+    }
+
+    /// Forget the results of a change, e.g. because it was merged.
+    pub fn forget_change(&mut self, number: u32) {
+        self.last_results.retain(|(change, _), _| *change != number);
+    }
+
+    fn find_urls(&self, comment: &str) -> Vec<String> {
+        let mut urls: Vec<String> = Vec::new();
+        for regex in &self.url_regexes {
+            for m in regex.find_iter(comment) {
+                if !urls.iter().any(|url| url == m.as_str()) {
+                    urls.push(m.as_str().to_string());
+                }
+            }
+        }
+        urls
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::get_ci_event;
+
+    #[test]
+    fn outcomes() {
+        let mut tracker = CiTracker::default();
+        let outcome = |tracker: &mut CiTracker, value| {
+            tracker
+                .update(&get_ci_event(value, "Build finished"))
+                .map(|result| result.outcome)
+        };
+
+        assert_eq!(outcome(&mut tracker, "-1"), Some(CiOutcome::Failed));
+        assert_eq!(outcome(&mut tracker, "-1"), Some(CiOutcome::StillFailing));
+        assert_eq!(outcome(&mut tracker, "1"), Some(CiOutcome::Fixed));
+        assert_eq!(outcome(&mut tracker, "1"), Some(CiOutcome::Passed));
+        assert_eq!(outcome(&mut tracker, "-1"), Some(CiOutcome::NewFailure));
+        assert_eq!(outcome(&mut tracker, "0"), None);
+
+        tracker.forget_change(49);
+        assert_eq!(outcome(&mut tracker, "-1"), Some(CiOutcome::Failed));
+    }
+
+    #[test]
+    fn other_labels_are_ignored() {
+        let mut tracker = CiTracker::new(vec!["QA".to_string()], Vec::new());
+        assert!(tracker.update(&get_ci_event("1", "")).is_none());
+    }
+
+    #[test]
+    fn build_urls() {
+        let mut tracker = CiTracker::new(
+            vec!["verified".to_string()],
+            vec![Regex::new(r"https://ci\.example\.com/job/\S+").unwrap()],
+        );
+        let result = tracker
+            .update(&get_ci_event(
+                "-1",
+                "Build failed.\n\nhttps://ci.example.com/job/demo/42/ : FAILURE\nhttps://example.com/docs",
+            ))
+            .unwrap();
+        assert_eq!(result.label, "Verified");
+        assert_eq!(result.value, -1);
+        assert_eq!(result.urls, vec!["https://ci.example.com/job/demo/42/"]);
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use gerritbot_gerrit as gerrit;
+
+/// Lines shown before and after a commented line.
+const CONTEXT_LINES: u32 = 2;
+
+/// Maximum number of context lines in a single message. Comments beyond that
+/// are shown without context.
+const MAX_CONTEXT_LINES: usize = 30;
+
+/// Maximum number of characters of a context line, e.g. of minified code.
+const MAX_LINE_LENGTH: usize = 120;
+
+/// Files of the inline comments of a patchset for which context can be shown.
+/// Magic files like `/COMMIT_MSG` are skipped.
+pub fn commented_files(patchset: &gerrit::Patchset) -> Vec<&str> {
+    let mut files: Vec<&str> = Vec::new();
+    for comment in patchset.comments.iter().flatten() {
+        if comment.line > 0 && !comment.file.starts_with('/') && !files.contains(&&*comment.file) {
+            files.push(&comment.file);
+        }
+    }
+    files
+}
+
+/// Add the lines around the commented line to the inline comments of a
+/// patchset, given the contents of the commented files.
+pub fn add_code_context(patchset: &mut gerrit::Patchset, contents: &HashMap<String, String>) {
+    let mut budget = MAX_CONTEXT_LINES;
+
+    for comment in patchset.comments.iter_mut().flatten() {
+        let content = match contents.get(&comment.file) {
+            Some(content) if comment.line > 0 => content,
+            _ => continue,
+        };
+
+        let first = comment.line.saturating_sub(CONTEXT_LINES).max(1);
+        let context: Vec<gerrit::ContextLine> = content
+            .lines()
+            .zip(1..)
+            .skip(first as usize - 1)
+            .take_while(|(_, number)| *number <= comment.line + CONTEXT_LINES)
+            .map(|(text, line)| gerrit::ContextLine {
+                line,
+                text: truncate_line(text),
+                commented: line == comment.line,
+            })
+            .collect();
+
+        if context.is_empty() || context.len() > budget {
+            continue;
+        }
+        budget -= context.len();
+        comment.context = Some(context);
+    }
+}
+
+/// Cut a line after `MAX_LINE_LENGTH` characters.
+fn truncate_line(text: &str) -> String {
+    match text.char_indices().nth(MAX_LINE_LENGTH) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::get_event;
+
+    fn get_patchset(comments: &[(&str, u32)]) -> gerrit::Patchset {
+        let mut patchset = get_event().patchset;
+        patchset.comments = Some(
+            comments
+                .iter()
+                .map(|(file, line)| gerrit::InlineComment {
+                    file: file.to_string(),
+                    line: *line,
+                    reviewer: patchset.author.clone(),
+                    message: "off by one here".to_string(),
+                    context: None,
+                })
+                .collect(),
+        );
+        patchset
+    }
+
+    fn context_lines(comment: &gerrit::InlineComment) -> Vec<(u32, &str, bool)> {
+        comment
+            .context
+            .iter()
+            .flatten()
+            .map(|line| (line.line, &line.text[..], line.commented))
+            .collect()
+    }
+
+    #[test]
+    fn files_with_comments() {
+        let patchset = get_patchset(&[("src/main.rs", 3), ("/COMMIT_MSG", 1), ("src/main.rs", 7)]);
+        assert_eq!(commented_files(&patchset), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn lines_around_comments() {
+        let mut patchset = get_patchset(&[("src/main.rs", 1), ("src/main.rs", 4)]);
+        let mut contents = HashMap::new();
+        contents.insert(
+            "src/main.rs".to_string(),
+            "one\ntwo\nthree\nfour\nfive".to_string(),
+        );
+        add_code_context(&mut patchset, &contents);
+
+        let comments = patchset.comments.unwrap();
+        assert_eq!(
+            context_lines(&comments[0]),
+            vec![(1, "one", true), (2, "two", false), (3, "three", false)]
+        );
+        assert_eq!(
+            context_lines(&comments[1]),
+            vec![
+                (2, "two", false),
+                (3, "three", false),
+                (4, "four", true),
+                (5, "five", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn long_lines_are_truncated() {
+        let mut patchset = get_patchset(&[("dist/app.min.js", 1)]);
+        let mut contents = HashMap::new();
+        contents.insert("dist/app.min.js".to_string(), "ä".repeat(1000));
+        add_code_context(&mut patchset, &contents);
+
+        let comments = patchset.comments.unwrap();
+        let text = &comments[0].context.as_ref().unwrap()[0].text;
+        assert_eq!(*text, format!("{}…", "ä".repeat(MAX_LINE_LENGTH)));
+    }
+
+    #[test]
+    fn context_is_capped() {
+        let comments: Vec<_> = (1..=10).map(|i| ("src/main.rs", i * 10)).collect();
+        let mut patchset = get_patchset(&comments);
+        let mut contents = HashMap::new();
+        contents.insert(
+            "src/main.rs".to_string(),
+            (1..=200)
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        );
+        add_code_context(&mut patchset, &contents);
+
+        let with_context = patchset
+            .comments
+            .unwrap()
+            .iter()
+            .filter(|comment| comment.context.is_some())
+            .count();
+        assert_eq!(with_context, MAX_CONTEXT_LINES / 5);
+    }
+}
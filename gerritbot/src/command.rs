@@ -3,7 +3,7 @@ use std::str::FromStr;
 use lazy_static::lazy_static;
 use regex::Regex;
 
-use crate::state::UserFlag;
+use crate::state::{AccountPreference, FilterAction, MuteRule, UserFlag, Watch};
 
 #[derive(Debug)]
 pub enum Command {
@@ -16,6 +16,31 @@ pub enum Command {
     FilterStatus,
     FilterEnable(bool),
     FilterAdd(String),
+    FilterSet {
+        name: String,
+        action: FilterAction,
+        regex: String,
+    },
+    FilterRemove(String),
+    FilterSetEnabled {
+        name: String,
+        enabled: bool,
+    },
+    FilterList,
+    Watch(Watch),
+    WatchQuery(String),
+    Unwatch(Option<Watch>),
+    Mute(MuteRule),
+    MuteWhen(String),
+    Unmute(Option<MuteRule>),
+    SetAccountPreference(String, Option<AccountPreference>),
+    SetMaxInlineComments(Option<u32>),
+    OutOfOffice {
+        until: String,
+        note: Option<String>,
+        summarize: bool,
+    },
+    Back,
 }
 
 impl FromStr for Command {
@@ -24,7 +49,31 @@ impl FromStr for Command {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
             static ref FILTER_REGEX: Regex = Regex::new(r"(?i)^filter (.*)$").unwrap();
+            static ref NAMED_FILTER_REGEX: Regex =
+                Regex::new(r"(?i)^filter\s+(add|allow)\s+([\w-]+)\s+(.*)$").unwrap();
+            static ref FILTER_REMOVE_REGEX: Regex =
+                Regex::new(r"(?i)^filter\s+remove\s+([\w-]+)$").unwrap();
+            static ref FILTER_ENABLE_REGEX: Regex =
+                Regex::new(r"(?i)^filter\s+(enable|disable)\s+([\w-]+)$").unwrap();
             static ref FLAG_REGEX: Regex = Regex::new(r"(?i)^(enable|disable) (.*)$").unwrap();
+            static ref WATCH_QUERY_REGEX: Regex =
+                Regex::new(r"(?i)^watch\s+query\s+(.*)$").unwrap();
+            static ref MUTE_REGEX: Regex = Regex::new(r"(?i)^mute\s+when\s+(.*)$").unwrap();
+            static ref UNMUTE_REGEX: Regex = Regex::new(r"(?i)^unmute\s+when\s+(.*)$").unwrap();
+            static ref MUTE_CHANGE_REGEX: Regex =
+                Regex::new(r"(?i)^(mute|unmute|follow|unfollow)\s+(\d+)$").unwrap();
+            static ref MUTE_TOPIC_REGEX: Regex =
+                Regex::new(r"(?i)^(mute|unmute)\s+topic:(\S+)$").unwrap();
+            static ref IGNORE_REGEX: Regex =
+                Regex::new(r"(?i)^(ignore|unignore)\s+user\s+(\S+)$").unwrap();
+            static ref ALWAYS_NOTIFY_REGEX: Regex =
+                Regex::new(r"(?i)^(always|normally)\s+notify\s+from\s+(\S+)$").unwrap();
+            static ref MAX_INLINE_COMMENTS_REGEX: Regex =
+                Regex::new(r"(?i)^max\s+inline\s+comments\s+(\d+|off)$").unwrap();
+            static ref OUT_OF_OFFICE_REGEX: Regex =
+                Regex::new(r#"(?i)^ooo\s+until\s+(\S+)(\s+with\s+summary)?(?:\s+"?([^"]*?)"?)?$"#)
+                    .unwrap();
+            static ref WATCH_REGEX: Regex = Regex::new(r"(?i)^(watch|unwatch)\s+(.*)$").unwrap();
         };
 
         Ok(match &s.trim().to_lowercase()[..] {
@@ -33,16 +82,146 @@ impl FromStr for Command {
             "status" => Command::Status,
             "help" => Command::Help,
             "version" => Command::Version,
+            "back" => Command::Back,
             "filter" => Command::FilterStatus,
             "filter enable" => Command::FilterEnable(true),
             "filter disable" => Command::FilterEnable(false),
+            "filter list" => Command::FilterList,
+            "unwatch" => Command::Unwatch(None),
+            "unmute" => Command::Unmute(None),
             _ => None
+                .or_else(|| {
+                    NAMED_FILTER_REGEX.captures(&s.trim()[..]).map(|cap| {
+                        let action = if cap[1].eq_ignore_ascii_case("allow") {
+                            FilterAction::Allow
+                        } else {
+                            FilterAction::Deny
+                        };
+                        Command::FilterSet {
+                            name: cap[2].to_string(),
+                            action,
+                            regex: cap[3].to_string(),
+                        }
+                    })
+                })
+                .or_else(|| {
+                    FILTER_REMOVE_REGEX
+                        .captures(&s.trim()[..])
+                        .map(|cap| Command::FilterRemove(cap[1].to_string()))
+                })
+                .or_else(|| {
+                    FILTER_ENABLE_REGEX.captures(&s.trim()[..]).map(|cap| {
+                        Command::FilterSetEnabled {
+                            name: cap[2].to_string(),
+                            enabled: cap[1].eq_ignore_ascii_case("enable"),
+                        }
+                    })
+                })
                 .or_else(|| {
                     FILTER_REGEX
                         .captures(&s.trim()[..])
                         .and_then(|cap| cap.get(1))
+                        // incomplete commands for named filters are not taken
+                        // as regex for the default filter
+                        .filter(|m| !starts_with_filter_keyword(m.as_str()))
                         .map(|m| Command::FilterAdd(m.as_str().to_string()))
                 })
+                .or_else(|| {
+                    MUTE_CHANGE_REGEX.captures(&s.trim()[..]).and_then(|cap| {
+                        let number = cap[2].parse().ok()?;
+                        Some(match &cap[1].to_lowercase()[..] {
+                            "mute" => Command::Mute(MuteRule::change(number)),
+                            "unmute" => Command::Unmute(Some(MuteRule::change(number))),
+                            "follow" => Command::Watch(Watch::Change(number)),
+                            _ => Command::Unwatch(Some(Watch::Change(number))),
+                        })
+                    })
+                })
+                .or_else(|| {
+                    MUTE_TOPIC_REGEX.captures(&s.trim()[..]).map(|cap| {
+                        let rule = MuteRule::topic(&cap[2]);
+                        if cap[1].eq_ignore_ascii_case("mute") {
+                            Command::Mute(rule)
+                        } else {
+                            Command::Unmute(Some(rule))
+                        }
+                    })
+                })
+                .or_else(|| {
+                    MUTE_REGEX
+                        .captures(&s.trim()[..])
+                        .map(|cap| Command::MuteWhen(cap[1].to_string()))
+                })
+                .or_else(|| {
+                    UNMUTE_REGEX
+                        .captures(&s.trim()[..])
+                        .and_then(|cap| cap[1].parse().ok())
+                        .map(|rule| Command::Unmute(Some(rule)))
+                })
+                .or_else(|| {
+                    IGNORE_REGEX.captures(&s.trim()[..]).map(|cap| {
+                        let preference = if cap[1].eq_ignore_ascii_case("ignore") {
+                            Some(AccountPreference::Ignore)
+                        } else {
+                            None
+                        };
+                        Command::SetAccountPreference(cap[2].to_string(), preference)
+                    })
+                })
+                .or_else(|| {
+                    ALWAYS_NOTIFY_REGEX.captures(&s.trim()[..]).map(|cap| {
+                        let preference = if cap[1].eq_ignore_ascii_case("always") {
+                            Some(AccountPreference::AlwaysNotify)
+                        } else {
+                            None
+                        };
+                        Command::SetAccountPreference(cap[2].to_string(), preference)
+                    })
+                })
+                .or_else(|| {
+                    MAX_INLINE_COMMENTS_REGEX
+                        .captures(&s.trim()[..])
+                        .and_then(|cap| {
+                            if cap[1].eq_ignore_ascii_case("off") {
+                                Some(None)
+                            } else {
+                                cap[1].parse().ok().map(Some)
+                            }
+                        })
+                        .map(Command::SetMaxInlineComments)
+                })
+                .or_else(|| {
+                    OUT_OF_OFFICE_REGEX
+                        .captures(&s.trim()[..])
+                        .map(|cap| Command::OutOfOffice {
+                            until: cap[1].to_string(),
+                            note: cap
+                                .get(3)
+                                .map(|m| m.as_str().trim().to_string())
+                                .filter(|note| !note.is_empty()),
+                            summarize: cap.get(2).is_some(),
+                        })
+                })
+                .or_else(|| {
+                    WATCH_QUERY_REGEX
+                        .captures(&s.trim()[..])
+                        .and_then(|cap| cap.get(1))
+                        .map(|m| Command::WatchQuery(m.as_str().to_string()))
+                })
+                .or_else(|| {
+                    WATCH_REGEX
+                        .captures(&s.trim()[..])
+                        .and_then(|cap| cap.get(1).and_then(|m1| cap.get(2).map(|m2| (m1, m2))))
+                        .and_then(|(m1, m2)| {
+                            m2.as_str().parse().ok().map(|watch| {
+                                if m1.as_str().eq_ignore_ascii_case("watch") {
+                                    Command::Watch(watch)
+                                } else {
+                                    Command::Unwatch(Some(watch))
+                                }
+                            })
+                        })
+                })
                 .or_else(|| {
                     FLAG_REGEX
                         .captures(&s.trim()[..])
@@ -59,11 +238,24 @@ impl FromStr for Command {
     }
 }
 
+/// Check if the argument of `filter <regex>` starts with one of the keywords of
+/// the other filter commands.
+fn starts_with_filter_keyword(regex: &str) -> bool {
+    const KEYWORDS: &[&str] = &["add", "allow", "remove", "enable", "disable", "list"];
+    match regex.split_whitespace().next() {
+        Some(word) => KEYWORDS
+            .iter()
+            .any(|keyword| word.eq_ignore_ascii_case(keyword)),
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use assert_matches::assert_matches;
 
     use super::Command;
+    use crate::state::{AccountPreference, FilterAction, Watch};
 
     macro_rules! test_parse {
         ($name:ident, $s:expr, $( $c:tt )+) => {
@@ -112,5 +304,169 @@ mod test {
         Command::FilterAdd(ref s) if s == " abc def"
     );
 
+    test_parse!(filter_list, "filter list", Command::FilterList);
+    test_parse!(
+        filter_add_named,
+        "filter add ci-noise ^CI (Bot|Robot)$",
+        Command::FilterSet { ref name, action: FilterAction::Deny, ref regex }
+            if name == "ci-noise" && regex == "^CI (Bot|Robot)$"
+    );
+    test_parse!(
+        filter_allow_named,
+        "Filter Allow failures FAILURE",
+        Command::FilterSet { ref name, action: FilterAction::Allow, ref regex }
+            if name == "failures" && regex == "FAILURE"
+    );
+    test_parse!(
+        filter_remove,
+        "filter remove ci-noise",
+        Command::FilterRemove(ref name) if name == "ci-noise"
+    );
+    test_parse!(
+        filter_enable_named,
+        "filter enable ci-noise",
+        Command::FilterSetEnabled { ref name, enabled: true } if name == "ci-noise"
+    );
+    test_parse!(
+        filter_disable_named,
+        "Filter Disable ci-noise",
+        Command::FilterSetEnabled { ref name, enabled: false } if name == "ci-noise"
+    );
+    test_parse!(
+        filter_starting_with_keyword,
+        "filter address",
+        Command::FilterAdd(ref s) if s == "address"
+    );
+
+    test_parse!(
+        watch_project,
+        "watch project:tools",
+        Command::Watch(Watch::Project { ref project, branch: None }) if project == "tools"
+    );
+    test_parse!(
+        watch_project_and_branch,
+        "Watch project:Tools branch:stable",
+        Command::Watch(Watch::Project { ref project, branch: Some(ref branch) })
+            if project == "Tools" && branch == "stable"
+    );
+    test_parse!(
+        watch_hashtag,
+        "watch hashtag:refactoring",
+        Command::Watch(Watch::Hashtag(ref hashtag)) if hashtag == "refactoring"
+    );
+    test_parse!(
+        watch_query,
+        "watch query project:tools (owner:admin OR label:Code-Review=-2",
+        Command::WatchQuery(ref query)
+            if query == "project:tools (owner:admin OR label:Code-Review=-2"
+    );
+    test_parse!(unwatch, Command::Unwatch(None));
+    test_parse!(
+        unwatch_hashtag,
+        "unwatch hashtag:refactoring",
+        Command::Unwatch(Some(Watch::Hashtag(ref hashtag))) if hashtag == "refactoring"
+    );
+    test_parse!(
+        unwatch_query,
+        "unwatch query project:tools",
+        Command::Unwatch(Some(Watch::Query(ref query))) if query.as_str() == "project:tools"
+    );
+
+    test_parse!(
+        mute_when,
+        "Mute when actor=ci-bot and label=Verified value>0",
+        Command::MuteWhen(ref rule) if rule == "actor=ci-bot and label=Verified value>0"
+    );
+    test_parse!(
+        mute_change,
+        "mute 12345",
+        Command::Mute(ref rule) if rule.to_string() == "change=12345"
+    );
+    test_parse!(
+        mute_topic,
+        "mute topic:refactoring",
+        Command::Mute(ref rule) if rule.to_string() == "topic=refactoring"
+    );
+    test_parse!(
+        unmute_change,
+        "Unmute 12345",
+        Command::Unmute(Some(ref rule)) if rule.to_string() == "change=12345"
+    );
+    test_parse!(
+        unmute_topic,
+        "unmute topic:refactoring",
+        Command::Unmute(Some(ref rule)) if rule.to_string() == "topic=refactoring"
+    );
+    test_parse!(follow, "follow 12345", Command::Watch(Watch::Change(12345)));
+    test_parse!(
+        unfollow,
+        "unfollow 12345",
+        Command::Unwatch(Some(Watch::Change(12345)))
+    );
+    test_parse!(unmute, Command::Unmute(None));
+    test_parse!(
+        unmute_when,
+        "unmute when actor=ci-bot",
+        Command::Unmute(Some(ref rule)) if rule.to_string() == "actor=ci-bot"
+    );
+
+    test_parse!(
+        ignore_user,
+        "ignore user jdoe",
+        Command::SetAccountPreference(ref account, Some(AccountPreference::Ignore))
+            if account == "jdoe"
+    );
+    test_parse!(
+        unignore_user,
+        "Unignore User jdoe",
+        Command::SetAccountPreference(ref account, None) if account == "jdoe"
+    );
+    test_parse!(
+        always_notify,
+        "always notify from lead@example.com",
+        Command::SetAccountPreference(ref account, Some(AccountPreference::AlwaysNotify))
+            if account == "lead@example.com"
+    );
+    test_parse!(
+        normally_notify,
+        "normally notify from lead@example.com",
+        Command::SetAccountPreference(ref account, None) if account == "lead@example.com"
+    );
+
+    test_parse!(
+        out_of_office,
+        r#"ooo until 2026-11-02 "backup: alice@example.com""#,
+        Command::OutOfOffice { ref until, note: Some(ref note), summarize: false }
+            if until == "2026-11-02" && note == "backup: alice@example.com"
+    );
+    test_parse!(
+        out_of_office_with_summary,
+        "OOO until 2026-11-02 with summary",
+        Command::OutOfOffice { ref until, note: None, summarize: true } if until == "2026-11-02"
+    );
+    test_parse!(back, "back", Command::Back);
+    test_parse!(
+        max_inline_comments,
+        "max inline comments 5",
+        Command::SetMaxInlineComments(Some(5))
+    );
+    test_parse!(
+        max_inline_comments_off,
+        "Max Inline Comments OFF",
+        Command::SetMaxInlineComments(None)
+    );
+
     test_parse_fail!(unknown_command, "unknown");
+    test_parse_fail!(filter_add_without_regex, "filter add ci-noise");
+    test_parse_fail!(filter_allow_without_name, "filter allow");
+    test_parse_fail!(filter_remove_without_name, "filter remove");
+    test_parse_fail!(filter_enable_invalid_name, "filter enable ci noise");
+    test_parse_fail!(ignore_nobody, "ignore user");
+    test_parse_fail!(unmute_invalid_rule, "unmute when author");
+    test_parse_fail!(follow_nothing, "follow");
+    test_parse_fail!(mute_invalid_change, "mute 12345abc");
+    test_parse_fail!(watch_nothing, "watch");
+    test_parse_fail!(watch_unknown, "watch owner:admin");
+    test_parse_fail!(max_inline_comments_nothing, "max inline comments");
+    test_parse_fail!(max_inline_comments_negative, "max inline comments -1");
 }
@@ -0,0 +1,69 @@
+//! Gerrit events shared by the unit tests.
+
+use gerritbot_gerrit as gerrit;
+
+/// Approval of change 49 of `author@example.com` by `approver@approvers.com`.
+pub const EVENT_JSON: &str = r#"
+{"author":{"name":"Approver","username":"approver","email":"approver@approvers.com"},"approvals":[{"type":"Code-Review","description":"Code-Review","value":"2","oldValue":"-1"}],"comment":"Patch Set 1: Code-Review+2\n\nJust a buggy script. FAILURE\n\nAnd more problems. FAILURE","patchSet":{"number":1,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":["fb1909b4eda306985d2bbce769310e5a50a98cf5"],"ref":"refs/changes/42/42/1","uploader":{"name":"Author","email":"author@example.com","username":"Author"},"createdOn":1494165142,"author":{"name":"Author","email":"author@example.com","username":"Author"},"isDraft":false,"kind":"REWORK","sizeInsertions":0,"sizeDeletions":0},"change":{"project":"demo-project","branch":"master","id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14","number":49,"subject":"Some review.","owner":{"name":"Author","email":"author@example.com","username":"author"},"url":"http://localhost/42","commitMessage":"Some review.\n\nChange-Id: Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14\n","status":"NEW"},"project":"demo-project","refName":"refs/heads/master","changeKey":{"id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14"},"type":"comment-added","eventCreatedOn":1499190282}"#;
+
+pub fn get_event() -> gerrit::CommentAddedEvent {
+    let event: Result<gerrit::Event, _> = serde_json::from_str(EVENT_JSON);
+    match event.expect("failed to decode event") {
+        gerrit::Event::CommentAdded(event) => event,
+        event => panic!("wrong type of event: {:?}", event),
+    }
+}
+
+/// Upload of the patch set of `get_event` by the owner of the change, touching
+/// two source files.
+pub fn get_patchset_created_event() -> gerrit::PatchsetCreatedEvent {
+    let event = get_event();
+    let mut patchset = event.patchset;
+    patchset.files = Some(
+        serde_json::from_str(
+            r#"[{"file":"/COMMIT_MSG","type":"ADDED","insertions":5,"deletions":0},{"file":"src/net/socket.rs","type":"MODIFIED","insertions":1,"deletions":0},{"file":"src/main.rs","type":"MODIFIED","insertions":1,"deletions":0}]"#,
+        )
+        .unwrap(),
+    );
+    gerrit::PatchsetCreatedEvent {
+        uploader: event.change.owner.clone(),
+        change: event.change,
+        patchset,
+        created_on: event.created_on,
+    }
+}
+
+/// The change of `get_event` with a reviewer besides its owner, last updated at
+/// the given time.
+pub fn get_stale_change(last_updated: u64) -> gerrit::Change {
+    let mut change = get_event().change;
+    change.last_updated = Some(last_updated);
+    change.all_reviewers = Some(vec![
+        gerrit::User {
+            name: Some("Reviewer".to_string()),
+            username: Some("reviewer".to_string()),
+            email: Some("reviewer@example.com".to_string()),
+        },
+        change.owner.clone(),
+    ]);
+    change
+}
+
+/// Vote of the CI account on the `Verified` label of the change of `get_event`.
+pub fn get_ci_event(value: &str, comment: &str) -> gerrit::CommentAddedEvent {
+    let mut event = get_event();
+    event.author = gerrit::User {
+        name: Some("CI".to_string()),
+        username: Some("ci-bot".to_string()),
+        email: Some("ci-bot@example.com".to_string()),
+    };
+    event.approvals = Some(vec![gerrit::Approval {
+        approval_type: "Verified".to_string(),
+        description: None,
+        value: value.to_string(),
+        old_value: None,
+        by: None,
+    }]);
+    event.comment = comment.to_string();
+    event
+}
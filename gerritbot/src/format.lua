@@ -132,7 +132,18 @@ local function format_comment(comment, is_human)
     end
 end
 
-local function format_inline_comment(base_url, change, patchset, comment)
+-- Format the lines of a file around an inline comment as code block.
+local function format_code_context(context)
+    local lines = {}
+
+    for _i, line in ipairs(context) do
+        table.insert(lines, string.format("%s%4d | %s", line.commented and ">" or " ", line.line, line.text))
+    end
+
+    return "```\n" .. table.concat(lines, "\n") .. "\n```"
+end
+
+local function format_inline_comment(base_url, change, patchset, comment, with_context)
     local lines = {}
 
     for line in lines_iter(comment.message) do
@@ -162,24 +173,50 @@ local function format_inline_comment(base_url, change, patchset, comment)
         end
     end
 
+    if with_context and comment.context and #comment.context > 0 then
+        table.insert(lines, "\n" .. format_code_context(comment.context))
+    end
+
     return table.concat(lines, "\n")
 end
 
-local function format_inline_comments(base_url, change, patchset)
-    local lines = {}
+-- Format the inline comments of a patchset within the given limits: at most
+-- `max_inline_comments` comments, and without code context if they would not
+-- fit into a single message of `max_length` bytes otherwise.
+local function format_inline_comments(base_url, change, patchset, limits)
     local comments = patchset.comments or {}
+    local max_comments = limits.max_inline_comments or #comments
 
     table.sort(comments, function (c1, c2) return c1.file < c2.file end)
 
-    local file
+    local function format_lines(with_context)
+        local lines = {}
+        local file
 
-    for _i, comment in ipairs(comments) do
-        if comment.file ~= file then
-            file = comment.file
-            table.insert(lines, string.format("`%s`", file))
+        for i, comment in ipairs(comments) do
+            if i > max_comments then
+                break
+            end
+
+            if comment.file ~= file then
+                file = comment.file
+                table.insert(lines, string.format("`%s`", file))
+            end
+
+            table.insert(lines, format_inline_comment(base_url, change, patchset, comment, with_context))
         end
 
-        table.insert(lines, format_inline_comment(base_url, change, patchset, comment))
+        return lines
+    end
+
+    local lines = format_lines(true)
+
+    if limits.max_length and #table.concat(lines, "\n\n") > limits.max_length then
+        lines = format_lines(false)
+    end
+
+    if #comments > max_comments then
+        table.insert(lines, "…and " .. format_link(string.format("%d more", #comments - max_comments), change.url))
     end
 
     if #lines > 0 then
@@ -224,7 +261,7 @@ end
 
 -- Filter and format messages
 -- return nil to filter the message
-function format_comment_added(event, flags)
+function format_comment_added(event, flags, limits)
     local is_human = is_human(event.author)
     local change = event.change
 
@@ -236,7 +273,7 @@ function format_comment_added(event, flags)
     local base_url = get_gerrit_base_url(change.url)
     local formatted_approvals = flags["notify_review_approvals"] and format_approvals(event.approvals or {})
     local formatted_status_message = flags["notify_review_approvals"] and format_change_status(change)
-    local formatted_inline_comments = flags["notify_review_inline_comments"] and format_inline_comments(base_url, change, patchset)
+    local formatted_inline_comments = flags["notify_review_inline_comments"] and format_inline_comments(base_url, change, patchset, limits or {})
     local formatted_comment = (
         flags["notify_review_comments"]
         or formatted_approvals
@@ -271,6 +308,62 @@ function format_reviewer_added(event, flags)
     )
 end
 
+-- Format votes removed from a change for its owner.
+function format_vote_deleted(event, flags)
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+    local removed_votes = {}
+
+    -- the removed vote is the old value
+    for _i, approval in ipairs(event.approvals or {}) do
+        table.insert(removed_votes, {type = approval.type, value = approval.oldValue})
+    end
+
+    local formatted_votes = format_approvals(removed_votes)
+
+    if not formatted_votes then
+        return
+    end
+
+    return string.format(
+        "%s (%s) 🗑️ Vote%s from %s removed by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        formatted_votes,
+        format_user(base_url, event.reviewer, "reviewer"),
+        format_user(base_url, event.remover, "owner")
+    )
+end
+
+-- Format the removal of the user as reviewer of a change.
+function format_reviewer_deleted(event, flags)
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    return string.format(
+        "%s (%s) by %s 🚪 Removed as reviewer by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner"),
+        format_user(base_url, event.remover or change.owner, "owner")
+    )
+end
+
+-- Format a review requested from members of a team for the room of the team.
+function format_team_reviewer_added(team_review, flags)
+    local event = team_review.event
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    return string.format(
+        "%s (%s) by %s 👓 Review requested from team %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner"),
+        team_review.team
+    )
+end
+
 function format_change_merged(event, flags)
     local change = event.change
     local base_url = get_gerrit_base_url(change.url)
@@ -295,6 +388,411 @@ function format_change_abandoned(event, flags)
     )
 end
 
+-- Format a comment mentioning the user.
+function format_mention(mention, flags)
+    local event = mention.event
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    local msg = string.format(
+        "%s (%s) by %s 📣 Mentioned by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner"),
+        format_user(base_url, event.author, "reviewer")
+    )
+
+    for _i, comment in ipairs(mention.comments or {}) do
+        msg = msg .. "\n"
+        for line in lines_iter(comment) do
+            msg = msg .. "\n> " .. line
+        end
+    end
+
+    return msg
+end
+
+local RESOLUTIONS = {
+    resolved = "✅ Resolved",
+    reopened = "↩️ Reopened",
+}
+
+-- Format replies to inline comment threads the user took part in.
+function format_thread_replies(thread_replies, flags)
+    local event = thread_replies.event
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    local msg = string.format(
+        "%s (%s) by %s 🧵 %s replied to your comments",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner"),
+        format_user(base_url, event.author, "reviewer")
+    )
+
+    for _i, reply in ipairs(thread_replies.replies or {}) do
+        local comment = reply.comment
+        local location = comment.path
+        if comment.line then
+            location = string.format("%s:%d", location, comment.line)
+        end
+        msg = msg .. string.format("\n\n`%s`", location)
+
+        for line in lines_iter(reply.parent.message) do
+            msg = msg .. "\n> " .. line
+        end
+        msg = msg .. "\n\n" .. comment.message
+
+        if reply.resolution then
+            msg = msg .. "\n\n" .. RESOLUTIONS[reply.resolution]
+        end
+    end
+
+    return msg
+end
+
+local PATCHSET_KINDS = {
+    REWORK = "rework",
+    TRIVIAL_REBASE = "trivial rebase",
+    MERGE_FIRST_PARENT_UPDATE = "merge parent update",
+    NO_CODE_CHANGE = "commit message change",
+    NO_CHANGE = "no change",
+}
+
+-- Format a patchset uploaded to a change by somebody else than its owner.
+function format_patchset_created(event, flags)
+    local change = event.change
+    local patchset = event.patchSet
+    local base_url = get_gerrit_base_url(change.url)
+
+    return string.format(
+        "%s (%s) 📤 Patchset %d (%s) uploaded by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        patchset.number,
+        PATCHSET_KINDS[patchset.kind] or patchset.kind:lower(),
+        format_user(base_url, patchset.uploader, "owner")
+    )
+end
+
+local CI_OUTCOMES = {
+    passed = "🌞 Passed",
+    fixed = "🎉 Fixed",
+    failed = "⛈️ Failed",
+    new_failure = "💥 New failure",
+    still_failing = "⛈️ Still failing",
+}
+
+-- Format a compact message for a CI vote.
+function format_ci_result(ci, flags)
+    local event = ci.event
+    local result = ci.result
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    local msg = string.format(
+        "%s (%s) %s: %s%d (%s) from %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        CI_OUTCOMES[result.outcome] or result.outcome,
+        result.value > 0 and "+" or "",
+        result.value,
+        result.label,
+        format_user(base_url, event.author, "reviewer")
+    )
+
+    for _i, url in ipairs(result.urls or {}) do
+        msg = msg .. "\n\n> " .. url
+    end
+
+    return msg
+end
+
+-- Format a revert of a change for its owner and reviewers. The revert is
+-- either proposed or already merged.
+function format_change_reverted(reverted, flags)
+    local change = reverted.change
+    local revert = reverted.revert
+    local base_url = get_gerrit_base_url(change.url)
+
+    local msg = string.format(
+        "%s (%s) ⏪ %s by %s in %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        reverted.merged and "Reverted" or "Revert proposed",
+        format_user(base_url, revert.owner, "owner"),
+        format_change_subject(revert)
+    )
+
+    if reverted.reason then
+        for line in lines_iter(reverted.reason) do
+            msg = msg .. "\n> " .. line
+        end
+    end
+
+    return msg
+end
+
+-- Format a change that is neither work in progress nor private anymore for
+-- its reviewers.
+function format_ready_for_review(ready, flags)
+    local change = ready.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    return string.format(
+        "%s (%s) by %s 🚦 Ready for review",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner")
+    )
+end
+
+-- Format a new, changed or removed topic of a change.
+function format_topic_changed(event, flags)
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+    local old_topic = event.oldTopic
+    local new_topic = change.topic
+    local topic_change
+
+    if old_topic and new_topic then
+        topic_change = string.format("Topic changed from `%s` to `%s`", old_topic, new_topic)
+    elseif new_topic then
+        topic_change = string.format("Topic set to `%s`", new_topic)
+    elseif old_topic then
+        topic_change = string.format("Topic `%s` removed", old_topic)
+    else
+        return
+    end
+
+    return string.format(
+        "%s (%s) 🔖 %s by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        topic_change,
+        format_user(base_url, event.changer, "owner")
+    )
+end
+
+-- Format hashtags added to or removed from a change.
+function format_hashtags_changed(event, flags)
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+    local function format_hashtags(hashtags)
+        local formatted = {}
+        for _i, hashtag in ipairs(hashtags or {}) do
+            table.insert(formatted, string.format("`%s`", hashtag))
+        end
+        return table.concat(formatted, ", ")
+    end
+
+    local changes = {}
+    if #(event.added or {}) > 0 then
+        table.insert(changes, "added " .. format_hashtags(event.added))
+    end
+    if #(event.removed or {}) > 0 then
+        table.insert(changes, "removed " .. format_hashtags(event.removed))
+    end
+    if #changes == 0 then
+        return
+    end
+
+    return string.format(
+        "%s (%s) #️⃣ Hashtags %s by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        table.concat(changes, ", "),
+        format_user(base_url, event.editor, "owner")
+    )
+end
+
+-- Format a change that cannot be merged anymore after other changes were
+-- merged into its branch.
+function format_merge_conflict(conflict, flags)
+    local change = conflict.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    local merged = {}
+    for _i, other in ipairs(conflict.merged) do
+        table.insert(merged, format_change_subject(other))
+    end
+
+    local msg = string.format(
+        "%s (%s) 💥 Merge conflict",
+        format_change_subject(change),
+        format_change_project(base_url, change)
+    )
+    if #merged > 0 then
+        msg = msg .. " after merge of " .. table.concat(merged, ", ")
+    end
+
+    return msg
+end
+
+-- Format a reminder about a change without activity. The role is one of
+-- "reviewer", "owner" or "escalation".
+function format_reminder(reminder, flags)
+    local change = reminder.change
+    local base_url = get_gerrit_base_url(change.url)
+    local msg = string.format(
+        "%s (%s) by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner")
+    )
+
+    if reminder.role == "reviewer" then
+        return string.format("%s ⏰ Waiting for your review for %d working days", msg, reminder.idle_days)
+    elseif reminder.role == "team" then
+        return string.format("%s ⏰ Waiting for review from team %s for %d working days", msg, reminder.team, reminder.idle_days)
+    end
+
+    local reviewers = {}
+
+    for _i, reviewer in ipairs(reminder.reviewers or {}) do
+        table.insert(reviewers, format_user(base_url, reviewer, "reviewer"))
+    end
+
+    local icon = reminder.role == "escalation" and "🚨" or "⏰"
+    msg = string.format("%s %s No activity for %d working days", msg, icon, reminder.idle_days)
+
+    if #reviewers > 0 then
+        msg = msg .. ", waiting for " .. table.concat(reviewers, ", ")
+    end
+
+    return msg
+end
+
+-- Tell the owner of a change that the added reviewer is out of office.
+function format_reviewer_away(away, flags)
+    local event = away.event
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+    local msg = string.format(
+        "%s (%s) 🏖️ %s is out of office until %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, event.reviewer, "reviewer"),
+        away["until"]
+    )
+
+    if away.note then
+        msg = msg .. ": " .. away.note
+    end
+
+    return msg
+end
+
+-- Format the notifications a user missed while being out of office.
+function format_away_summary(summary, flags)
+    local missed = summary.missed or {}
+
+    if #missed == 0 then
+        return "Welcome back! You did not miss any notifications."
+    end
+
+    return string.format(
+        "Welcome back! Here is what you missed while you were out of office:\n\n%s",
+        table.concat(missed, "\n\n---\n\n")
+    )
+end
+
+-- Format a reviewer added event for users watching the change.
+local function format_watched_reviewer_added(event, flags)
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    return string.format(
+        "%s (%s) by %s 👓 %s added as reviewer",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        format_user(base_url, change.owner, "owner"),
+        format_user(base_url, event.reviewer, "reviewer")
+    )
+end
+
+-- Format a patchset created event for users watching the change.
+local function format_watched_patchset_created(event, flags)
+    local change = event.change
+    local base_url = get_gerrit_base_url(change.url)
+
+    return string.format(
+        "%s (%s) 📤 Patchset %d uploaded by %s",
+        format_change_subject(change),
+        format_change_project(base_url, change),
+        event.patchSet.number,
+        format_user(base_url, event.uploader, "owner")
+    )
+end
+
+local WATCHED_EVENT_FORMATTERS = {
+    ["comment-added"] = function(event, flags, limits) return format_comment_added(event, flags, limits) end,
+    ["reviewer-added"] = format_watched_reviewer_added,
+    ["change-merged"] = function(event, flags) return format_change_merged(event, flags) end,
+    ["change-abandoned"] = function(event, flags) return format_change_abandoned(event, flags) end,
+    ["patchset-created"] = format_watched_patchset_created,
+}
+
+-- Format an event for a user that is not involved in the change but watches it.
+function format_watched_event(watched, flags, limits)
+    local formatter = WATCHED_EVENT_FORMATTERS[watched.type]
+
+    if not formatter then
+        return
+    end
+
+    local msg = formatter(watched.event, flags, limits)
+
+    if not msg then
+        return
+    end
+
+    local file_lines = {}
+
+    for _i, file in ipairs(watched.files or {}) do
+        table.insert(file_lines, string.format("> `%s`", file))
+    end
+
+    if #file_lines > 0 then
+        msg = string.format("%s\n\nMatching files:\n%s", msg, table.concat(file_lines, "\n"))
+    end
+
+    return string.format("%s\n\n👀 Watching `%s`", msg, watched.watch)
+end
+
+-- Format the creation, update or deletion of a ref for users watching it.
+function format_ref_updated(watched, flags)
+    local update = watched.event.refUpdate
+    local submitter = watched.event.submitter
+    local ref_name = update.refName
+    local kind = "Ref"
+    local name = ref_name
+
+    if ref_name:find("^refs/tags/") then
+        kind, name = "🏷️ Tag", ref_name:sub(#"refs/tags/" + 1)
+    elseif ref_name:find("^refs/heads/") then
+        kind, name = "🌿 Branch", ref_name:sub(#"refs/heads/" + 1)
+    end
+
+    local null_rev = string.rep("0", 40)
+    local change
+    if update.oldRev == null_rev then
+        change = string.format("created at `%s`", update.newRev:sub(1, 10))
+    elseif update.newRev == null_rev then
+        change = string.format("deleted, was at `%s`", update.oldRev:sub(1, 10))
+    else
+        change = string.format("updated from `%s` to `%s`", update.oldRev:sub(1, 10), update.newRev:sub(1, 10))
+    end
+
+    local msg = string.format("%s `%s` in `%s` %s", kind, name, update.project, change)
+    if submitter then
+        msg = string.format("%s by %s", msg, submitter.name or submitter.email or submitter.username)
+    end
+
+    return string.format("%s\n\n👀 Watching `%s`", msg, watched.watch)
+end
+
 function format_version_info(version_info)
     return string.format(
         "%s %s (commit id: %s, built with Rust %s for %s on %s)",
@@ -325,6 +823,17 @@ local FLAG_DESCRIPTIONS = {
     notify_reviewer_added = "Toggle notification messages when added as reviewer.",
     notify_change_abandoned = "Toggle notification when a change is abandoned.",
     notify_change_merged = "Toggle notification when a change is merged.",
+    notify_foreign_upload = "Toggle notification messages when somebody else uploads a patchset to your change, e.g. a colleague or a rebase bot.",
+    notify_mentions = "Toggle notification messages when somebody mentions you with `@` in a comment, even if you are not involved in the change.",
+    notify_thread_replies = "Toggle notification messages for replies to inline comment threads you took part in. Requires access to the Gerrit REST API.",
+    notify_vote_deleted = "Toggle notification messages when a vote on your change is removed.",
+    notify_reviewer_deleted = "Toggle notification messages when removed as reviewer.",
+    notify_ready_for_review = "Toggle notification messages when a change you review is not work in progress or private anymore.",
+    notify_topic_changed = "Toggle notification messages when the topic of a change you own or review changes.",
+    notify_hashtags_changed = "Toggle notification messages when hashtags are added to or removed from a change you own or review.",
+    notify_merge_conflicts = "Toggle notification messages when your change cannot be merged anymore after other changes were merged into its branch. Requires access to the Gerrit REST API.",
+    notify_reverts = "Toggle notification messages when a change you own or reviewed is reverted. Each revert is looked up in Gerrit, so this is off by default.",
+    notify_ci_results = "Toggle compact notifications with build links for votes of CI accounts, telling new failures from fixes. These replace the regular messages for CI votes.",
 }
 
 local FLAG_SINGLE_LINE_FORMAT = "* `%s` -- %s"
@@ -350,11 +859,49 @@ Commands:
 ]=] .. table.concat(flags, "\n") .. [=[
 
 
-`filter <regex>` -- Filter all messages by applying the specified regex pattern. If the pattern matches, the message is filtered. The pattern is applied to the full text I send to you. Be aware, to send this command **not** in markdown mode, otherwise, Spark would eat some special characters in the pattern. For regex specification, cf. https://docs.rs/regex/0.2.10/regex/#syntax.
+`filter <regex>` -- Filter all messages by applying the specified regex pattern. If the pattern matches, the message is filtered. This sets the filter named `default`. The pattern is applied to the full text I send to you. Be aware, to send this command **not** in markdown mode, otherwise, Spark would eat some special characters in the pattern. For regex specification, cf. https://docs.rs/regex/0.2.10/regex/#syntax.
+
+`filter add <name> <regex>` -- Add a named deny filter. Messages matching the pattern are filtered. An existing filter with the same name is replaced.
+
+`filter allow <name> <regex>` -- Add a named allow filter. Messages matching the pattern are never filtered, even if a deny filter matches them.
+
+`filter remove <name>` -- Remove the named filter.
+
+`filter list` -- List all your filters.
+
+`filter enable [<name>]` -- Enable the filtering of messages with the named filter, or the `default` filter if no name is given.
+
+`filter disable [<name>]` -- Disable the filtering of messages with the named filter, or the `default` filter if no name is given.
+
+`mute when <condition> [and <condition>...]` -- Mute events matching all conditions, e.g. `mute when actor=ci-bot and label=Verified value>0`. Conditions compare a field with a value using `=`, `!=`, `>`, `>=`, `<` or `<=`. Fields are `project`, `branch`, `actor` (the user causing the event, e.g. the author of a comment or the added reviewer; formerly `author`), `event` (e.g. `comment-added`), `label`, `value` (label and value refer to the same vote), `size` (changed lines) and `wip` (`true` or `false`). Unlike filters, mute rules don't depend on the message format.
+
+`mute <number>` or `mute topic:<topic>` -- Mute all events of a single change or of all changes with the given topic. Mutes of a change are removed once it is merged or abandoned.
+
+`unmute [when <rule>]` -- Remove the given mute rule, or all mute rules if none is given. `unmute <number>` and `unmute topic:<topic>` remove the corresponding mute.
 
-`filter enable` -- Enable the filtering of messages with the configured filter.
+`ignore user <account>` -- Never notify about events caused by the Gerrit account with the given email or username, e.g. a linter. Undo with `unignore user <account>`.
 
-`filter disable` -- Disable the filtering of messages with the configured filter.
+`always notify from <account>` -- Notify about all review comments and votes of the given Gerrit account, even if the corresponding flags are disabled. Undo with `normally notify from <account>`.
+
+`watch project:<project> [branch:<branch>]` -- Get notified about all changes in a project (and branch), even if you are not involved in them.
+
+`watch project:<project> path:<glob>` -- Get notified about all changes in a project touching files matching the glob, e.g. `src/net/**`. Matching files are listed in the notification.
+
+`watch hashtag:<hashtag>` -- Get notified about all changes with the given hashtag.
+
+`watch query <expression>` -- Get notified about all changes matching a Gerrit search expression. Supported are `project:`, `branch:`, `owner:`, `file:`, `topic:`, `hashtag:`, `label:` (e.g. `label:Code-Review=-2`) and `is:wip`, combined with `AND`, `OR`, `NOT` and parentheses.
+
+`watch refs project:<project> <glob>` -- Get notified when refs matching the glob are created, updated or deleted, e.g. `refs/tags/v*` for new release tags or `refs/heads/release/*` for release branches.
+
+`follow <number>` -- Get notified about all events of a single change, even if you are not involved in it, until it is merged or abandoned. Stop following with `unfollow <number>`.
+
+`unwatch [<watch>]` -- Stop watching the given project, path, hashtag, query or refs, or stop watching anything if none is given.
+
+`ooo until <YYYY-MM-DD> [with summary] ["<note>"]` -- Pause your notifications while you are out of office. Owners adding you as reviewer are told that you are away, together with the note, e.g. `ooo until 2026-11-02 "backup: alice@example.com"`. With `with summary`, I send you the paused notifications when you are back.
+
+`back` -- End your out of office status early.
+
+`max inline comments <n>` -- Show at most the given number of inline comments per review, with a link to the change for the rest. Show all of them again with `max inline comments off`.
 
 `status` -- Show if I am notifying you, and a little bit more information. 😉
 
@@ -400,10 +947,68 @@ function format_status(status_details, user_flags)
         flags_string = "No flags are enabled for you."
     end
 
+    local watch_strings = {}
+
+    for _i, watch in ipairs(status_details.watches or {}) do
+        table.insert(watch_strings, string.format("* `%s`", watch))
+    end
+
+    local watches_string = ""
+
+    if #watch_strings > 0 then
+        watches_string = "\n\nYou are **watching** the following changes regardless of your involvement: \n" .. table.concat(watch_strings, "\n")
+    end
+
+    local mute_strings = {}
+
+    for _i, mute in ipairs(status_details.mutes or {}) do
+        table.insert(mute_strings, string.format("* `%s`", mute))
+    end
+
+    local mutes_string = ""
+
+    if #mute_strings > 0 then
+        mutes_string = "\n\nEvents matching the following rules are **muted**: \n" .. table.concat(mute_strings, "\n")
+    end
+
+    local account_strings = {}
+
+    for _i, account in ipairs(status_details.accounts or {}) do
+        table.insert(account_strings, string.format("* `%s` (%s)", account.account, account.preference))
+    end
+
+    local accounts_string = ""
+
+    if #account_strings > 0 then
+        accounts_string = "\n\nEvents caused by the following accounts are treated **specially**: \n" .. table.concat(account_strings, "\n")
+    end
+
+    local away = status_details.away
+    local away_string = ""
+
+    if away then
+        away_string = string.format("\n\nYou are **out of office** until %s", away["until"])
+        if away.note then
+            away_string = away_string .. string.format(" (%s)", away.note)
+        end
+        away_string = away_string .. (away.summarize and ", I will summarize what you missed." or ".")
+    end
+
+    local inline_comments_string = ""
+
+    if status_details.max_inline_comments then
+        inline_comments_string = string.format("\n\nYou see at most **%d** inline comments per review.", status_details.max_inline_comments)
+    end
+
     return string.format(
-        "Notifications for you are **%s**. I am notifying %s.\n\n%s",
+        "Notifications for you are **%s**. I am notifying %s.\n\n%s%s%s%s%s%s",
         status_details.user_enabled and "enabled" or "disabled",
         other_users_string,
-        flags_string
+        flags_string,
+        watches_string,
+        mutes_string,
+        accounts_string,
+        away_string,
+        inline_comments_string
     )
 end
@@ -3,9 +3,14 @@ use serde::Serialize;
 
 use gerritbot_gerrit as gerrit;
 
-use crate::state::{User, NOTIFICATION_FLAGS};
+use crate::ci::CiResult;
+use crate::reminders::ReminderRole;
+use crate::revert::revert_reason;
+use crate::service_accounts::ServiceAccounts;
+use crate::split::MAX_MESSAGE_LENGTH;
+use crate::state::{Away, User, Watch, NOTIFICATION_FLAGS};
+use crate::threads::ThreadReply;
 use crate::version::VersionInfo;
-use crate::IsHuman;
 
 pub const DEFAULT_FORMAT_SCRIPT: &str = include_str!("format.lua");
 
@@ -29,10 +34,292 @@ impl<'a> MessageInput for &'a gerrit::ChangeAbandonedEvent {
     const FORMAT_FUNCTION: &'static str = "format_change_abandoned";
 }
 
+impl<'a> MessageInput for &'a gerrit::PatchsetCreatedEvent {
+    const FORMAT_FUNCTION: &'static str = "format_patchset_created";
+}
+
+impl<'a> MessageInput for &'a gerrit::VoteDeletedEvent {
+    const FORMAT_FUNCTION: &'static str = "format_vote_deleted";
+}
+
+impl<'a> MessageInput for &'a gerrit::ReviewerDeletedEvent {
+    const FORMAT_FUNCTION: &'static str = "format_reviewer_deleted";
+}
+
+impl<'a> MessageInput for &'a gerrit::TopicChangedEvent {
+    const FORMAT_FUNCTION: &'static str = "format_topic_changed";
+}
+
+impl<'a> MessageInput for &'a gerrit::HashtagsChangedEvent {
+    const FORMAT_FUNCTION: &'static str = "format_hashtags_changed";
+}
+
 impl<'a> MessageInput for &'a VersionInfo {
     const FORMAT_FUNCTION: &'static str = "format_version_info";
 }
 
+/// An event sent to a user because it matched one of their watches.
+#[derive(Serialize)]
+pub struct WatchedEvent<E> {
+    /// Gerrit event type, e.g. `comment-added`.
+    #[serde(rename = "type")]
+    event_type: &'static str,
+    event: E,
+    watch: String,
+    /// Files of the patchset matching a path watch.
+    files: Vec<String>,
+}
+
+impl<E: Serialize> WatchedEvent<E> {
+    pub fn new(event_type: &'static str, event: E, watch: &Watch, files: Vec<String>) -> Self {
+        Self {
+            event_type,
+            event,
+            watch: watch.to_string(),
+            files,
+        }
+    }
+}
+
+impl<E: Serialize> MessageInput for WatchedEvent<E> {
+    const FORMAT_FUNCTION: &'static str = "format_watched_event";
+}
+
+/// A change that is neither work in progress nor private anymore, sent to its
+/// reviewers.
+#[derive(Serialize, Clone, Copy)]
+pub struct ReadyForReviewMessage<'a> {
+    change: &'a gerrit::Change,
+    changer: &'a gerrit::User,
+}
+
+impl<'a> ReadyForReviewMessage<'a> {
+    pub fn new(change: &'a gerrit::Change, changer: &'a gerrit::User) -> Self {
+        Self { change, changer }
+    }
+}
+
+impl<'a> MessageInput for ReadyForReviewMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_ready_for_review";
+}
+
+/// An update of a ref matching a watch of the user.
+#[derive(Serialize)]
+pub struct RefUpdatedMessage<'a> {
+    event: &'a gerrit::RefUpdatedEvent,
+    watch: String,
+}
+
+impl<'a> RefUpdatedMessage<'a> {
+    pub fn new(event: &'a gerrit::RefUpdatedEvent, watch: &Watch) -> Self {
+        Self {
+            event,
+            watch: watch.to_string(),
+        }
+    }
+}
+
+impl<'a> MessageInput for RefUpdatedMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_ref_updated";
+}
+
+/// A vote of a CI account, sent instead of the regular comment message to
+/// users with the `notify_ci_results` flag.
+#[derive(Serialize)]
+pub struct CiResultMessage<'a> {
+    event: &'a gerrit::CommentAddedEvent,
+    result: &'a CiResult,
+}
+
+impl<'a> CiResultMessage<'a> {
+    pub fn new(event: &'a gerrit::CommentAddedEvent, result: &'a CiResult) -> Self {
+        Self { event, result }
+    }
+}
+
+impl<'a> MessageInput for CiResultMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_ci_result";
+}
+
+/// A revert of a change, sent to the owner and reviewers of the reverted
+/// change.
+#[derive(Serialize)]
+pub struct RevertMessage<'a> {
+    change: &'a gerrit::Change,
+    revert: &'a gerrit::Change,
+    merged: bool,
+    reason: Option<String>,
+}
+
+impl<'a> RevertMessage<'a> {
+    pub fn new(change: &'a gerrit::Change, revert: &'a gerrit::Change, merged: bool) -> Self {
+        Self {
+            change,
+            revert,
+            merged,
+            reason: revert_reason(&revert.commit_message),
+        }
+    }
+}
+
+impl<'a> MessageInput for RevertMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_change_reverted";
+}
+
+/// A change that cannot be merged anymore, sent to its owner.
+#[derive(Serialize)]
+pub struct MergeConflictMessage<'a> {
+    change: &'a gerrit::Change,
+    /// The changes merged into the branch since the last check.
+    merged: &'a [gerrit::Change],
+}
+
+impl<'a> MergeConflictMessage<'a> {
+    pub fn new(change: &'a gerrit::Change, merged: &'a [gerrit::Change]) -> Self {
+        Self { change, merged }
+    }
+}
+
+impl<'a> MessageInput for MergeConflictMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_merge_conflict";
+}
+
+/// A comment mentioning the user with `@`.
+#[derive(Serialize)]
+pub struct MentionMessage<'a> {
+    event: &'a gerrit::CommentAddedEvent,
+    /// The comments containing the mention.
+    comments: Vec<&'a str>,
+}
+
+impl<'a> MentionMessage<'a> {
+    pub fn new(event: &'a gerrit::CommentAddedEvent, comments: Vec<&'a str>) -> Self {
+        Self { event, comments }
+    }
+}
+
+impl<'a> MessageInput for MentionMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_mention";
+}
+
+/// Replies to inline comment threads the user took part in.
+#[derive(Serialize)]
+pub struct ThreadRepliesMessage<'a> {
+    event: &'a gerrit::CommentAddedEvent,
+    replies: Vec<&'a ThreadReply<'a>>,
+}
+
+impl<'a> ThreadRepliesMessage<'a> {
+    pub fn new(event: &'a gerrit::CommentAddedEvent, replies: Vec<&'a ThreadReply<'a>>) -> Self {
+        Self { event, replies }
+    }
+}
+
+impl<'a> MessageInput for ThreadRepliesMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_thread_replies";
+}
+
+/// A reminder about a change without activity.
+#[derive(Serialize)]
+pub struct ReminderMessage<'a> {
+    change: &'a gerrit::Change,
+    role: ReminderRole,
+    idle_days: u32,
+    reviewers: &'a [&'a gerrit::User],
+    /// Gerrit group of the team, for reminders sent to team rooms.
+    team: Option<&'a str>,
+}
+
+impl<'a> ReminderMessage<'a> {
+    pub fn new(
+        change: &'a gerrit::Change,
+        role: ReminderRole,
+        idle_days: u32,
+        reviewers: &'a [&'a gerrit::User],
+    ) -> Self {
+        Self {
+            change,
+            role,
+            idle_days,
+            reviewers,
+            team: None,
+        }
+    }
+
+    pub fn for_team(
+        change: &'a gerrit::Change,
+        team: &'a str,
+        idle_days: u32,
+        reviewers: &'a [&'a gerrit::User],
+    ) -> Self {
+        Self {
+            team: Some(team),
+            ..Self::new(change, ReminderRole::Team, idle_days, reviewers)
+        }
+    }
+}
+
+impl<'a> MessageInput for ReminderMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_reminder";
+}
+
+/// Review requested from members of a team, sent to the room of the team.
+#[derive(Serialize)]
+pub struct TeamReviewerAddedMessage<'a> {
+    event: &'a gerrit::ReviewerAddedEvent,
+    team: &'a str,
+}
+
+impl<'a> TeamReviewerAddedMessage<'a> {
+    pub fn new(event: &'a gerrit::ReviewerAddedEvent, team: &'a str) -> Self {
+        Self { event, team }
+    }
+}
+
+impl<'a> MessageInput for TeamReviewerAddedMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_team_reviewer_added";
+}
+
+/// Tells the owner of a change that a newly added reviewer is out of office.
+#[derive(Serialize)]
+pub struct ReviewerAwayMessage<'a> {
+    event: &'a gerrit::ReviewerAddedEvent,
+    until: String,
+    note: Option<&'a str>,
+}
+
+impl<'a> ReviewerAwayMessage<'a> {
+    pub fn new(event: &'a gerrit::ReviewerAddedEvent, away: &'a Away) -> Self {
+        Self {
+            event,
+            until: away.until.to_string(),
+            note: away.note.as_deref(),
+        }
+    }
+}
+
+impl<'a> MessageInput for ReviewerAwayMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_reviewer_away";
+}
+
+/// The notifications a user missed while being out of office.
+#[derive(Serialize)]
+pub struct AwaySummaryMessage<'a> {
+    missed: &'a [String],
+}
+
+impl<'a> AwaySummaryMessage<'a> {
+    pub fn new(away: &'a Away) -> Self {
+        Self {
+            missed: &away.missed,
+        }
+    }
+}
+
+impl<'a> MessageInput for AwaySummaryMessage<'a> {
+    const FORMAT_FUNCTION: &'static str = "format_away_summary";
+}
+
 #[derive(Serialize)]
 pub struct HelpMessage;
 
@@ -51,12 +338,49 @@ impl<'a> MessageInput for GreetingMessage {
 struct StatusDetails {
     user_enabled: bool,
     enabled_user_count: usize,
+    watches: Vec<String>,
+    mutes: Vec<String>,
+    accounts: Vec<AccountStatus>,
+    away: Option<AwayStatus>,
+    max_inline_comments: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct AwayStatus {
+    until: String,
+    note: Option<String>,
+    summarize: bool,
+}
+
+#[derive(Serialize)]
+struct AccountStatus {
+    account: String,
+    preference: String,
 }
 
 impl MessageInput for StatusDetails {
     const FORMAT_FUNCTION: &'static str = "format_status";
 }
 
+/// Limits passed to the formatting functions, so that they can leave out
+/// details instead of having the message split into several parts.
+#[derive(Serialize)]
+struct Limits {
+    /// length of a single message in bytes
+    max_length: usize,
+    /// inline comments shown per review; all if unset
+    max_inline_comments: Option<u32>,
+}
+
+impl Limits {
+    fn for_user(user: Option<&User>) -> Self {
+        Self {
+            max_length: MAX_MESSAGE_LENGTH,
+            max_inline_comments: user.and_then(User::max_inline_comments),
+        }
+    }
+}
+
 pub struct Formatter {
     lua: Lua,
 }
@@ -73,18 +397,7 @@ fn load_format_script(script_source: &str) -> Result<Lua, String> {
     let lua_std_lib = LuaStdLib::BASE | LuaStdLib::STRING | LuaStdLib::TABLE;
     let lua = Lua::new_with(lua_std_lib);
     lua.context(|context| -> Result<(), String> {
-        let globals = context.globals();
-
-        let is_human = context
-            .create_function(|_, user| {
-                let user: gerrit::User = rlua_serde::from_value(user)?;
-                Ok(user.is_human())
-            })
-            .map_err(|e| format!("failed to create is_human function: {}", e))?;
-
-        globals
-            .set("is_human", is_human)
-            .map_err(|e| format!("failed to set is_human function: {}", e))?;
+        set_is_human_function(context, ServiceAccounts::default())?;
 
         context
             .load(script_source)
@@ -98,6 +411,23 @@ fn load_format_script(script_source: &str) -> Result<Lua, String> {
     Ok(lua)
 }
 
+fn set_is_human_function(
+    context: rlua::Context,
+    service_accounts: ServiceAccounts,
+) -> Result<(), String> {
+    let is_human = context
+        .create_function(move |_, user| {
+            let user: gerrit::User = rlua_serde::from_value(user)?;
+            Ok(service_accounts.is_human(&user))
+        })
+        .map_err(|e| format!("failed to create is_human function: {}", e))?;
+
+    context
+        .globals()
+        .set("is_human", is_human)
+        .map_err(|e| format!("failed to set is_human function: {}", e))
+}
+
 fn get_flags_table<'lua>(user: &User, lua: rlua::Context<'lua>) -> rlua::Result<rlua::Table<'lua>> {
     lua.create_table_from(NOTIFICATION_FLAGS.iter().cloned().filter_map(|flag| {
         if user.has_flag(flag) {
@@ -115,6 +445,13 @@ impl Formatter {
         })
     }
 
+    /// Make the `is_human` function of the script use the given service
+    /// accounts.
+    pub fn set_service_accounts(&self, service_accounts: ServiceAccounts) -> Result<(), String> {
+        self.lua
+            .context(move |lua| set_is_human_function(lua, service_accounts))
+    }
+
     fn format_lua<'lua, I>(
         lua: rlua::Context<'lua>,
         user: Option<&User>,
@@ -140,6 +477,8 @@ impl Formatter {
             } else {
                 LuaNil
             },
+            rlua_serde::to_value(lua, Limits::for_user(user))
+                .map_err(|e| format!("failed to serialize limits: {}", e))?,
         );
 
         let result = format_function
@@ -171,6 +510,29 @@ impl Formatter {
                     .map(|u| u.has_any_flag(NOTIFICATION_FLAGS))
                     .unwrap_or(false),
                 enabled_user_count,
+                watches: user
+                    .map(|u| u.watches().iter().map(ToString::to_string).collect())
+                    .unwrap_or_default(),
+                mutes: user
+                    .map(|u| u.mutes().iter().map(ToString::to_string).collect())
+                    .unwrap_or_default(),
+                accounts: user
+                    .map(|u| {
+                        u.account_preferences()
+                            .iter()
+                            .map(|(account, preference)| AccountStatus {
+                                account: account.clone(),
+                                preference: preference.to_string(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                away: user.and_then(User::away).map(|away| AwayStatus {
+                    until: away.until.to_string(),
+                    note: away.note.clone(),
+                    summarize: away.summarize,
+                }),
+                max_inline_comments: user.and_then(User::max_inline_comments),
             },
         )
     }
@@ -303,4 +665,79 @@ mod test {
 
         assert!(res.ends_with("`/COMMIT_MSG`\n\n> [Line 1](http://localhost:8080/#/c/1/1//COMMIT_MSG@1) by [jdoe](http://localhost:8080/q/reviewer:john.doe@localhost+status:open): This is a multiline\n> comment\n> on some change.\n"), "no inline comments: {:?}", res);
     }
+
+    #[test]
+    fn test_format_comments_with_context() {
+        let mut event = get_event();
+        let (change, mut patchset) = get_change_with_comments();
+        event.comment = "(1 comment)".to_string();
+        event.change = change;
+        patchset.comments.as_mut().unwrap()[0].context = Some(vec![
+            gerrit::ContextLine {
+                line: 1,
+                text: "Some change.".to_string(),
+                commented: true,
+            },
+            gerrit::ContextLine {
+                line: 2,
+                text: "".to_string(),
+                commented: false,
+            },
+        ]);
+        event.patchset = patchset;
+
+        let res = Formatter::default()
+            .format_message(Some(&FORMAT_TEST_USER), &event)
+            .expect("format failed")
+            .expect("no comments");
+
+        assert!(
+            res.ends_with("> on some change.\n\n```\n>   1 | Some change.\n    2 | \n```\n"),
+            "no code context: {:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn format_comments_within_limits() {
+        let mut event = get_event();
+        let (change, mut patchset) = get_change_with_comments();
+        event.comment = "(3 comments)".to_string();
+        event.change = change;
+        let comments = patchset.comments.as_mut().unwrap();
+        comments[0].context = Some(
+            (1..=100)
+                .map(|line| gerrit::ContextLine {
+                    line,
+                    text: "x".repeat(100),
+                    commented: line == 1,
+                })
+                .collect(),
+        );
+        comments.push(comments[0].clone());
+        comments.push(comments[0].clone());
+        event.patchset = patchset;
+
+        // too long with code context
+        let res = Formatter::default()
+            .format_message(Some(&FORMAT_TEST_USER), &event)
+            .expect("format failed")
+            .expect("no comments");
+        assert!(!res.contains("```"), "code context: {:?}", res);
+        assert_eq!(res.matches("> [Line 1]").count(), 3);
+
+        let mut state = State::new();
+        let email = spark::EmailRef::new("some@example.com");
+        state.set_max_inline_comments(email, Some(2));
+        let res = Formatter::default()
+            .format_message(state.find_user(email), &event)
+            .expect("format failed")
+            .expect("no comments");
+        assert_eq!(res.matches("> [Line 1]").count(), 2);
+        assert!(
+            res.ends_with("\n\n…and [1 more](http://localhost:8080/1)\n"),
+            "no link to the other comments: {:?}",
+            res
+        );
+    }
 }
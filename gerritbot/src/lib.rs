@@ -3,38 +3,102 @@ use std::convert::{self, identity};
 use std::fs::File;
 use std::io;
 use std::path::Path;
-use std::time::Duration;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use futures::{future::Future, stream, stream::Stream};
+use futures::{future, future::Future, stream, stream::Stream};
 use lazy_static::lazy_static;
 use log::{debug, error};
+use lru_time_cache::LruCache;
 use regex::Regex;
+use serde::Serialize;
 
 use gerritbot_gerrit as gerrit;
 use gerritbot_spark as spark;
 
 pub mod args;
+mod ci;
+mod code_context;
 mod command;
+#[cfg(test)]
+mod fixtures;
 mod format;
+mod mentions;
+mod merge_conflicts;
+mod outbox;
+mod query;
 mod rate_limit;
+mod reminders;
+mod revert;
+mod send_queue;
+mod service_accounts;
+mod split;
 mod state;
+mod teams;
+mod threads;
 mod version;
 
+pub use ci::CiTracker;
 use command::Command;
-use format::Formatter;
 pub use format::DEFAULT_FORMAT_SCRIPT;
+use format::{
+    AwaySummaryMessage, CiResultMessage, Formatter, MentionMessage, MergeConflictMessage,
+    MessageInput, ReadyForReviewMessage, RefUpdatedMessage, ReminderMessage, RevertMessage,
+    ReviewerAwayMessage, TeamReviewerAddedMessage, ThreadRepliesMessage, WatchedEvent,
+};
+pub use outbox::Outbox;
 use rate_limit::RateLimiter;
+use reminders::{ReminderRole, ESCALATION_RECIPIENT, SECONDS_PER_DAY};
+pub use service_accounts::ServiceAccounts;
 pub use state::State;
-use state::{User, UserFlag, NOTIFICATION_FLAGS, REVIEW_COMMENT_FLAGS};
+use state::{
+    AccountPreference, Away, Date, User, UserFlag, NOTIFICATION_FLAGS, REVIEW_COMMENT_FLAGS,
+};
+pub use teams::Teams;
 use version::VERSION_INFO;
 
-pub trait GerritCommandRunner {}
+/// Review requests of a team are announced at most once per change within
+/// this duration.
+const TEAM_ANNOUNCEMENT_EXPIRATION: Duration = Duration::from_secs(3600);
+const TEAM_ANNOUNCEMENT_CAPACITY: usize = 1000;
+
+/// Recipients of the messages for a comment are remembered for this duration,
+/// so that they don't get another message for replies in the same comment.
+const COMMENT_RECIPIENTS_EXPIRATION: Duration = Duration::from_secs(600);
+const COMMENT_RECIPIENTS_CAPACITY: usize = 1000;
+
+/// Fetching more information for an event from Gerrit is given up after this
+/// duration.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events for which more information is fetched from Gerrit at the same time.
+const MAX_CONCURRENT_FETCHES: usize = 4;
+
+pub trait GerritCommandRunner {
+    /// Query changes including all of their reviewers and the current
+    /// patchset with its approvals.
+    fn query_changes(
+        &mut self,
+        query: &str,
+    ) -> Box<dyn Future<Item = Vec<gerrit::Change>, Error = String> + Send>;
+}
 
-impl GerritCommandRunner for gerrit::CommandRunner {}
+impl GerritCommandRunner for gerrit::CommandRunner {
+    fn query_changes(
+        &mut self,
+        query: &str,
+    ) -> Box<dyn Future<Item = Vec<gerrit::Change>, Error = String> + Send> {
+        Box::new(gerrit::query_changes(self, query))
+    }
+}
 
 pub trait SparkClient: Clone {
     type ReplyFuture: Future<Item = (), Error = spark::Error> + Send;
     fn send_message(&self, email: &spark::EmailRef, msg: &str) -> Self::ReplyFuture;
+    fn send_room_message(&self, room_id: &spark::RoomIdRef, msg: &str) -> Self::ReplyFuture;
 }
 
 impl SparkClient for spark::Client {
@@ -42,6 +106,9 @@ impl SparkClient for spark::Client {
     fn send_message(&self, email: &spark::EmailRef, msg: &str) -> Self::ReplyFuture {
         Box::new(self.send_message(email, msg))
     }
+    fn send_room_message(&self, room_id: &spark::RoomIdRef, msg: &str) -> Self::ReplyFuture {
+        Box::new(self.send_message(room_id, msg))
+    }
 }
 
 #[derive(Debug)]
@@ -67,16 +134,41 @@ pub struct Builder {
     state: State,
     rate_limiter: RateLimiter,
     formatter: Formatter,
+    fetch_files: Arc<AtomicBool>,
+    service_accounts: ServiceAccounts,
+    ci_tracker: CiTracker,
+    reminders: Option<args::RemindersConfig>,
+    rest_client: Option<gerrit::RestClient>,
+    teams: Teams,
+    outbox: Outbox,
 }
 
 impl Builder {
     pub fn new(state: State) -> Self {
         Self {
+            fetch_files: Arc::new(AtomicBool::new(state.has_file_watches())),
             state,
             ..Default::default()
         }
     }
 
+    /// Return a function selecting the extended info to fetch for gerrit
+    /// events. It stays in sync with the state of the bot built later on, e.g.
+    /// files are only fetched while some user watches file paths.
+    pub fn extended_gerrit_info_selector(
+        &self,
+    ) -> impl FnMut(&gerrit::Event) -> Cow<'static, [gerrit::ExtendedInfo]> + Send {
+        let fetch_files = self.fetch_files.clone();
+        let service_accounts = self.service_accounts.clone();
+        move |event| {
+            select_extended_gerrit_info(
+                event,
+                &service_accounts,
+                fetch_files.load(Ordering::Relaxed),
+            )
+        }
+    }
+
     pub fn with_msg_cache(self, capacity: usize, expiration: Duration) -> Self {
         Self {
             rate_limiter: RateLimiter::with_expiry_duration_and_capacity(expiration, capacity),
@@ -84,6 +176,39 @@ impl Builder {
         }
     }
 
+    pub fn with_service_accounts(self, service_accounts: ServiceAccounts) -> Self {
+        Self {
+            service_accounts,
+            ..self
+        }
+    }
+
+    pub fn with_ci_tracker(self, ci_tracker: CiTracker) -> Self {
+        Self { ci_tracker, ..self }
+    }
+
+    pub fn with_reminders(self, reminders: args::RemindersConfig) -> Self {
+        Self {
+            reminders: Some(reminders),
+            ..self
+        }
+    }
+
+    pub fn with_gerrit_rest_client(self, rest_client: gerrit::RestClient) -> Self {
+        Self {
+            rest_client: Some(rest_client),
+            ..self
+        }
+    }
+
+    pub fn with_teams(self, teams: Teams) -> Self {
+        Self { teams, ..self }
+    }
+
+    pub fn with_outbox(self, outbox: Outbox) -> Self {
+        Self { outbox, ..self }
+    }
+
     pub fn with_format_script(self, script_source: &str) -> Result<Self, String> {
         Ok(Self {
             formatter: Formatter::new(script_source)?,
@@ -96,14 +221,41 @@ impl Builder {
             formatter,
             rate_limiter,
             state,
+            fetch_files,
+            service_accounts,
+            ci_tracker,
+            reminders,
+            rest_client,
+            teams,
+            outbox,
         } = self;
 
+        if let Err(e) = formatter.set_service_accounts(service_accounts.clone()) {
+            error!("failed to set service accounts of format script: {}", e);
+        }
+
         Bot {
             gerrit_command_runner,
             spark_client,
             rate_limiter,
             formatter,
             state,
+            fetch_files,
+            service_accounts,
+            ci_tracker,
+            reminders,
+            rest_client,
+            merge_checks: Default::default(),
+            teams,
+            team_announcements: LruCache::with_expiry_duration_and_capacity(
+                TEAM_ANNOUNCEMENT_EXPIRATION,
+                TEAM_ANNOUNCEMENT_CAPACITY,
+            ),
+            comment_recipients: LruCache::with_expiry_duration_and_capacity(
+                COMMENT_RECIPIENTS_EXPIRATION,
+                COMMENT_RECIPIENTS_CAPACITY,
+            ),
+            outbox,
         }
     }
 }
@@ -123,23 +275,59 @@ fn gerrit_event_to_action(event: gerrit::Event) -> Option<Action> {
     match event {
         gerrit::Event::CommentAdded(event) => Some(Action::CommentAdded(Box::new(event))),
         gerrit::Event::ReviewerAdded(event) => Some(Action::ReviewerAdded(Box::new(event))),
+        gerrit::Event::VoteDeleted(event) => Some(Action::VoteDeleted(Box::new(event))),
+        gerrit::Event::ReviewerDeleted(event) => Some(Action::ReviewerDeleted(Box::new(event))),
         gerrit::Event::ChangeMerged(event) => Some(Action::ChangeMerged(Box::new(event))),
         gerrit::Event::ChangeAbandoned(event) => Some(Action::ChangeAbandoned(Box::new(event))),
+        gerrit::Event::PatchsetCreated(event) => Some(Action::PatchsetCreated(Box::new(event))),
+        gerrit::Event::RefUpdated(event) => Some(Action::RefUpdated(Box::new(event))),
+        gerrit::Event::WipStateChanged(event) => Some(Action::WipStateChanged(Box::new(event))),
+        gerrit::Event::PrivateStateChanged(event) => {
+            Some(Action::PrivateStateChanged(Box::new(event)))
+        }
+        gerrit::Event::TopicChanged(event) => Some(Action::TopicChanged(Box::new(event))),
+        gerrit::Event::HashtagsChanged(event) => Some(Action::HashtagsChanged(Box::new(event))),
     }
 }
 
+#[deprecated(note = "use `ServiceAccounts::is_human`, which follows the configuration")]
 pub trait IsHuman {
     fn is_human(&self) -> bool;
 }
 
+/// Uses the default service accounts, i.e. usernames containing "bot".
+#[allow(deprecated)]
 impl IsHuman for gerrit::User {
     fn is_human(&self) -> bool {
-        // XXX: Maybe this should be sophisticated to avoid matching humans
-        // whose name contains "bot"?
-        !matches!(self.username, Some(ref username) if username.contains("bot"))
+        lazy_static! {
+            static ref DEFAULT_SERVICE_ACCOUNTS: ServiceAccounts = ServiceAccounts::default();
+        }
+        DEFAULT_SERVICE_ACCOUNTS.is_human(self)
     }
 }
 
+/// Whether the reviewer voted on the current patchset of the change.
+fn has_current_vote(change: &gerrit::Change, reviewer: &gerrit::User) -> bool {
+    change
+        .current_patch_set
+        .iter()
+        .flat_map(|patchset| patchset.approvals.iter().flatten())
+        .any(|approval| {
+            approval.value != "0"
+                && reviewer.email.is_some()
+                && matches!(&approval.by, Some(by) if by.email == reviewer.email)
+        })
+}
+
+/// Check if the account (email, username or full name) refers to the user.
+fn user_matches_account(user: &gerrit::User, account: &str) -> bool {
+    user.email
+        .iter()
+        .any(|email| email.eq_ignore_ascii_case(account))
+        || user.username.iter().any(|username| username == account)
+        || user.name.iter().any(|name| name == account)
+}
+
 trait SparkEmail {
     fn spark_email(&self) -> Option<&spark::EmailRef>;
 }
@@ -150,7 +338,19 @@ impl SparkEmail for gerrit::User {
     }
 }
 
+/// Select the extended info to fetch for gerrit events, using the default
+/// service accounts and never fetching files. See
+/// [`Builder::extended_gerrit_info_selector`] for a selector following the
+/// configuration of the bot.
 pub fn request_extended_gerrit_info(event: &gerrit::Event) -> Cow<'static, [gerrit::ExtendedInfo]> {
+    select_extended_gerrit_info(event, &ServiceAccounts::default(), false)
+}
+
+fn select_extended_gerrit_info(
+    event: &gerrit::Event,
+    service_accounts: &ServiceAccounts,
+    fetch_files: bool,
+) -> Cow<'static, [gerrit::ExtendedInfo]> {
     let mut extended_info = Vec::new();
 
     match event {
@@ -158,7 +358,7 @@ pub fn request_extended_gerrit_info(event: &gerrit::Event) -> Cow<'static, [gerr
             let owner_name = event.change.owner.username.as_ref();
             let approver_name = event.author.username.as_ref();
 
-            if event.author.is_human() && maybe_has_inline_comments(event) {
+            if service_accounts.is_human(&event.author) && maybe_has_inline_comments(event) {
                 extended_info.push(gerrit::ExtendedInfo::InlineComments);
             }
 
@@ -172,12 +372,26 @@ pub fn request_extended_gerrit_info(event: &gerrit::Event) -> Cow<'static, [gerr
             // is positive.
             extended_info.push(gerrit::ExtendedInfo::SubmitRecords);
         }
+        // The reviewers are notified about these.
+        gerrit::Event::WipStateChanged(_)
+        | gerrit::Event::PrivateStateChanged(_)
+        | gerrit::Event::TopicChanged(_)
+        | gerrit::Event::HashtagsChanged(_) => {
+            extended_info.push(gerrit::ExtendedInfo::AllReviewers);
+        }
         gerrit::Event::ChangeMerged(_) | gerrit::Event::ChangeAbandoned(_) => {
             extended_info.push(gerrit::ExtendedInfo::AllApprovals);
         }
         _ => (),
     }
 
+    // Files are only needed to match path watches.
+    if fetch_files {
+        if let gerrit::Event::PatchsetCreated(_) | gerrit::Event::ChangeMerged(_) = event {
+            extended_info.push(gerrit::ExtendedInfo::Files);
+        }
+    }
+
     Cow::Owned(extended_info)
 }
 
@@ -187,6 +401,21 @@ pub struct Bot<G = gerrit::CommandRunner, S = spark::Client> {
     formatter: format::Formatter,
     gerrit_command_runner: G,
     spark_client: S,
+    /// Shared with the extended info selector of the gerrit event stream.
+    fetch_files: Arc<AtomicBool>,
+    service_accounts: ServiceAccounts,
+    ci_tracker: CiTracker,
+    reminders: Option<args::RemindersConfig>,
+    rest_client: Option<gerrit::RestClient>,
+    merge_checks: merge_conflicts::PendingChecks,
+    teams: Teams,
+    /// Changes and teams whose room was told about a review request recently.
+    /// Adding a group as reviewer adds its members one by one.
+    team_announcements: LruCache<(u32, String), ()>,
+    /// Users who got a message for a comment, by change and creation time of
+    /// the comment.
+    comment_recipients: LruCache<(u32, u32), Vec<spark::Email>>,
+    outbox: Outbox,
 }
 
 impl<G, S> Bot<G, S>
@@ -199,42 +428,367 @@ where
         gerrit_events: impl Stream<Item = gerrit::Event, Error = ()> + Send,
         spark_messages: impl Stream<Item = spark::Message, Error = ()> + Send,
     ) -> impl Future<Item = (), Error = ()> {
-        let _ = &self.gerrit_command_runner;
         let spark_client = self.spark_client.clone();
-        let gerrit_actions = gerrit_events.filter_map(gerrit_event_to_action);
+        let reminder_config = self.reminders.clone();
+        let check_merge_conflicts = self.rest_client.is_some();
         let spark_actions = spark_messages.map(spark_message_to_action);
         let bot_for_action = std::sync::Arc::new(std::sync::Mutex::new(self));
-        let bot_for_task = bot_for_action.clone();
+        let bot_for_reminders = bot_for_action.clone();
+        let bot_for_follow_ups = bot_for_action.clone();
+        let bot_for_context = bot_for_action.clone();
+        let bot_for_merge_checks = bot_for_action.clone();
+        let bot_for_retries = bot_for_action.clone();
+        let bot_for_results = bot_for_action.clone();
+
+        // fetch more information for some events from gerrit, which is handled
+        // after the event itself
+        let gerrit_actions = gerrit_events
+            .filter_map(gerrit_event_to_action)
+            .map(move |action| bot_for_context.lock().unwrap().add_code_context(action))
+            .buffered(MAX_CONCURRENT_FETCHES)
+            .map(move |action| {
+                let follow_up = bot_for_follow_ups.lock().unwrap().follow_up(&action);
+                match follow_up {
+                    Some(follow_up) => future::Either::A(follow_up.then(move |result| {
+                        Ok(std::iter::once(action).chain(result.ok()).collect())
+                    })),
+                    None => future::Either::B(future::ok(vec![action])),
+                }
+            })
+            .buffered(MAX_CONCURRENT_FETCHES)
+            .map(stream::iter_ok)
+            .flatten();
+
+        // regularly query the changes to remind reviewers of
+        let reminder_actions = match reminder_config {
+            Some(config) => future::Either::A(
+                tokio::timer::Interval::new(
+                    Instant::now(),
+                    Duration::from_secs(config.check_interval),
+                )
+                .map_err(|e| error!("reminder timer failed: {}", e))
+                .and_then(move |_| {
+                    let query = bot_for_reminders
+                        .lock()
+                        .unwrap()
+                        .gerrit_command_runner
+                        .query_changes(&config.query);
+                    query.then(|result| {
+                        Ok(result
+                            .map_err(|e| error!("failed to query stale changes: {}", e))
+                            .ok())
+                    })
+                })
+                .filter_map(identity)
+                .map(|changes| Action::RemindStaleChanges {
+                    changes,
+                    now: unix_now(),
+                }),
+            ),
+            None => future::Either::B(stream::empty()),
+        };
 
-        gerrit_actions
+        // regularly check the open changes of branches with merged changes
+        let merge_check_actions = if check_merge_conflicts {
+            future::Either::A(
+                tokio::timer::Interval::new(
+                    Instant::now(),
+                    Duration::from_secs(merge_conflicts::CHECK_INTERVAL),
+                )
+                .map_err(|e| error!("merge conflict timer failed: {}", e))
+                .map(move |_| bot_for_merge_checks.lock().unwrap().check_merge_conflicts())
+                .flatten(),
+            )
+        } else {
+            future::Either::B(stream::empty())
+        };
+
+        // new messages are added to the outbox and saved before they are sent,
+        // once for all messages of an action
+        let new_messages = gerrit_actions
             .select(spark_actions)
-            .map(move |action| bot_for_action.lock().unwrap().update(action))
-            .map(stream::iter_ok)
-            .flatten()
-            .filter_map(move |task| bot_for_task.lock().unwrap().handle_task(task))
-            .map(move |response| {
-                debug!("Replying with: {}", response.message);
-                spark_client.send_message(&response.email, &response.message)
+            .select(reminder_actions)
+            .select(merge_check_actions)
+            .map(move |action| {
+                let mut bot = bot_for_action.lock().unwrap();
+                let tasks = bot.update(action);
+                let entries: Vec<_> = tasks
+                    .into_iter()
+                    .flat_map(|task| bot.handle_task(task))
+                    .collect();
+                bot.outbox.flush();
+                stream::iter_ok(entries)
             })
-            .map(|send_future| {
-                // try sending a message for up to 5 seconds, then give up
-                tokio::timer::Timeout::new(send_future, Duration::from_secs(5))
-                    // log and suppress errors
-                    .or_else(|e| {
+            .flatten();
+
+        // regularly retry failed messages and send the ones left over from a
+        // previous run
+        let retried_messages = tokio::timer::Interval::new(
+            Instant::now(),
+            Duration::from_secs(outbox::RETRY_INTERVAL),
+        )
+        // without a timer, e.g. in tests, messages are not retried
+        .then(|tick| {
+            Ok(tick
+                .map_err(|e| error!("outbox timer failed, not retrying messages: {}", e))
+                .ok())
+        })
+        .take_while(|tick| Ok(tick.is_some()))
+        .map(move |_| {
+            let mut bot = bot_for_retries.lock().unwrap();
+            // results of sends are saved in batches as well
+            bot.outbox.flush();
+            stream::iter_ok(bot.outbox.take_due(unix_now()))
+        })
+        .flatten();
+
+        send_queue::send_ordered(new_messages.select(retried_messages), move |entry| {
+            let send_future = match entry.recipient {
+                outbox::Recipient::Person(ref email) => {
+                    debug!("Replying with: {}", entry.message);
+                    spark_client.send_message(email, &entry.message)
+                }
+                outbox::Recipient::Room(ref room_id) => {
+                    debug!("Sending to room {}: {}", room_id, entry.message);
+                    spark_client.send_room_message(room_id, &entry.message)
+                }
+            };
+            let bot = bot_for_results.clone();
+            // try sending a message for up to 5 seconds, then retry later
+            tokio::timer::Timeout::new(send_future, Duration::from_secs(5)).then(move |result| {
+                let mut bot = bot.lock().unwrap();
+                let retried = match result {
+                    Ok(()) => {
+                        bot.outbox.ack(entry.id);
+                        false
+                    }
+                    Err(e) => {
                         error!("failed to send spark message: {}", e);
-                        Ok(())
+                        match e.into_inner() {
+                            Some(ref e) if e.is_permanent() => {
+                                bot.outbox.give_up(entry.id, e.to_string());
+                                false
+                            }
+                            Some(e) => bot.outbox.fail(
+                                entry.id,
+                                e.to_string(),
+                                e.retry_after().map(|duration| duration.as_secs()),
+                                unix_now(),
+                            ),
+                            None => {
+                                bot.outbox
+                                    .fail(entry.id, "timed out".to_string(), None, unix_now())
+                            }
+                        }
+                    }
+                };
+                Ok(if retried {
+                    send_queue::Delivery::Retry
+                } else {
+                    send_queue::Delivery::Done
+                })
+            })
+        })
+    }
+
+    /// Return a future adding the lines around inline comments to a comment
+    /// event. The action is returned unchanged without access to the REST API,
+    /// or if nobody gets to see the inline comments.
+    fn add_code_context(
+        &self,
+        action: Action,
+    ) -> Box<dyn Future<Item = Action, Error = ()> + Send> {
+        let (mut event, rest_client) = match (action, &self.rest_client) {
+            (Action::CommentAdded(event), Some(rest_client))
+                if !code_context::commented_files(&event.patchset).is_empty()
+                    && self.shows_inline_comments(&event) =>
+            {
+                (event, rest_client)
+            }
+            (action, _) => return Box::new(future::ok(action)),
+        };
+
+        let contents: Vec<_> = code_context::commented_files(&event.patchset)
+            .into_iter()
+            .map(|file| {
+                let file = file.to_string();
+                let content = rest_client
+                    .get_file_content(event.change.number, &event.patchset.revision, &file)
+                    .map_err({
+                        let file = file.clone();
+                        move |e| error!("failed to fetch content of {}: {}", file, e)
+                    });
+                with_fetch_timeout(content)
+                    .then(move |result| Ok(result.ok().map(|content| (file, content))))
+            })
+            .collect();
+
+        Box::new(future::join_all(contents).map(move |contents| {
+            let contents = contents.into_iter().flatten().collect();
+            code_context::add_code_context(&mut event.patchset, &contents);
+            Action::CommentAdded(event)
+        }))
+    }
+
+    /// Check if somebody gets a message for the comment event showing its inline
+    /// comments, i.e. if it is worth fetching their code context.
+    fn shows_inline_comments(&self, event: &gerrit::CommentAddedEvent) -> bool {
+        self.interested_users(&event.change, &event.patchset)
+            .filter(|user| Some(user.email()) != event.author.spark_email())
+            .filter_map(|user| user.for_actor(&event.author))
+            .filter(|user| user.has_flag(UserFlag::NotifyReviewInlineComments))
+            .filter(|user| user.max_inline_comments() != Some(0))
+            .any(|user| !self.state.is_muted(&user, event))
+    }
+
+    /// Return a future fetching what is needed for a follow-up action of the
+    /// given one: the change reverted by a new or merged change, or the
+    /// comment threads of a change with new inline comments.
+    fn follow_up(
+        &mut self,
+        action: &Action,
+    ) -> Option<Box<dyn Future<Item = Action, Error = ()> + Send>> {
+        let has_flag = |bot: &Self, flag| bot.state.users().any(|u| u.has_flag(flag));
+
+        if let Some(sha) = action.reverted_commit() {
+            if !has_flag(self, UserFlag::NotifyReverts) {
+                return None;
+            }
+            let (revert, merged) = match action {
+                Action::ChangeMerged(event) => (event.change.clone(), true),
+                Action::PatchsetCreated(event) => (event.change.clone(), false),
+                _ => return None,
+            };
+            let query = self
+                .gerrit_command_runner
+                .query_changes(&format!("commit:{}", sha));
+            return Some(Box::new(
+                with_fetch_timeout(
+                    query.map_err(|e| error!("failed to query reverted change: {}", e)),
+                )
+                .and_then(|changes| changes.into_iter().next().ok_or(()))
+                .map(move |reverted| Action::ChangeReverted {
+                    revert: Box::new(revert),
+                    reverted: Box::new(reverted),
+                    merged,
+                }),
+            ));
+        }
+
+        match action {
+            Action::CommentAdded(event)
+                if self.service_accounts.is_human(&event.author)
+                    && maybe_has_inline_comments(event)
+                    && has_flag(self, UserFlag::NotifyThreadReplies) =>
+            {
+                let event = event.clone();
+                let comments = self.rest_client.as_ref()?.get_comments(event.change.number);
+                Some(Box::new(
+                    with_fetch_timeout(
+                        comments.map_err(|e| error!("failed to fetch comments: {}", e)),
+                    )
+                    .map(move |comments| Action::ThreadReplies { event, comments }),
+                ))
+            }
+            _ => None,
+        }
+    }
+
+    /// Return a stream checking the mergeability of the open changes on the
+    /// branches with changes merged since the last check. Only changes of
+    /// users who want to know about merge conflicts are checked.
+    fn check_merge_conflicts(&mut self) -> Box<dyn Stream<Item = Action, Error = ()> + Send> {
+        let rest_client = match self.rest_client {
+            Some(ref rest_client) => rest_client.clone(),
+            None => return Box::new(stream::empty()),
+        };
+        let owners: Vec<String> = self
+            .state
+            .users()
+            .filter(|user| user.has_flag(UserFlag::NotifyMergeConflicts))
+            .map(|user| user.email().as_str().to_string())
+            .collect();
+
+        let checks: Vec<_> = self
+            .merge_checks
+            .take()
+            .into_iter()
+            .map(|(query, merged)| {
+                let rest_client = rest_client.clone();
+                let owners = owners.clone();
+                self.gerrit_command_runner
+                    .query_changes(&query)
+                    .map_err(|e| error!("failed to query open changes: {}", e))
+                    .and_then(move |changes| {
+                        stream::iter_ok(changes)
+                            .filter(move |change| {
+                                matches!(&change.owner.email, Some(email)
+                                    if owners.iter().any(|owner| owner.eq_ignore_ascii_case(email)))
+                            })
+                            .map(move |change| {
+                                rest_client
+                                    .get_mergeable(change.number)
+                                    .then(move |result| {
+                                        Ok(match result {
+                                            Ok(mergeable) => Some((change, mergeable)),
+                                            Err(e) => {
+                                                error!(
+                                                    "failed to check mergeability of change {}: {}",
+                                                    change.number, e
+                                                );
+                                                None
+                                            }
+                                        })
+                                    })
+                            })
+                            .buffered(merge_conflicts::MAX_CONCURRENT_REQUESTS)
+                            .filter_map(identity)
+                            .collect()
                     })
+                    .map(move |changes| Action::MergeabilityChecked { merged, changes })
+                    .then(|result| Ok(result.ok()))
             })
-            // try sending up to 10 messages at a time
-            .buffer_unordered(10)
-            .for_each(|()| Ok(()))
+            .collect();
+
+        Box::new(stream::futures_ordered(checks).filter_map(identity))
     }
 
     /// Action controller
     /// Return an optional message to send to the user
     fn update(&mut self, action: Action) -> Vec<Task> {
+        let now = unix_now();
+        let is_event = !matches!(
+            action,
+            Action::RunCommand { .. } | Action::UnknownCommand { .. }
+        );
+        let mut learned_usernames = false;
+        for account in action.accounts() {
+            if let (Some(email), Some(username)) = (account.spark_email(), &account.username) {
+                learned_usernames |= self.state.learn_gerrit_username(email, username);
+            }
+        }
+        let mut tasks = self.end_expired_away(now);
+        let action_tasks = self.handle_action(action, now);
+        if is_event {
+            tasks.extend(self.pause_away_notifications(action_tasks, now));
+        } else {
+            tasks.extend(action_tasks);
+        }
+        if learned_usernames && !tasks.iter().any(|task| matches!(task, Task::Save)) {
+            tasks.push(Task::Save);
+        }
+        tasks
+    }
+
+    fn handle_action(&mut self, action: Action, now: u64) -> Vec<Task> {
         match action {
-            Action::RunCommand { sender, command } => self.run_command(sender, command),
+            Action::RunCommand { sender, command } => {
+                let tasks = self.run_command(sender, command);
+                // commands can add or remove path watches
+                self.fetch_files
+                    .store(self.state.has_file_watches(), Ordering::Relaxed);
+                tasks
+            }
             Action::UnknownCommand { sender } => self
                 .formatter
                 .format_greeting()
@@ -244,28 +798,141 @@ where
                 .flatten()
                 .map(|message| Task::Reply(Response::new(sender.clone(), message)))
                 .collect(),
-            Action::CommentAdded(event) => self
-                .get_comment_messages(event)
-                .into_iter()
-                .map(|(email, message)| Task::Reply(Response::new(email, message)))
-                .collect(),
-            Action::ReviewerAdded(event) => self
-                .get_reviewer_added_msg(&event)
-                .map(|(user, message)| Task::Reply(Response::new(user.email().to_owned(), message)))
-                .into_iter()
-                .collect(),
-            Action::ChangeMerged(event) => self
-                .get_change_merged_messages(&event)
-                .into_iter()
-                .map(|(email, message)| Task::Reply(Response::new(email, message)))
-                .into_iter()
-                .collect(),
-            Action::ChangeAbandoned(event) => self
-                .get_change_abandoned_messages(&event)
-                .into_iter()
-                .map(|(email, message)| Task::Reply(Response::new(email, message)))
-                .into_iter()
-                .collect(),
+            Action::CommentAdded(event) => {
+                let comment_key = (event.change.number, event.created_on);
+                let watcher_messages = self.get_watcher_messages(&*event);
+                let mention_messages = self.get_mention_messages(&event);
+                let ci_result = if self.service_accounts.is_human(&event.author) {
+                    None
+                } else {
+                    self.ci_tracker.update(&event)
+                };
+                let mut messages = match ci_result {
+                    Some(result) => self.get_ci_result_messages(event, &result),
+                    None => self.get_comment_messages(event),
+                };
+                // users get only a single message per event
+                for (email, message) in mention_messages {
+                    if !messages.iter().any(|(other, _)| *other == email) {
+                        messages.push((email, message));
+                    }
+                }
+                let recipients = messages.iter().map(|(email, _)| email.clone()).collect();
+                self.comment_recipients.insert(comment_key, recipients);
+                into_reply_tasks(messages, watcher_messages)
+            }
+            Action::ReviewerAdded(event) => {
+                let watcher_messages = self.get_watcher_messages(&*event);
+                let messages = self
+                    .get_reviewer_added_msg(&event)
+                    .into_iter()
+                    .chain(self.get_reviewer_away_msg(&event, now))
+                    .collect();
+                let mut tasks = into_reply_tasks(messages, watcher_messages);
+                tasks.extend(self.get_team_reviewer_added_tasks(&event));
+                tasks
+            }
+            Action::VoteDeleted(event) => into_reply_tasks(
+                self.get_vote_deleted_msg(&event).into_iter().collect(),
+                Vec::new(),
+            ),
+            Action::ReviewerDeleted(event) => into_reply_tasks(
+                self.get_reviewer_deleted_msg(&event).into_iter().collect(),
+                Vec::new(),
+            ),
+            Action::ChangeMerged(event) => {
+                let watcher_messages = self.get_watcher_messages(&*event);
+                let messages = self.get_change_merged_messages(&event);
+                if self.rest_client.is_some()
+                    && self
+                        .state
+                        .users()
+                        .any(|user| user.has_flag(UserFlag::NotifyMergeConflicts))
+                {
+                    self.merge_checks.add(&event.change);
+                }
+                let expired = self.state.expire_change(event.change.number);
+                self.ci_tracker.forget_change(event.change.number);
+                let mut tasks = into_reply_tasks(messages, watcher_messages);
+                if expired {
+                    tasks.push(Task::Save);
+                }
+                tasks
+            }
+            Action::ChangeAbandoned(event) => {
+                let watcher_messages = self.get_watcher_messages(&*event);
+                let messages = self.get_change_abandoned_messages(&event);
+                let expired = self.state.expire_change(event.change.number);
+                self.ci_tracker.forget_change(event.change.number);
+                let mut tasks = into_reply_tasks(messages, watcher_messages);
+                if expired {
+                    tasks.push(Task::Save);
+                }
+                tasks
+            }
+            Action::PatchsetCreated(event) => {
+                let watcher_messages = self.get_watcher_messages(&*event);
+                let messages = self.get_foreign_upload_msg(&event).into_iter().collect();
+                into_reply_tasks(messages, watcher_messages)
+            }
+            Action::WipStateChanged(event) => self.get_ready_for_review_messages(&*event),
+            Action::PrivateStateChanged(event) => self.get_ready_for_review_messages(&*event),
+            Action::TopicChanged(event) => {
+                let messages =
+                    self.get_involved_user_messages(&*event, UserFlag::NotifyTopicChanged, &*event);
+                into_reply_tasks(messages, Vec::new())
+            }
+            Action::HashtagsChanged(event) => {
+                let messages = self.get_involved_user_messages(
+                    &*event,
+                    UserFlag::NotifyHashtagsChanged,
+                    &*event,
+                );
+                into_reply_tasks(messages, Vec::new())
+            }
+            Action::RefUpdated(event) => {
+                let messages = self.get_ref_watcher_messages(&event);
+                into_reply_tasks(Vec::new(), messages)
+            }
+            Action::RemindStaleChanges { changes, now } => self.get_reminder_tasks(&changes, now),
+            Action::ThreadReplies { event, comments } => {
+                let mut messages = self.get_thread_reply_messages(&event, &comments);
+                // users get only a single message per comment
+                if let Some(recipients) = self
+                    .comment_recipients
+                    .get(&(event.change.number, event.created_on))
+                {
+                    messages.retain(|(email, _)| !recipients.contains(email));
+                }
+                into_reply_tasks(messages, Vec::new())
+            }
+            Action::ChangeReverted {
+                revert,
+                reverted,
+                merged,
+            } => {
+                let messages = self.get_change_reverted_messages(&revert, &reverted, merged);
+                into_reply_tasks(messages, Vec::new())
+            }
+            Action::MergeabilityChecked { merged, changes } => {
+                let mut tasks = Vec::new();
+                let mut changed = false;
+                for (change, mergeable) in &changes {
+                    let was_mergeable = self.state.set_mergeable(change.number, *mergeable);
+                    changed |= was_mergeable != Some(*mergeable);
+                    // changes checked for the first time count as mergeable
+                    if was_mergeable != Some(false) && !mergeable {
+                        tasks
+                            .extend(self.get_merge_conflict_msg(change, &merged).map(
+                                |(email, message)| Task::Reply(Response::new(email, message)),
+                            ));
+                    }
+                }
+                if changed {
+                    tasks.push(Task::Save);
+                }
+                tasks
+            }
         }
     }
 
@@ -334,6 +1001,70 @@ where
                 "Your provided filter is invalid. Please double-check the regex you provided. Specifications of the regex are here: https://doc.rust-lang.org/regex/regex/index.html#syntax");
                 vec![Task::Reply(Response::new(sender, resp.to_string()))]
             }
+            Command::FilterSet {
+                name,
+                action,
+                regex,
+            } => match self.state.set_named_filter(&sender, &name, action, &regex) {
+                Ok(()) => vec![
+                    Task::Save,
+                    Task::Reply(Response::new(
+                        sender,
+                        format!("Got it! Filter `{}` ({}) is now enabled.", name, action),
+                    )),
+                ],
+                Err(_) => vec![Task::Reply(Response::new(
+                    sender,
+                    "Your provided filter is invalid. Please double-check the regex you provided. Specifications of the regex are here: https://doc.rust-lang.org/regex/regex/index.html#syntax".to_string(),
+                ))],
+            },
+            Command::FilterRemove(name) => {
+                let resp = if self.state.remove_filter(&sender, &name) {
+                    format!("Got it! Filter `{}` was removed.", name)
+                } else {
+                    format!("There is no filter named `{}`.", name)
+                };
+                vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+            }
+            Command::FilterSetEnabled { name, enabled } => {
+                if self.state.enable_filter(&sender, &name, enabled) {
+                    let resp = format!(
+                        "Got it! Filter `{}` is now {}.",
+                        name,
+                        if enabled { "enabled" } else { "disabled" }
+                    );
+                    vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+                } else {
+                    vec![Task::Reply(Response::new(
+                        sender,
+                        format!("There is no filter named `{}`.", name),
+                    ))]
+                }
+            }
+            Command::FilterList => {
+                let filters = self.state.get_filters(&sender);
+                let resp = if filters.is_empty() {
+                    "No filter is configured for you.".to_string()
+                } else {
+                    let lines: Vec<_> = filters
+                        .iter()
+                        .map(|f| {
+                            format!(
+                                "* `{}` ({}{}): `{}`",
+                                f.name,
+                                f.action,
+                                if f.enabled { "" } else { ", disabled" },
+                                f.regex.as_str()
+                            )
+                        })
+                        .collect();
+                    format!(
+                        "The following filters are configured for you. Allow filters take precedence over deny filters.\n{}",
+                        lines.join("\n")
+                    )
+                };
+                vec![Task::Reply(Response::new(sender, resp))]
+            }
             Command::FilterEnable(enable) => {
                 let resp = self.state.enable_and_get_filter(&sender, enable).map(
                 |filter|
@@ -355,6 +1086,136 @@ where
 
                 vec![Task::Save, Task::Reply(Response::new(sender, resp))]
             }
+            Command::Mute(rule) => self.add_mute(sender, rule),
+            Command::MuteWhen(rule) => match rule.parse() {
+                Ok(rule) => self.add_mute(sender, rule),
+                Err(e) => vec![Task::Reply(Response::new(
+                    sender,
+                    format!("Sorry, I could not understand your mute rule: {}.", e),
+                ))],
+            },
+            Command::Unmute(rule) => {
+                let removed = self.state.remove_mutes(&sender, rule.as_ref());
+                let resp = match rule {
+                    _ if removed == 0 => "There was nothing to unmute.".to_string(),
+                    Some(rule) => format!("Got it! Events matching `{}` are no longer muted.", rule),
+                    None => "Got it! Nothing is muted anymore.".to_string(),
+                };
+                vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+            }
+            Command::Watch(watch) => self.add_watch(sender, watch),
+            Command::WatchQuery(query) => match query.parse() {
+                Ok(parsed) => self.add_watch(sender, state::Watch::Query(parsed)),
+                Err(e @ query::ParseError { .. }) => {
+                    // point at the failing position below the query
+                    let resp = format!(
+                        "Sorry, I could not understand your query: {}.\n\n```\n{}\n{:>width$}\n```",
+                        e.message,
+                        query.trim(),
+                        "^",
+                        width = e.position
+                    );
+                    vec![Task::Reply(Response::new(sender, resp))]
+                }
+            },
+            Command::Unwatch(watch) => {
+                let removed = self.state.remove_watches(&sender, watch.as_ref());
+                let resp = match watch {
+                    _ if removed == 0 => "There was nothing to unwatch.".to_string(),
+                    Some(watch) => format!("Got it! You are no longer watching `{}`.", watch),
+                    None => "Got it! You are no longer watching anything.".to_string(),
+                };
+                vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+            }
+            Command::SetAccountPreference(account, preference) => {
+                let changed = self
+                    .state
+                    .set_account_preference(&sender, &account, preference);
+                let resp = match preference {
+                    Some(AccountPreference::Ignore) if changed => {
+                        format!("Got it! I will ignore events caused by `{}`.", account)
+                    }
+                    Some(AccountPreference::AlwaysNotify) if changed => format!(
+                        "Got it! I will always notify you about review comments and votes of `{}`.",
+                        account
+                    ),
+                    Some(preference) => {
+                        format!("`{}` is already set to {} for you.", account, preference)
+                    }
+                    None if changed => format!(
+                        "Got it! Events caused by `{}` are handled normally again.",
+                        account
+                    ),
+                    None => format!("There is nothing configured for `{}`.", account),
+                };
+                vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+            }
+            Command::SetMaxInlineComments(max) => {
+                let changed = self.state.set_max_inline_comments(&sender, max);
+                let resp = match max {
+                    Some(max) if changed => format!(
+                        "Got it! I will show at most {} inline comments per review.",
+                        max
+                    ),
+                    None if changed => "Got it! I will show all inline comments.".to_string(),
+                    _ => "Nothing changed.".to_string(),
+                };
+                vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+            }
+            Command::OutOfOffice {
+                until,
+                note,
+                summarize,
+            } => match until.parse::<Date>() {
+                Ok(until) if until.timestamp() <= unix_now() => vec![Task::Reply(Response::new(
+                    sender,
+                    format!("Sorry, {} is not in the future.", until),
+                ))],
+                Ok(until) => {
+                    let mut away = Away::new(until, note, summarize);
+                    // keep what was missed so far when the status is updated
+                    if let Some(previous) = self.state.find_user(&sender).and_then(User::away) {
+                        away.missed = previous.missed.clone();
+                    }
+                    self.state.set_away(&sender, Some(away));
+                    let resp = format!(
+                        "Got it! Your notifications are paused until {}.{}",
+                        until,
+                        if summarize {
+                            " I will summarize what you missed when you are back."
+                        } else {
+                            ""
+                        }
+                    );
+                    vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+                }
+                Err(e) => vec![Task::Reply(Response::new(
+                    sender,
+                    format!("Sorry, I could not understand the date: {}.", e),
+                ))],
+            },
+            Command::Back => match self.state.set_away(&sender, None) {
+                Some(away) => {
+                    let summary = if away.summarize {
+                        self.get_away_summary(sender.clone(), &away)
+                    } else {
+                        None
+                    };
+                    let mut tasks = vec![
+                        Task::Save,
+                        Task::Reply(Response::new(
+                            sender,
+                            "Got it! Your notifications are resumed.",
+                        )),
+                    ];
+                    tasks.extend(summary);
+                    tasks
+                }
+                None => vec![Task::Reply(Response::new(
+                    sender,
+                    "You are not out of office.",
+                ))],
+            },
             Command::SetFlag(flag, enable) => {
                 self.state.set_flag(&sender, flag, enable);
                 vec![
@@ -372,21 +1233,109 @@ where
         }
     }
 
-    fn handle_task(&mut self, task: Task) -> Option<Response> {
+    fn add_watch(&mut self, sender: spark::Email, watch: state::Watch) -> Vec<Task> {
+        let resp = if self.state.add_watch(&sender, watch.clone()) {
+            format!("Got it! You are now watching `{}`.", watch)
+        } else {
+            format!("You are already watching `{}`.", watch)
+        };
+        vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+    }
+
+    fn add_mute(&mut self, sender: spark::Email, rule: state::MuteRule) -> Vec<Task> {
+        let resp = if self.state.add_mute(&sender, rule.clone()) {
+            format!("Got it! Events matching `{}` are muted now.", rule)
+        } else {
+            format!("Events matching `{}` are already muted.", rule)
+        };
+        vec![Task::Save, Task::Reply(Response::new(sender, resp))]
+    }
+
+    /// Hold back notifications of users that are out of office. They are kept
+    /// for the summary if the user asked for one.
+    fn pause_away_notifications(&mut self, tasks: Vec<Task>, now: u64) -> Vec<Task> {
+        let mut kept = false;
+        let mut result = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            match task {
+                Task::Reply(response) if matches!(self.state.find_user(&response.email), Some(user) if user.is_away(now)) =>
+                {
+                    debug!("Pausing notification of {}.", response.email);
+                    kept |= self.state.add_missed(&response.email, response.message);
+                }
+                task => result.push(task),
+            }
+        }
+        if kept {
+            result.push(Task::Save);
+        }
+        result
+    }
+
+    /// End the out of office status of users that are back and send them the
+    /// summary of what they missed.
+    fn end_expired_away(&mut self, now: u64) -> Vec<Task> {
+        let returned = self.state.end_expired_away(now);
+        if returned.is_empty() {
+            return Vec::new();
+        }
+        let mut tasks: Vec<Task> = returned
+            .into_iter()
+            .filter(|(_, away)| away.summarize)
+            .filter_map(|(email, away)| self.get_away_summary(email, &away))
+            .collect();
+        tasks.push(Task::Save);
+        tasks
+    }
+
+    fn get_away_summary(&self, email: spark::Email, away: &Away) -> Option<Task> {
+        self.formatter
+            .format_message(None, AwaySummaryMessage::new(away))
+            .map_err(|e| error!("formatting away summary failed: {}", e))
+            .ok()
+            .and_then(identity)
+            .map(|message| Task::Reply(Response::new(email, message)))
+    }
+
+    fn handle_task(&mut self, task: Task) -> Vec<outbox::Entry> {
         debug!("New task {:#?}", task);
         match task {
-            Task::Reply(response) => Some(response),
+            Task::Reply(response) => {
+                self.push_message(outbox::Recipient::Person(response.email), &response.message)
+            }
+            Task::RoomReply(response) => {
+                self.push_message(outbox::Recipient::Room(response.room_id), &response.message)
+            }
             Task::Save => {
                 self.save("state.json")
                     .map_err(|err| {
                         error!("Could not save state: {:?}", err);
                     })
                     .ok();
-                None
+                Vec::new()
             }
         }
     }
 
+    /// Add a message to the outbox, split into parts if it is too long for
+    /// Webex Teams.
+    fn push_message(&mut self, recipient: outbox::Recipient, message: &str) -> Vec<outbox::Entry> {
+        let parts = split::split_message(message, split::MAX_MESSAGE_LENGTH);
+        if parts.len() > 1 {
+            debug!(
+                "Splitting message of {} bytes to {} into {} parts",
+                message.len(),
+                recipient,
+                parts.len()
+            );
+        }
+        let now = unix_now();
+        parts
+            .into_iter()
+            .map(|part| self.outbox.push(recipient.clone(), part, now))
+            .collect()
+    }
+
     /// Return iterator of users which might be interested in an event.
     fn interested_users<'bot, 'event, 'result>(
         &'bot self,
@@ -413,24 +1362,161 @@ where
             .flatten()
             .filter_map(|approval| approval.by.as_ref())
             .chain(std::iter::once(&change.owner))
-            .filter(|user| user.is_human())
+            .filter(move |user| self.service_accounts.is_human(user))
             .filter_map(|user| user.spark_email())
             .filter_map(move |email| self.state.find_user_by_email(email))
     }
 
+    /// Format messages for users watching the change, which can be independent
+    /// of the user's involvement. The user that caused the event is skipped.
+    fn get_watcher_messages<E: gerrit::ChangeEvent + Serialize>(
+        &self,
+        event: &E,
+    ) -> Vec<(spark::Email, String)> {
+        // watchers might not be able to see private changes
+        if event.change().private {
+            return Vec::new();
+        }
+        self.state
+            .watching_users(event)
+            .filter(|(user, _)| event.actor().spark_email() != Some(user.email()))
+            .filter_map(|(user, watch)| Some((user.for_actor(event.actor())?, watch)))
+            .filter(|(user, _)| !self.state.is_muted(user, event))
+            .filter_map(|(user, watch)| {
+                self.formatter
+                    .format_message(
+                        Some(&user),
+                        WatchedEvent::new(
+                            event.event_type(),
+                            event,
+                            watch,
+                            watch
+                                .matching_files(event)
+                                .into_iter()
+                                .map(String::from)
+                                .collect(),
+                        ),
+                    )
+                    .map_err(|e| error!("message formatting failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            })
+            .collect()
+    }
+
+    /// Registered human owner and reviewers of a change, which are only known
+    /// if the change was queried with all reviewers.
+    fn involved_users<'a>(&'a self, change: &'a gerrit::Change) -> Vec<&'a User> {
+        let mut users: Vec<&User> = Vec::new();
+        let accounts = std::iter::once(&change.owner).chain(change.all_reviewers.iter().flatten());
+        for account in accounts.filter(|account| self.service_accounts.is_human(account)) {
+            if let Some(user) = account
+                .spark_email()
+                .and_then(|email| self.state.find_user_by_email(email))
+            {
+                if !users.iter().any(|other| other.email() == user.email()) {
+                    users.push(user);
+                }
+            }
+        }
+        users
+    }
+
+    /// Format a message for the owner and reviewers of the change of an event
+    /// with the given flag. The user that caused the event is skipped.
+    fn get_involved_user_messages<E, I>(
+        &self,
+        event: &E,
+        flag: UserFlag,
+        input: I,
+    ) -> Vec<(spark::Email, String)>
+    where
+        E: gerrit::ChangeEvent + ?Sized,
+        I: MessageInput + Copy,
+    {
+        let actor = event.actor();
+        self.involved_users(event.change())
+            .into_iter()
+            .filter(|user| actor.spark_email() != Some(user.email()))
+            .filter_map(|user| user.for_actor(actor))
+            .filter(|user| user.has_flag(flag))
+            .filter(|user| !self.state.is_muted(user, event))
+            .filter_map(|user| {
+                self.formatter
+                    .format_message(Some(&user), input)
+                    .map_err(|e| error!("message formatting failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            })
+            .collect()
+    }
+
+    /// Tell the reviewers of a change that it is ready for review, unless it is
+    /// still work in progress or private.
+    fn get_ready_for_review_messages<E: gerrit::ChangeEvent + ?Sized>(
+        &self,
+        event: &E,
+    ) -> Vec<Task> {
+        let change = event.change();
+        if change.wip || change.private {
+            return Vec::new();
+        }
+        let messages = self
+            .get_involved_user_messages(
+                event,
+                UserFlag::NotifyReadyForReview,
+                ReadyForReviewMessage::new(change, event.actor()),
+            )
+            .into_iter()
+            .filter(|(email, _)| change.owner.spark_email() != Some(email))
+            .collect();
+        into_reply_tasks(messages, Vec::new())
+    }
+
+    fn get_ref_watcher_messages(
+        &self,
+        event: &gerrit::RefUpdatedEvent,
+    ) -> Vec<(spark::Email, String)> {
+        self.state
+            .ref_watching_users(&event.ref_update)
+            .filter(|(user, _)| {
+                event.submitter.as_ref().and_then(|s| s.spark_email()) != Some(user.email())
+            })
+            .filter_map(|(user, watch)| {
+                let user = match &event.submitter {
+                    Some(submitter) => user.for_actor(submitter)?,
+                    None => Cow::Borrowed(user),
+                };
+                self.formatter
+                    .format_message(Some(&user), RefUpdatedMessage::new(event, watch))
+                    .map_err(|e| error!("message formatting failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            })
+            .collect()
+    }
+
     fn get_comment_response_messages(
         &self,
         event: Box<gerrit::CommentAddedEvent>,
     ) -> Vec<(spark::Email, String)> {
         self.interested_users(&event.change, &event.patchset)
             .filter(|user| Some(user.email()) != event.author.spark_email())
+            .filter_map(|user| user.for_actor(&event.author))
             .filter(|user| user.has_flag(UserFlag::NotifyReviewResponses))
+            .filter(|user| !self.state.is_muted(user, &*event))
             .filter_map(|user| {
                 self.formatter
-                    .format_message(Some(user), &*event)
+                    .format_message(Some(&user), &*event)
                     .map_err(|e| error!("message formatting failed: {}", e))
                     .unwrap_or(None)
-                    .filter(|message| !self.state.is_filtered(user, &message))
+                    .filter(|message| !self.state.is_filtered(&user, &message))
                     .map(|message| (user.email().to_owned(), message))
             })
             .collect()
@@ -447,23 +1533,25 @@ where
         let user = self
             .state
             .find_user_by_email(owner_email)
-            .filter(|user| user.has_any_flag(REVIEW_COMMENT_FLAGS))?;
+            .and_then(|user| user.for_actor(&event.author))
+            .filter(|user| user.has_any_flag(REVIEW_COMMENT_FLAGS))
+            .filter(|user| !self.state.is_muted(user, &*event))?;
 
         // filter all messages that were already sent to the user recently
-        if !approvals.is_empty() && self.rate_limiter.limit(user, &*event) {
+        if !approvals.is_empty() && self.rate_limiter.limit(&user, &*event) {
             debug!("Filtered approval due to cache hit.");
             return None;
         }
 
         self.formatter
-            .format_message(Some(user), &*event)
+            .format_message(Some(&user), &*event)
             .unwrap_or_else(|e| {
                 error!("message formatting failed: {}", e);
                 None
             })
             .filter(|msg| {
                 // if user has configured and enabled a filter try to apply it
-                !self.state.is_filtered(user, &msg)
+                !self.state.is_filtered(&user, &msg)
             })
             .map(|m| (owner_email.to_owned(), m))
     }
@@ -483,29 +1571,552 @@ where
         }
     }
 
+    /// Send the compact CI message to the change owner if they enabled it,
+    /// otherwise fall back to the regular comment messages.
+    fn get_ci_result_messages(
+        &mut self,
+        event: Box<gerrit::CommentAddedEvent>,
+        result: &ci::CiResult,
+    ) -> Vec<(spark::Email, String)> {
+        let message = event
+            .change
+            .owner
+            .spark_email()
+            .and_then(|email| self.state.find_user_by_email(email))
+            .and_then(|user| user.for_actor(&event.author))
+            .filter(|user| user.has_flag(UserFlag::NotifyCiResults))
+            .map(|user| {
+                if self.state.is_muted(&user, &*event) {
+                    return None;
+                }
+                self.formatter
+                    .format_message(Some(&user), CiResultMessage::new(&event, result))
+                    .map_err(|e| error!("message formatting failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            });
+
+        match message {
+            Some(message) => message.into_iter().collect(),
+            None => self.get_comment_messages(event),
+        }
+    }
+
+    /// Remind reviewers (and optionally owners) of changes without activity
+    /// and escalate changes which are stale for too long.
+    fn get_reminder_tasks(&mut self, changes: &[gerrit::Change], now: u64) -> Vec<Task> {
+        let config = match self.reminders {
+            Some(ref config) => config,
+            None => return Vec::new(),
+        };
+        let interval = u64::from(config.repeat_days) * SECONDS_PER_DAY;
+        let mut tasks = Vec::new();
+        let mut stale_changes = Vec::new();
+        let mut reminded = false;
+
+        for change in changes {
+            let idle_days = match change.last_updated {
+                Some(last_updated) => reminders::working_days_between(last_updated, now),
+                None => continue,
+            };
+
+            if idle_days < config.after_days {
+                continue;
+            }
+
+            stale_changes.push(change.number);
+
+            // only reviewers who haven't voted on the current patchset yet
+            let reviewers: Vec<&gerrit::User> = change
+                .all_reviewers
+                .iter()
+                .flatten()
+                .filter(|reviewer| reviewer.email != change.owner.email)
+                .filter(|reviewer| self.service_accounts.is_human(reviewer))
+                .filter(|reviewer| !has_current_vote(change, reviewer))
+                .collect();
+
+            // members of teams are reminded in the room of their team instead
+            let mut individual_reviewers = Vec::new();
+            let mut team_rooms = Vec::new();
+            for reviewer in &reviewers {
+                let teams = if change.private {
+                    Vec::new()
+                } else {
+                    self.teams.teams_of(reviewer)
+                };
+                if teams.is_empty() {
+                    individual_reviewers.push(*reviewer);
+                }
+                for team in teams {
+                    if !team_rooms.contains(&team) {
+                        team_rooms.push(team);
+                    }
+                }
+            }
+
+            let recipients = individual_reviewers
+                .iter()
+                .map(|reviewer| (*reviewer, ReminderRole::Reviewer))
+                .chain(Some((&change.owner, ReminderRole::Owner)).filter(|_| config.notify_owner));
+
+            for (account, role) in recipients {
+                let user = match account
+                    .spark_email()
+                    .and_then(|email| self.state.find_user_by_email(email))
+                    .filter(|user| user.has_any_flag(NOTIFICATION_FLAGS))
+                    .filter(|user| !self.state.is_change_muted(user, change))
+                {
+                    Some(user) => user,
+                    None => continue,
+                };
+
+                let email = user.email().to_owned();
+
+                if !self
+                    .state
+                    .reminder_due(change.number, email.as_str(), now, interval)
+                {
+                    continue;
+                }
+
+                let message = self
+                    .formatter
+                    .format_message(
+                        Some(user),
+                        ReminderMessage::new(change, role, idle_days, &reviewers),
+                    )
+                    .map_err(|e| error!("formatting reminder failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(user, &message));
+
+                if let Some(message) = message {
+                    self.state.set_reminded(change.number, email.as_str(), now);
+                    tasks.push(Task::Reply(Response::new(email, message)));
+                    reminded = true;
+                }
+            }
+
+            for (team, room) in team_rooms {
+                let recipient = reminders::team_recipient(team);
+                if !self
+                    .state
+                    .reminder_due(change.number, &recipient, now, interval)
+                {
+                    continue;
+                }
+
+                if let Some(message) = self
+                    .formatter
+                    .format_message(
+                        None,
+                        ReminderMessage::for_team(change, team, idle_days, &reviewers),
+                    )
+                    .map_err(|e| error!("formatting team reminder failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                {
+                    self.state.set_reminded(change.number, &recipient, now);
+                    tasks.push(Task::RoomReply(RoomResponse::new(room.to_owned(), message)));
+                    reminded = true;
+                }
+            }
+
+            let escalation_room = match (config.escalate_after_days, &config.escalation_room) {
+                (Some(days), Some(room)) if idle_days >= days && !change.private => room,
+                _ => continue,
+            };
+
+            if !self
+                .state
+                .reminder_due(change.number, ESCALATION_RECIPIENT, now, interval)
+            {
+                continue;
+            }
+
+            if let Some(message) = self
+                .formatter
+                .format_message(
+                    None,
+                    ReminderMessage::new(change, ReminderRole::Escalation, idle_days, &reviewers),
+                )
+                .map_err(|e| error!("formatting escalation failed: {}", e))
+                .ok()
+                .and_then(identity)
+            {
+                self.state
+                    .set_reminded(change.number, ESCALATION_RECIPIENT, now);
+                tasks.push(Task::RoomReply(RoomResponse::new(
+                    spark::RoomId::new(escalation_room.clone()),
+                    message,
+                )));
+                reminded = true;
+            }
+        }
+
+        if self.state.retain_reminders(&stale_changes) || reminded {
+            tasks.push(Task::Save);
+        }
+
+        tasks
+    }
+
     fn get_reviewer_added_msg(
         &mut self,
         event: &gerrit::ReviewerAddedEvent,
-    ) -> Option<(&User, String)> {
+    ) -> Option<(spark::Email, String)> {
+        // members of teams are notified in the room of their team instead,
+        // unless the change is private
+        if !event.change.private && !self.teams.teams_of(&event.reviewer).is_empty() {
+            return None;
+        }
         let reviewer_email = spark::EmailRef::new(event.reviewer.email.as_ref()?);
+        // the event doesn't tell who added the reviewer, so the change owner
+        // is considered to be the cause
         let user = self
             .state
             .find_user_by_email(reviewer_email)
-            .filter(|user| user.has_flag(UserFlag::NotifyReviewerAdded))?;
+            .and_then(|user| user.for_actor(&event.change.owner))
+            .filter(|user| user.has_flag(UserFlag::NotifyReviewerAdded))
+            .filter(|user| !self.state.is_muted(user, event))?;
 
         // filter all messages that were already sent to the user recently
-        if self.rate_limiter.limit(user, event) {
+        if self.rate_limiter.limit(&user, event) {
             debug!("Filtered reviewer-added due to cache hit.");
             return None;
         }
 
         let message = self
             .formatter
-            .format_message(Some(user), event)
+            .format_message(Some(&user), event)
             .map_err(|e| error!("formatting reviewer added failed: {}", e))
             .ok()??;
 
-        Some((user, message))
+        Some((user.email().to_owned(), message))
+    }
+
+    /// Tell the owner of a change that votes were removed from it.
+    fn get_vote_deleted_msg(
+        &mut self,
+        event: &gerrit::VoteDeletedEvent,
+    ) -> Option<(spark::Email, String)> {
+        let owner_email = event.change.owner.spark_email()?;
+        if event.remover.spark_email() == Some(owner_email) {
+            return None;
+        }
+        let user = self
+            .state
+            .find_user_by_email(owner_email)
+            .and_then(|user| user.for_actor(&event.remover))
+            .filter(|user| user.has_flag(UserFlag::NotifyVoteDeleted))
+            .filter(|user| !self.state.is_muted(user, event))?;
+
+        // filter all messages that were already sent to the user recently
+        if self.rate_limiter.limit(&user, event) {
+            debug!("Filtered vote-deleted due to cache hit.");
+            return None;
+        }
+
+        self.formatter
+            .format_message(Some(&user), event)
+            .map_err(|e| error!("formatting vote deleted failed: {}", e))
+            .ok()?
+            .filter(|message| !self.state.is_filtered(&user, &message))
+            .map(|message| (user.email().to_owned(), message))
+    }
+
+    /// Tell a reviewer that they were removed from a change, unless they did
+    /// it themselves.
+    fn get_reviewer_deleted_msg(
+        &mut self,
+        event: &gerrit::ReviewerDeletedEvent,
+    ) -> Option<(spark::Email, String)> {
+        let reviewer_email = event.reviewer.spark_email()?;
+        let remover = gerrit::ChangeEvent::actor(event);
+        if remover.spark_email() == Some(reviewer_email) {
+            return None;
+        }
+        let user = self
+            .state
+            .find_user_by_email(reviewer_email)
+            .and_then(|user| user.for_actor(remover))
+            .filter(|user| user.has_flag(UserFlag::NotifyReviewerDeleted))
+            .filter(|user| !self.state.is_muted(user, event))?;
+
+        // filter all messages that were already sent to the user recently
+        if self.rate_limiter.limit(&user, event) {
+            debug!("Filtered reviewer-deleted due to cache hit.");
+            return None;
+        }
+
+        self.formatter
+            .format_message(Some(&user), event)
+            .map_err(|e| error!("formatting reviewer deleted failed: {}", e))
+            .ok()?
+            .filter(|message| !self.state.is_filtered(&user, &message))
+            .map(|message| (user.email().to_owned(), message))
+    }
+
+    /// Announce a review requested from a member of a team in the room of the
+    /// team, once per change and team.
+    fn get_team_reviewer_added_tasks(&mut self, event: &gerrit::ReviewerAddedEvent) -> Vec<Task> {
+        let mut tasks = Vec::new();
+        if event.change.private {
+            return tasks;
+        }
+        for (team, room) in self.teams.teams_of(&event.reviewer) {
+            let key = (event.change.number, team.to_string());
+            if self.team_announcements.insert(key, ()).is_some() {
+                debug!(
+                    "Filtered reviewer-added for team {} due to cache hit.",
+                    team
+                );
+                continue;
+            }
+            if let Some(message) = self
+                .formatter
+                .format_message(None, TeamReviewerAddedMessage::new(event, team))
+                .map_err(|e| error!("formatting team reviewer added failed: {}", e))
+                .ok()
+                .and_then(identity)
+            {
+                tasks.push(Task::RoomReply(RoomResponse::new(room.to_owned(), message)));
+            }
+        }
+        tasks
+    }
+
+    /// Format messages for the earlier participants of the inline comment
+    /// threads replied to in a comment. The change owner is skipped, as they
+    /// get the inline comments with the comment itself.
+    fn get_thread_reply_messages(
+        &self,
+        event: &gerrit::CommentAddedEvent,
+        comments: &[gerrit::CommentInfo],
+    ) -> Vec<(spark::Email, String)> {
+        let replies = threads::find_replies(comments, &event.author, event.created_on.into());
+
+        let mut recipients: Vec<(&User, Vec<&threads::ThreadReply>)> = Vec::new();
+        for reply in &replies {
+            for participant in &reply.participants {
+                let user = match participant.spark_email() {
+                    Some(email) if event.change.owner.spark_email() != Some(email) => {
+                        match self.state.find_user_by_email(email) {
+                            Some(user) => user,
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                match recipients
+                    .iter_mut()
+                    .find(|(u, _)| u.email() == user.email())
+                {
+                    Some((_, user_replies)) => user_replies.push(reply),
+                    None => recipients.push((user, vec![reply])),
+                }
+            }
+        }
+
+        recipients
+            .into_iter()
+            .filter_map(|(user, replies)| Some((user.for_actor(&event.author)?, replies)))
+            .filter(|(user, _)| user.has_flag(UserFlag::NotifyThreadReplies))
+            .filter(|(user, _)| !self.state.is_muted(user, event))
+            .filter_map(|(user, replies)| {
+                self.formatter
+                    .format_message(Some(&user), ThreadRepliesMessage::new(event, replies))
+                    .map_err(|e| error!("formatting thread replies failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            })
+            .collect()
+    }
+
+    /// Format messages for users mentioned with `@` in a comment, regardless of
+    /// their involvement in the change. The author of the comment is skipped.
+    fn get_mention_messages(
+        &self,
+        event: &gerrit::CommentAddedEvent,
+    ) -> Vec<(spark::Email, String)> {
+        // mentioned users might not be able to see private changes
+        if event.change.private {
+            return Vec::new();
+        }
+        let mut mentioned: Vec<(&User, Vec<&str>)> = Vec::new();
+        for text in mentions::comment_texts(event) {
+            for account in mentions::extract_mentions(text) {
+                let user = match self.state.find_user_by_account(account) {
+                    Some(user) if event.author.spark_email() != Some(user.email()) => user,
+                    _ => continue,
+                };
+                match mentioned
+                    .iter_mut()
+                    .find(|(u, _)| u.email() == user.email())
+                {
+                    Some((_, texts)) if !texts.contains(&text) => texts.push(text),
+                    Some(_) => (),
+                    None => mentioned.push((user, vec![text])),
+                }
+            }
+        }
+
+        mentioned
+            .into_iter()
+            .filter_map(|(user, texts)| Some((user.for_actor(&event.author)?, texts)))
+            .filter(|(user, _)| user.has_flag(UserFlag::NotifyMentions))
+            .filter(|(user, _)| !self.state.is_muted(user, event))
+            .filter_map(|(user, texts)| {
+                self.formatter
+                    .format_message(Some(&user), MentionMessage::new(event, texts))
+                    .map_err(|e| error!("formatting mention failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            })
+            .collect()
+    }
+
+    /// Notify the owner of a change about a patchset uploaded by somebody else.
+    fn get_foreign_upload_msg(
+        &self,
+        event: &gerrit::PatchsetCreatedEvent,
+    ) -> Option<(spark::Email, String)> {
+        let uploader = &event.patchset.uploader;
+        let owner = &event.change.owner;
+        let uploaded_by_owner = match (&uploader.email, &owner.email) {
+            (Some(uploader), Some(owner)) => uploader.eq_ignore_ascii_case(owner),
+            _ => uploader.username == owner.username,
+        };
+        if uploaded_by_owner {
+            return None;
+        }
+
+        let user = self
+            .state
+            .find_user_by_email(owner.spark_email()?)
+            .and_then(|user| user.for_actor(uploader))
+            .filter(|user| user.has_flag(UserFlag::NotifyForeignUpload))
+            .filter(|user| !self.state.is_muted(user, event))?;
+
+        let message = self
+            .formatter
+            .format_message(Some(&user), event)
+            .map_err(|e| error!("formatting foreign upload failed: {}", e))
+            .ok()??;
+        if self.state.is_filtered(&user, &message) {
+            return None;
+        }
+
+        Some((user.email().to_owned(), message))
+    }
+
+    /// Tell the owner of a change that it cannot be merged anymore.
+    fn get_merge_conflict_msg(
+        &self,
+        change: &gerrit::Change,
+        merged: &[gerrit::Change],
+    ) -> Option<(spark::Email, String)> {
+        let user = self.state.find_user_by_email(change.owner.spark_email()?)?;
+        // any of the merged changes might cause the conflict
+        let user = merged
+            .iter()
+            .find_map(|merged| user.for_actor(&merged.owner))
+            .filter(|user| user.has_flag(UserFlag::NotifyMergeConflicts))
+            .filter(|user| !self.state.is_change_muted(user, change))?;
+
+        let message = self
+            .formatter
+            .format_message(Some(&user), MergeConflictMessage::new(change, merged))
+            .map_err(|e| error!("formatting merge conflict failed: {}", e))
+            .ok()??;
+        if self.state.is_filtered(&user, &message) {
+            return None;
+        }
+
+        Some((user.email().to_owned(), message))
+    }
+
+    /// Tell the owner of a change that the added reviewer is out of office.
+    fn get_reviewer_away_msg(
+        &self,
+        event: &gerrit::ReviewerAddedEvent,
+        now: u64,
+    ) -> Option<(spark::Email, String)> {
+        let reviewer_email = spark::EmailRef::new(event.reviewer.email.as_ref()?);
+        let away = self
+            .state
+            .find_user_by_email(reviewer_email)?
+            .away()
+            .filter(|away| away.is_active(now))?;
+        let owner = event
+            .change
+            .owner
+            .spark_email()
+            .filter(|email| *email != reviewer_email)
+            .and_then(|email| self.state.find_user_by_email(email))
+            .and_then(|user| user.for_actor(&event.reviewer))
+            .filter(|user| user.has_any_flag(NOTIFICATION_FLAGS))
+            .filter(|user| !self.state.is_muted(user, event))?;
+
+        let message = self
+            .formatter
+            .format_message(Some(&owner), ReviewerAwayMessage::new(event, away))
+            .map_err(|e| error!("formatting reviewer away failed: {}", e))
+            .ok()?
+            .filter(|message| !self.state.is_filtered(&owner, message))?;
+
+        Some((owner.email().to_owned(), message))
+    }
+
+    /// Format messages for the owner and reviewers of a reverted change.
+    fn get_change_reverted_messages(
+        &self,
+        revert: &gerrit::Change,
+        reverted: &gerrit::Change,
+        merged: bool,
+    ) -> Vec<(spark::Email, String)> {
+        // the reviewers of the reverted change might not see the private revert
+        if revert.private {
+            return Vec::new();
+        }
+        let mut emails: Vec<&spark::EmailRef> = Vec::new();
+        for user in std::iter::once(&reverted.owner).chain(reverted.all_reviewers.iter().flatten())
+        {
+            match user.spark_email() {
+                Some(email)
+                    if self.service_accounts.is_human(user)
+                        && Some(email) != revert.owner.spark_email()
+                        && !emails.contains(&email) =>
+                {
+                    emails.push(email)
+                }
+                _ => (),
+            }
+        }
+
+        emails
+            .into_iter()
+            .filter_map(|email| self.state.find_user_by_email(email))
+            .filter_map(|user| user.for_actor(&revert.owner))
+            .filter(|user| user.has_flag(UserFlag::NotifyReverts))
+            .filter(|user| !self.state.is_change_muted(user, reverted))
+            .filter_map(|user| {
+                self.formatter
+                    .format_message(Some(&user), RevertMessage::new(reverted, revert, merged))
+                    .map_err(|e| error!("formatting revert failed: {}", e))
+                    .ok()
+                    .and_then(identity)
+                    .filter(|message| !self.state.is_filtered(&user, &message))
+                    .map(|message| (user.email().to_owned(), message))
+            })
+            .collect()
     }
 
     fn get_change_merged_messages(
@@ -514,14 +2125,16 @@ where
     ) -> Vec<(spark::Email, String)> {
         self.interested_users(&event.change, &event.patchset)
             .filter(|user| event.submitter.spark_email() != Some(user.email()))
+            .filter_map(|user| user.for_actor(&event.submitter))
             .filter(|user| user.has_flag(UserFlag::NotifyChangeMerged))
+            .filter(|user| !self.state.is_muted(user, event))
             .filter_map(|user| {
                 self.formatter
-                    .format_message(Some(user), event)
+                    .format_message(Some(&user), event)
                     .map_err(|e| error!("message formatting failed: {}", e))
                     .ok()
                     .and_then(identity)
-                    .filter(|message| !self.state.is_filtered(user, &message))
+                    .filter(|message| !self.state.is_filtered(&user, &message))
                     .map(|message| (user.email().to_owned(), message))
             })
             .collect()
@@ -533,14 +2146,16 @@ where
     ) -> Vec<(spark::Email, String)> {
         self.interested_users(&event.change, &event.patchset)
             .filter(|user| event.abandoner.spark_email() != Some(user.email()))
+            .filter_map(|user| user.for_actor(&event.abandoner))
             .filter(|user| user.has_flag(UserFlag::NotifyChangeAbandoned))
+            .filter(|user| !self.state.is_muted(user, event))
             .filter_map(|user| {
                 self.formatter
-                    .format_message(Some(user), event)
+                    .format_message(Some(&user), event)
                     .map_err(|e| error!("message formatting failed: {}", e))
                     .ok()
                     .and_then(identity)
-                    .filter(|message| !self.state.is_filtered(user, &message))
+                    .filter(|message| !self.state.is_filtered(&user, &message))
                     .map(|message| (user.email().to_owned(), message))
             })
             .collect()
@@ -580,8 +2195,76 @@ enum Action {
     },
     CommentAdded(Box<gerrit::CommentAddedEvent>),
     ReviewerAdded(Box<gerrit::ReviewerAddedEvent>),
+    VoteDeleted(Box<gerrit::VoteDeletedEvent>),
+    ReviewerDeleted(Box<gerrit::ReviewerDeletedEvent>),
     ChangeMerged(Box<gerrit::ChangeMergedEvent>),
     ChangeAbandoned(Box<gerrit::ChangeAbandonedEvent>),
+    PatchsetCreated(Box<gerrit::PatchsetCreatedEvent>),
+    RefUpdated(Box<gerrit::RefUpdatedEvent>),
+    WipStateChanged(Box<gerrit::WipStateChangedEvent>),
+    PrivateStateChanged(Box<gerrit::PrivateStateChangedEvent>),
+    TopicChanged(Box<gerrit::TopicChangedEvent>),
+    HashtagsChanged(Box<gerrit::HashtagsChangedEvent>),
+    RemindStaleChanges {
+        changes: Vec<gerrit::Change>,
+        now: u64,
+    },
+    ThreadReplies {
+        event: Box<gerrit::CommentAddedEvent>,
+        comments: Vec<gerrit::CommentInfo>,
+    },
+    ChangeReverted {
+        revert: Box<gerrit::Change>,
+        reverted: Box<gerrit::Change>,
+        merged: bool,
+    },
+    /// Mergeability of open changes of a branch after other changes were
+    /// merged into it.
+    MergeabilityChecked {
+        merged: Vec<gerrit::Change>,
+        changes: Vec<(gerrit::Change, bool)>,
+    },
+}
+
+impl Action {
+    /// Gerrit accounts named in an event: the owner and the reviewers of the
+    /// change, and the users causing the event.
+    fn accounts(&self) -> Vec<&gerrit::User> {
+        let (change, users): (_, Vec<&gerrit::User>) = match self {
+            Action::CommentAdded(event) => (&event.change, vec![&event.author]),
+            Action::ReviewerAdded(event) => (&event.change, vec![&event.reviewer]),
+            Action::VoteDeleted(event) => (&event.change, vec![&event.reviewer, &event.remover]),
+            Action::ReviewerDeleted(event) => (
+                &event.change,
+                std::iter::once(&event.reviewer)
+                    .chain(&event.remover)
+                    .collect(),
+            ),
+            Action::ChangeMerged(event) => (&event.change, vec![&event.submitter]),
+            Action::ChangeAbandoned(event) => (&event.change, vec![&event.abandoner]),
+            Action::PatchsetCreated(event) => (&event.change, vec![&event.uploader]),
+            Action::WipStateChanged(event) => (&event.change, vec![&event.changer]),
+            Action::PrivateStateChanged(event) => (&event.change, vec![&event.changer]),
+            Action::TopicChanged(event) => (&event.change, vec![&event.changer]),
+            Action::HashtagsChanged(event) => (&event.change, vec![&event.editor]),
+            _ => return Vec::new(),
+        };
+        std::iter::once(&change.owner)
+            .chain(change.all_reviewers.iter().flatten())
+            .chain(users)
+            .collect()
+    }
+
+    /// The revision reverted by a newly created or merged change.
+    fn reverted_commit(&self) -> Option<&str> {
+        match self {
+            Action::PatchsetCreated(event) if event.patchset.number == 1 => {
+                revert::reverted_commit(&event.change.commit_message)
+            }
+            Action::ChangeMerged(event) => revert::reverted_commit(&event.change.commit_message),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -602,12 +2285,72 @@ impl Response {
     }
 }
 
+#[derive(Debug)]
+struct RoomResponse {
+    pub room_id: spark::RoomId,
+    pub message: String,
+}
+
+impl RoomResponse {
+    pub fn new<A>(room_id: spark::RoomId, message: A) -> RoomResponse
+    where
+        A: Into<String>,
+    {
+        RoomResponse {
+            room_id,
+            message: message.into(),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Task {
     Reply(Response),
+    RoomReply(RoomResponse),
     Save,
 }
 
+/// Current unix timestamp in seconds.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Give up fetching more information for an event after [`FETCH_TIMEOUT`].
+/// Errors of the future itself are expected to be logged already.
+fn with_fetch_timeout<F>(future: F) -> impl Future<Item = F::Item, Error = ()>
+where
+    F: Future<Error = ()>,
+{
+    tokio::timer::Timeout::new(future, FETCH_TIMEOUT).map_err(|e| {
+        if e.is_elapsed() {
+            error!("fetching from gerrit timed out");
+        } else if let Some(e) = e.into_timer() {
+            error!("fetch timer failed: {}", e);
+        }
+    })
+}
+
+/// Turn messages into reply tasks. Messages for watchers are only sent to users
+/// that don't already get a message because of their involvement in the change.
+fn into_reply_tasks(
+    messages: Vec<(spark::Email, String)>,
+    watcher_messages: Vec<(spark::Email, String)>,
+) -> Vec<Task> {
+    let watcher_messages: Vec<_> = watcher_messages
+        .into_iter()
+        .filter(|(watcher, _)| !messages.iter().any(|(email, _)| email == watcher))
+        .collect();
+
+    messages
+        .into_iter()
+        .chain(watcher_messages)
+        .map(|(email, message)| Task::Reply(Response::new(email, message)))
+        .collect()
+}
+
 /// Guess if the change might have comments by looking for a specially formatted
 /// comment.
 fn maybe_has_inline_comments(event: &gerrit::CommentAddedEvent) -> bool {
@@ -624,16 +2367,24 @@ mod test {
     use std::thread;
     use std::time::Duration;
 
-    use futures::future;
+    use assert_matches::assert_matches;
     use spectral::prelude::*;
     use speculate::speculate;
 
     use spark::EmailRef;
 
     use super::*;
+    use crate::fixtures::{get_ci_event, get_event, get_patchset_created_event, get_stale_change};
 
     struct TestGerritCommandRunner;
-    impl GerritCommandRunner for TestGerritCommandRunner {}
+    impl GerritCommandRunner for TestGerritCommandRunner {
+        fn query_changes(
+            &mut self,
+            _query: &str,
+        ) -> Box<dyn Future<Item = Vec<gerrit::Change>, Error = String> + Send> {
+            Box::new(future::ok(Vec::new()))
+        }
+    }
 
     #[derive(Clone)]
     struct TestSparkClient;
@@ -645,6 +2396,9 @@ mod test {
         fn send_message(&self, _email: &EmailRef, _msg: &str) -> Self::ReplyFuture {
             future::ok(())
         }
+        fn send_room_message(&self, _room_id: &spark::RoomIdRef, _msg: &str) -> Self::ReplyFuture {
+            future::ok(())
+        }
     }
 
     impl TestBot {
@@ -831,17 +2585,6 @@ mod test {
         }
     }
 
-    const EVENT_JSON: &str = r#"
-{"author":{"name":"Approver","username":"approver","email":"approver@approvers.com"},"approvals":[{"type":"Code-Review","description":"Code-Review","value":"2","oldValue":"-1"}],"comment":"Patch Set 1: Code-Review+2\n\nJust a buggy script. FAILURE\n\nAnd more problems. FAILURE","patchSet":{"number":1,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":["fb1909b4eda306985d2bbce769310e5a50a98cf5"],"ref":"refs/changes/42/42/1","uploader":{"name":"Author","email":"author@example.com","username":"Author"},"createdOn":1494165142,"author":{"name":"Author","email":"author@example.com","username":"Author"},"isDraft":false,"kind":"REWORK","sizeInsertions":0,"sizeDeletions":0},"change":{"project":"demo-project","branch":"master","id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14","number":49,"subject":"Some review.","owner":{"name":"Author","email":"author@example.com","username":"author"},"url":"http://localhost/42","commitMessage":"Some review.\n\nChange-Id: Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14\n","status":"NEW"},"project":"demo-project","refName":"refs/heads/master","changeKey":{"id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14"},"type":"comment-added","eventCreatedOn":1499190282}"#;
-
-    fn get_event() -> gerrit::CommentAddedEvent {
-        let event: Result<gerrit::Event, _> = serde_json::from_str(EVENT_JSON);
-        match event.expect("failed to decode event") {
-            gerrit::Event::CommentAdded(event) => event,
-            event => panic!("wrong type of event: {:?}", event),
-        }
-    }
-
     #[test]
     fn get_approvals_msg_for_empty_bot() {
         // bot does not have the user => no message
@@ -996,6 +2739,1147 @@ mod test {
         }
     }
 
+    fn reply_emails(tasks: &[Task]) -> Vec<&str> {
+        tasks
+            .iter()
+            .filter_map(|task| match task {
+                Task::Reply(response) => Some(response.email.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn watchers_get_messages_for_watched_changes() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("watcher@example.com");
+        bot.add_user("approver@approvers.com");
+        bot.add_user("other@example.com");
+        let watch: state::Watch = "project:demo-project".parse().unwrap();
+        bot.state
+            .add_watch(EmailRef::new("watcher@example.com"), watch.clone());
+        bot.state
+            .add_watch(EmailRef::new("approver@approvers.com"), watch.clone());
+        bot.state
+            .add_watch(EmailRef::new("author@example.com"), watch);
+        bot.state.add_watch(
+            EmailRef::new("other@example.com"),
+            "project:other-project".parse().unwrap(),
+        );
+
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        let emails = reply_emails(&tasks);
+
+        // the owner gets the regular message only once, the approver doesn't
+        // get notified about their own comment
+        assert_that!(emails).has_length(2);
+        assert_that!(emails).contains("author@example.com");
+        assert_that!(emails).contains("watcher@example.com");
+
+        let watcher_message = tasks.iter().find_map(|task| match task {
+            Task::Reply(response) if response.email == EmailRef::new("watcher@example.com") => {
+                Some(&response.message)
+            }
+            _ => None,
+        });
+        assert_that!(watcher_message.map(|message| message.as_str()))
+            .is_some()
+            .contains("Watching `project:demo-project`");
+    }
+
+    #[test]
+    fn disabled_watchers_get_no_messages() {
+        let mut bot = new_bot();
+        bot.add_user("watcher@example.com");
+        bot.state.add_watch(
+            EmailRef::new("watcher@example.com"),
+            "project:demo-project".parse().unwrap(),
+        );
+        bot.enable("watcher@example.com", false);
+
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_empty();
+    }
+
+    #[test]
+    fn muted_events_are_not_sent() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let sender = || spark::Email::new("author@example.com".to_string());
+
+        let tasks = bot.run_command(
+            sender(),
+            Command::MuteWhen("actor=approver and label=Code-Review value<0".to_string()),
+        );
+        assert_that!(tasks).has_length(2);
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+
+        bot.run_command(
+            sender(),
+            Command::MuteWhen("actor=approver and label=Code-Review value>0".to_string()),
+        );
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        bot.run_command(sender(), Command::Unmute(None));
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+    }
+
+    #[test]
+    fn followed_changes_expire_when_merged() {
+        let mut bot = new_bot();
+        bot.add_user("follower@example.com");
+        bot.add_user("author@example.com");
+        let follower = || spark::Email::new("follower@example.com".to_string());
+
+        bot.run_command(follower(), "follow 49".parse().unwrap());
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            "mute 49".parse().unwrap(),
+        );
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["follower@example.com"]);
+
+        let event = get_event();
+        let merged_event = gerrit::ChangeMergedEvent {
+            change: event.change,
+            patchset: event.patchset,
+            submitter: event.author,
+            new_revision: "49a65998c02eda928559f2d0b586c20bc8e37b10".to_string(),
+            created_on: event.created_on,
+        };
+        let tasks = bot.update(Action::ChangeMerged(Box::new(merged_event)));
+        assert!(tasks.iter().any(|task| matches!(task, Task::Save)));
+        let find_user = |email| bot.state.find_user(EmailRef::new(email)).unwrap();
+        assert!(find_user("follower@example.com").watches().is_empty());
+        assert!(find_user("author@example.com").mutes().is_empty());
+
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+    }
+
+    #[test]
+    fn ignored_accounts_are_skipped() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let sender = || spark::Email::new("author@example.com".to_string());
+
+        bot.run_command(sender(), "ignore user approver".parse().unwrap());
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        bot.run_command(sender(), "unignore user approver".parse().unwrap());
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+    }
+
+    #[test]
+    fn always_notified_accounts_override_flags() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let sender = || spark::Email::new("author@example.com".to_string());
+
+        bot.run_command(
+            sender(),
+            Command::SetFlag(UserFlag::NotifyReviewApprovals, false),
+        );
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        bot.run_command(
+            sender(),
+            "always notify from approver@approvers.com".parse().unwrap(),
+        );
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+
+        // only review comments are forced, opt-in formats stay as they are
+        let user = bot.state.find_user(&sender()).unwrap();
+        let user = user.for_actor(&get_event().author).unwrap();
+        assert!(user.has_flag(UserFlag::NotifyReviewComments));
+        assert!(!user.has_flag(UserFlag::NotifyCiResults));
+
+        let tasks = bot.run_command(sender(), Command::Status);
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("* `approver@approvers.com` (always notify)");
+        });
+    }
+
+    #[test]
+    fn mentioned_users_are_notified_once() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        bot.add_user("tester@example.com");
+        for email in &["reviewer@example.com", "tester@example.com"] {
+            bot.run_command(
+                spark::Email::new(email.to_string()),
+                Command::SetFlag(UserFlag::NotifyMentions, true),
+            );
+        }
+
+        let mut event = get_event();
+        event.comment = "Patch Set 1: Code-Review+2\n\nLooks good, @author. @reviewer@example.com, please check the tests. @tester?"
+            .to_string();
+        // the username of the tester is not known yet
+        let tasks = bot.update(Action::CommentAdded(Box::new(event.clone())));
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["author@example.com", "reviewer@example.com"]);
+
+        let mut uploaded = get_patchset_created_event();
+        uploaded.uploader = gerrit::User {
+            name: Some("Tester".to_string()),
+            username: Some("tester".to_string()),
+            email: Some("tester@example.com".to_string()),
+        };
+        let tasks = bot.update(Action::PatchsetCreated(Box::new(uploaded)));
+        assert!(tasks.iter().any(|task| matches!(task, Task::Save)));
+
+        let tasks = bot.update(Action::CommentAdded(Box::new(event.clone())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec![
+            "author@example.com",
+            "reviewer@example.com",
+            "tester@example.com",
+        ]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert!(!response.message.contains("Mentioned by"));
+        });
+        assert_matches!(&tasks[1], Task::Reply(response) => {
+            assert_that!(response.message).contains("📣 Mentioned by");
+            assert_that!(response.message).contains("> Looks good, @author.");
+        });
+
+        bot.run_command(
+            spark::Email::new("reviewer@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyMentions, false),
+        );
+        let tasks = bot.update(Action::CommentAdded(Box::new(event)));
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["author@example.com", "tester@example.com"]);
+    }
+
+    #[test]
+    fn code_context_is_only_needed_for_shown_inline_comments() {
+        let mut bot = new_bot();
+        let event = get_event();
+        assert!(!bot.shows_inline_comments(&event));
+
+        bot.add_user("author@example.com");
+        assert!(bot.shows_inline_comments(&event));
+
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            Command::SetMaxInlineComments(Some(0)),
+        );
+        assert!(!bot.shows_inline_comments(&event));
+    }
+
+    #[test]
+    fn thread_participants_are_notified_about_replies() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        bot.add_user("approver@approvers.com");
+        let reviewer_email = || spark::Email::new("reviewer@example.com".to_string());
+        bot.run_command(
+            reviewer_email(),
+            Command::SetFlag(UserFlag::NotifyThreadReplies, true),
+        );
+
+        // the approver replies to a thread of the reviewer and the author
+        let mut event = get_event();
+        let comment = |id: &str, in_reply_to: Option<&str>, author: &gerrit::User, unresolved| {
+            gerrit::CommentInfo {
+                id: id.to_string(),
+                path: "src/main.rs".to_string(),
+                patch_set: Some(1),
+                line: Some(3),
+                in_reply_to: in_reply_to.map(String::from),
+                message: format!("Comment {}", id),
+                updated: "2017-07-04 17:44:40.000000000".to_string(),
+                author: Some(author.clone()),
+                unresolved,
+            }
+        };
+        let reviewer = gerrit::User {
+            name: Some("Reviewer".to_string()),
+            username: Some("reviewer".to_string()),
+            email: Some("reviewer@example.com".to_string()),
+        };
+        let comments = vec![
+            comment("1", None, &reviewer, true),
+            comment("2", Some("1"), &event.change.owner, true),
+            comment("3", Some("2"), &event.author, false),
+        ];
+
+        let tasks = bot.update(Action::ThreadReplies {
+            event: Box::new(event.clone()),
+            comments: comments.clone(),
+        });
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.email.as_str()).is_equal_to("reviewer@example.com");
+            assert_that!(response.message).contains("🧵");
+            assert_that!(response.message).contains("`src/main.rs:3`\n> Comment 2\n\nComment 3");
+            assert_that!(response.message).contains("✅ Resolved");
+        });
+
+        // no second message for a comment which already mentions the reviewer
+        bot.run_command(
+            reviewer_email(),
+            Command::SetFlag(UserFlag::NotifyMentions, true),
+        );
+        event.created_on += 1;
+        event.comment = "Patch Set 1:\n\n(1 comment)\n\n@reviewer@example.com".to_string();
+        let tasks = bot.update(Action::CommentAdded(Box::new(event.clone())));
+        assert_that!(reply_emails(&tasks)).contains("reviewer@example.com");
+        let tasks = bot.update(Action::ThreadReplies {
+            event: Box::new(event),
+            comments,
+        });
+        assert_that!(reply_emails(&tasks)).is_empty();
+    }
+
+    #[test]
+    fn ci_results_replace_regular_messages() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let sender = || spark::Email::new("author@example.com".to_string());
+        let ci_event = |value| {
+            let comment = "Build finished: https://ci.example.com/job/42/";
+            Action::CommentAdded(Box::new(get_ci_event(value, comment)))
+        };
+
+        let tasks = bot.update(ci_event("-1"));
+        // the usernames of the accounts are saved
+        assert_matches!(&tasks[..], [Task::Reply(response), Task::Save] => {
+            assert_that!(response.message).contains("(Verified)");
+            assert!(!response.message.contains("Failed:"));
+        });
+
+        bot.run_command(sender(), Command::SetFlag(UserFlag::NotifyCiResults, true));
+        let tasks = bot.update(ci_event("1"));
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("🎉 Fixed: +1 (Verified)");
+            assert_that!(response.message).contains("> https://ci.example.com/job/42/");
+        });
+
+        let tasks = bot.update(ci_event("-1"));
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("💥 New failure: -1 (Verified)");
+        });
+    }
+
+    #[test]
+    fn stale_changes_are_reminded_and_escalated() {
+        // Monday, 2019-04-01 12:00 UTC
+        const MONDAY: u64 = 1_554_120_000;
+        const DAY: u64 = 24 * 60 * 60;
+
+        let mut bot = Builder::new(State::new())
+            .with_reminders(args::RemindersConfig {
+                query: "status:open".to_string(),
+                after_days: 2,
+                repeat_days: 1,
+                notify_owner: true,
+                escalate_after_days: Some(5),
+                escalation_room: Some("team-room".to_string()),
+                check_interval: 3600,
+            })
+            .build(TestGerritCommandRunner, TestSparkClient);
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        let mut remind = |now| {
+            bot.update(Action::RemindStaleChanges {
+                changes: vec![get_stale_change(MONDAY)],
+                now,
+            })
+        };
+
+        assert_that!(remind(MONDAY + DAY)).is_empty();
+
+        let tasks = remind(MONDAY + 2 * DAY);
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["reviewer@example.com", "author@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("Waiting for your review for 2 working days");
+        });
+        assert_matches!(tasks.last(), Some(Task::Save));
+
+        // only once per interval
+        assert_that!(remind(MONDAY + 2 * DAY + 3600)).is_empty();
+
+        // the following Monday is five working days later
+        let tasks = remind(MONDAY + 7 * DAY);
+        assert_that!(reply_emails(&tasks)).has_length(2);
+        assert_matches!(&tasks[2], Task::RoomReply(response) => {
+            assert_that!(response.room_id.as_str()).is_equal_to("team-room");
+            assert_that!(response.message).contains("🚨 No activity for 5 working days");
+        });
+    }
+
+    #[test]
+    fn reminders_skip_muted_changes_and_reviewers_who_voted() {
+        // Monday, 2019-04-01 12:00 UTC
+        const MONDAY: u64 = 1_554_120_000;
+        const DAY: u64 = 24 * 60 * 60;
+
+        let mut bot = Builder::new(State::new())
+            .with_reminders(args::RemindersConfig {
+                query: "status:open".to_string(),
+                after_days: 2,
+                repeat_days: 1,
+                notify_owner: true,
+                escalate_after_days: None,
+                escalation_room: None,
+                check_interval: 3600,
+            })
+            .build(TestGerritCommandRunner, TestSparkClient);
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            "mute 49".parse().unwrap(),
+        );
+
+        let mut change = get_stale_change(MONDAY);
+        let mut patchset = get_event().patchset;
+        patchset.approvals = Some(vec![gerrit::Approval {
+            approval_type: "Code-Review".to_string(),
+            description: None,
+            value: "-1".to_string(),
+            old_value: None,
+            by: change.all_reviewers.as_ref().unwrap().first().cloned(),
+        }]);
+        change.current_patch_set = Some(patchset);
+
+        let tasks = bot.update(Action::RemindStaleChanges {
+            changes: vec![change],
+            now: MONDAY + 2 * DAY,
+        });
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            "unmute 49".parse().unwrap(),
+        );
+        let tasks = bot.update(Action::RemindStaleChanges {
+            changes: vec![get_stale_change(MONDAY)],
+            now: MONDAY + 2 * DAY,
+        });
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["reviewer@example.com", "author@example.com"]);
+    }
+
+    #[test]
+    fn team_members_are_notified_in_team_room() {
+        const MONDAY: u64 = 1_554_120_000;
+        const DAY: u64 = 24 * 60 * 60;
+
+        let teams = Teams::new(vec![(
+            "Core".to_string(),
+            spark::RoomId::new("core-room".to_string()),
+        )]);
+        let member = |name: &str| gerrit::User {
+            name: None,
+            username: Some(name.to_string()),
+            email: Some(format!("{}@example.com", name)),
+        };
+        teams.set_group_members("Core", vec![member("reviewer"), member("other")]);
+        let mut bot = Builder::new(State::new())
+            .with_teams(teams)
+            .with_reminders(args::RemindersConfig {
+                query: "status:open".to_string(),
+                after_days: 2,
+                repeat_days: 1,
+                notify_owner: false,
+                escalate_after_days: None,
+                escalation_room: None,
+                check_interval: 3600,
+            })
+            .build(TestGerritCommandRunner, TestSparkClient);
+        bot.add_user("reviewer@example.com");
+        bot.add_user("other@example.com");
+
+        let reviewer_added = |reviewer| {
+            let event = get_event();
+            Action::ReviewerAdded(Box::new(gerrit::ReviewerAddedEvent {
+                change: event.change,
+                patchset: event.patchset,
+                reviewer,
+                created_on: event.created_on,
+            }))
+        };
+        let tasks = bot.update(reviewer_added(member("reviewer")));
+        // the usernames of the accounts are saved
+        assert_matches!(&tasks[..], [Task::RoomReply(response), Task::Save] => {
+            assert_that!(response.room_id.as_str()).is_equal_to("core-room");
+            assert_that!(response.message).contains("👓 Review requested from team Core");
+        });
+        assert_matches!(
+            &bot.update(reviewer_added(member("other")))[..],
+            [Task::Save]
+        );
+
+        let tasks = bot.update(Action::RemindStaleChanges {
+            changes: vec![get_stale_change(MONDAY)],
+            now: MONDAY + 2 * DAY,
+        });
+        assert_that!(reply_emails(&tasks)).is_empty();
+        assert_matches!(&tasks[0], Task::RoomReply(response) => {
+            assert_that!(response.room_id.as_str()).is_equal_to("core-room");
+            assert_that!(response.message)
+                .contains("Waiting for review from team Core for 2 working days");
+        });
+    }
+
+    #[test]
+    fn out_of_office_pauses_notifications_and_informs_owners() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        let reviewer = || spark::Email::new("reviewer@example.com".to_string());
+
+        bot.run_command(
+            reviewer(),
+            r#"ooo until 2999-01-01 with summary "backup: alice@example.com""#
+                .parse()
+                .unwrap(),
+        );
+        let event = get_event();
+        let reviewer_added_event = gerrit::ReviewerAddedEvent {
+            change: event.change,
+            patchset: event.patchset,
+            reviewer: gerrit::User {
+                name: Some("Reviewer".to_string()),
+                username: Some("reviewer".to_string()),
+                email: Some("reviewer@example.com".to_string()),
+            },
+            created_on: event.created_on,
+        };
+        let tasks = bot.update(Action::ReviewerAdded(Box::new(reviewer_added_event)));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message)
+                .contains("is out of office until 2999-01-01: backup: alice@example.com");
+        });
+        assert!(tasks.iter().any(|task| matches!(task, Task::Save)));
+
+        let tasks = bot.run_command(reviewer(), Command::Back);
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["reviewer@example.com", "reviewer@example.com"]);
+        assert_matches!(&tasks[2], Task::Reply(response) => {
+            assert_that!(response.message).contains("Added as reviewer");
+        });
+    }
+
+    #[test]
+    fn reviewer_away_respects_owner_settings() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        let author = || spark::Email::new("author@example.com".to_string());
+
+        bot.run_command(
+            spark::Email::new("reviewer@example.com".to_string()),
+            "ooo until 2999-01-01".parse().unwrap(),
+        );
+        let reviewer_added = || {
+            let event = get_event();
+            Action::ReviewerAdded(Box::new(gerrit::ReviewerAddedEvent {
+                change: event.change,
+                patchset: event.patchset,
+                reviewer: gerrit::User {
+                    name: Some("Reviewer".to_string()),
+                    username: Some("reviewer".to_string()),
+                    email: Some("reviewer@example.com".to_string()),
+                },
+                created_on: event.created_on,
+            }))
+        };
+
+        bot.run_command(author(), "mute 49".parse().unwrap());
+        assert_that!(reply_emails(&bot.update(reviewer_added()))).is_empty();
+        bot.run_command(author(), "unmute 49".parse().unwrap());
+
+        bot.run_command(author(), "ignore user reviewer".parse().unwrap());
+        assert_that!(reply_emails(&bot.update(reviewer_added()))).is_empty();
+        bot.run_command(author(), "unignore user reviewer".parse().unwrap());
+
+        bot.run_command(author(), "filter out of office".parse().unwrap());
+        assert_that!(reply_emails(&bot.update(reviewer_added()))).is_empty();
+    }
+
+    #[test]
+    fn expired_out_of_office_ends_with_summary() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let author = EmailRef::new("author@example.com");
+        let mut away = Away::new("2000-01-01".parse().unwrap(), None, true);
+        away.missed.push("Missed message".to_string());
+        bot.state.set_away(author, Some(away));
+
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("Welcome back!");
+            assert_that!(response.message).contains("Missed message");
+        });
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["author@example.com", "author@example.com"]);
+        assert!(bot.state.find_user(author).unwrap().away().is_none());
+    }
+
+    #[test]
+    fn out_of_office_dates_are_validated() {
+        let mut bot = new_bot();
+        let sender = || spark::Email::new("some@example.com".to_string());
+        let tasks = bot.run_command(sender(), "ooo until 2000-01-01".parse().unwrap());
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("is not in the future");
+        });
+        let tasks = bot.run_command(sender(), "ooo until 2999-02-30".parse().unwrap());
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("invalid date `2999-02-30`");
+        });
+    }
+
+    #[test]
+    fn owners_and_reviewers_are_notified_about_reverts() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        bot.add_user("reverter@example.com");
+        for email in &["author@example.com", "reviewer@example.com"] {
+            bot.run_command(
+                spark::Email::new(email.to_string()),
+                Command::SetFlag(UserFlag::NotifyReverts, true),
+            );
+        }
+
+        let reverted = get_stale_change(0);
+        let mut revert = get_event().change;
+        revert.number = 50;
+        revert.subject = "Revert \"Some review.\"".to_string();
+        revert.owner = gerrit::User {
+            name: Some("Reverter".to_string()),
+            username: Some("reverter".to_string()),
+            email: Some("reverter@example.com".to_string()),
+        };
+        revert.commit_message = "Revert \"Some review.\"\n\nThis reverts commit 49a65998c02eda928559f2d0b586c20bc8e37b10.\n\nReason for revert: breaks the build\n\nChange-Id: I0123\n".to_string();
+        let revert_action = |bot: &mut TestBot, merged| {
+            bot.update(Action::ChangeReverted {
+                revert: Box::new(revert.clone()),
+                reverted: Box::new(reverted.clone()),
+                merged,
+            })
+        };
+
+        let tasks = revert_action(&mut bot, true);
+        assert_that!(reply_emails(&tasks))
+            .is_equal_to(vec!["author@example.com", "reviewer@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("⏪ Reverted by");
+            assert_that!(response.message).contains("> Reason for revert: breaks the build");
+            assert!(!response.message.contains("Change-Id"));
+        });
+
+        bot.run_command(
+            spark::Email::new("reviewer@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyReverts, false),
+        );
+        let tasks = revert_action(&mut bot, false);
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("⏪ Revert proposed by");
+        });
+
+        let mut private_revert = revert.clone();
+        private_revert.private = true;
+        let tasks = bot.update(Action::ChangeReverted {
+            revert: Box::new(private_revert),
+            reverted: Box::new(reverted.clone()),
+            merged: true,
+        });
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            "mute 49".parse().unwrap(),
+        );
+        assert_that!(reply_emails(&revert_action(&mut bot, true))).is_empty();
+    }
+
+    #[test]
+    fn owners_are_notified_once_about_merge_conflicts() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyMergeConflicts, true),
+        );
+
+        let change = get_stale_change(0);
+        let mut merged = get_event().change;
+        merged.number = 50;
+        merged.subject = "Conflicting review.".to_string();
+        let check = |bot: &mut TestBot, mergeable| {
+            bot.update(Action::MergeabilityChecked {
+                merged: vec![merged.clone()],
+                changes: vec![(change.clone(), mergeable)],
+            })
+        };
+
+        // changes checked for the first time count as mergeable
+        let tasks = check(&mut bot, false);
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("💥 Merge conflict after merge of [Conflicting review.]");
+        });
+
+        let tasks = check(&mut bot, false);
+        assert!(tasks.is_empty());
+
+        let tasks = check(&mut bot, true);
+        assert_matches!(&tasks[..], [Task::Save]);
+        let tasks = check(&mut bot, false);
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+
+        // muted changes
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            "mute 49".parse().unwrap(),
+        );
+        check(&mut bot, true);
+        assert_that!(reply_emails(&check(&mut bot, false))).is_empty();
+    }
+
+    #[test]
+    fn merged_changes_are_only_checked_with_rest_api() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyMergeConflicts, true),
+        );
+        let merged_event = || {
+            let event = get_event();
+            gerrit::ChangeMergedEvent {
+                change: event.change,
+                patchset: event.patchset,
+                submitter: event.author,
+                new_revision: "49a65998c02eda928559f2d0b586c20bc8e37b10".to_string(),
+                created_on: event.created_on,
+            }
+        };
+
+        bot.update(Action::ChangeMerged(Box::new(merged_event())));
+        assert!(bot.merge_checks.take().is_empty());
+
+        bot.rest_client = Some(gerrit::RestClient::new(
+            "http://localhost".to_string(),
+            "bot".to_string(),
+            "secret".to_string(),
+        ));
+        bot.update(Action::ChangeMerged(Box::new(merged_event())));
+        assert_that!(bot.merge_checks.take()).has_length(1);
+    }
+
+    #[test]
+    fn invalid_mute_rules_are_reported() {
+        let mut bot = new_bot();
+        let tasks = bot.run_command(
+            spark::Email::new("some@example.com".to_string()),
+            Command::MuteWhen("reviewer=alice".to_string()),
+        );
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("unknown field `reviewer`");
+        });
+    }
+
+    #[test]
+    fn allow_filters_take_precedence_over_deny_filters() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let sender = || spark::Email::new("author@example.com".to_string());
+
+        bot.run_command(sender(), "filter add reviews Code-Review".parse().unwrap());
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        bot.run_command(sender(), "filter allow demo demo-project".parse().unwrap());
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+
+        let tasks = bot.run_command(sender(), Command::FilterList);
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("* `reviews` (deny): `Code-Review`");
+            assert_that!(response.message).contains("* `demo` (allow): `demo-project`");
+        });
+
+        bot.run_command(sender(), "filter disable demo".parse().unwrap());
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        let tasks = bot.run_command(sender(), "filter enable unknown".parse().unwrap());
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.message).contains("no filter named `unknown`");
+        });
+    }
+
+    #[test]
+    fn query_watchers_get_messages_for_matching_changes() {
+        let mut bot = new_bot();
+        bot.add_user("watcher@example.com");
+        bot.add_user("other@example.com");
+        bot.run_command(
+            spark::Email::new("watcher@example.com".to_string()),
+            Command::WatchQuery("project:demo-project label:Code-Review>=1".to_string()),
+        );
+        bot.run_command(
+            spark::Email::new("other@example.com".to_string()),
+            Command::WatchQuery("project:demo-project -label:Code-Review+2".to_string()),
+        );
+
+        let tasks = bot.update(Action::CommentAdded(Box::new(get_event())));
+        assert_that!(reply_emails(&tasks)).contains("watcher@example.com");
+        assert_that!(reply_emails(&tasks)).does_not_contain("other@example.com");
+    }
+
+    #[test]
+    fn owners_are_notified_about_foreign_uploads() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyForeignUpload, true),
+        );
+
+        let tasks = bot.update(Action::PatchsetCreated(Box::new(
+            get_patchset_created_event(),
+        )));
+        assert_that!(reply_emails(&tasks)).is_empty();
+
+        let mut event = get_patchset_created_event();
+        event.patchset.number = 2;
+        event.patchset.kind = "TRIVIAL_REBASE".to_string();
+        event.patchset.uploader = gerrit::User {
+            name: Some("Rebaser".to_string()),
+            username: Some("rebaser".to_string()),
+            email: Some("rebaser@example.com".to_string()),
+        };
+        event.uploader = event.patchset.uploader.clone();
+        let tasks = bot.update(Action::PatchsetCreated(Box::new(event)));
+        assert_matches!(&tasks[..], [Task::Reply(response)] => {
+            assert_that!(response.email.as_str()).is_equal_to("author@example.com");
+            assert_that!(response.message).contains("📤 Patchset 2 (trivial rebase) uploaded by");
+            assert_that!(response.message).contains("Rebaser");
+        });
+    }
+
+    #[test]
+    fn path_watchers_get_matching_files() {
+        let mut bot = new_bot();
+        bot.add_user("watcher@example.com");
+        bot.add_user("other@example.com");
+        bot.state.add_watch(
+            EmailRef::new("watcher@example.com"),
+            "project:demo-project path:src/net/**".parse().unwrap(),
+        );
+        bot.state.add_watch(
+            EmailRef::new("other@example.com"),
+            "project:demo-project path:docs/**".parse().unwrap(),
+        );
+
+        let tasks = bot.update(Action::PatchsetCreated(Box::new(
+            get_patchset_created_event(),
+        )));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["watcher@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("`src/net/socket.rs`");
+            assert!(!response.message.contains("src/main.rs"));
+        });
+    }
+
+    #[test]
+    fn files_are_only_fetched_with_path_watches() {
+        let builder = Builder::new(State::new());
+        let mut select_extended_info = builder.extended_gerrit_info_selector();
+        let mut bot = builder.build(TestGerritCommandRunner, TestSparkClient);
+        let event = gerrit::Event::PatchsetCreated(get_patchset_created_event());
+
+        assert_that!(select_extended_info(&event).to_vec()).is_empty();
+
+        bot.update(Action::RunCommand {
+            sender: spark::Email::new("watcher@example.com".to_string()),
+            command: "watch project:demo-project path:src/**".parse().unwrap(),
+        });
+        assert_that!(select_extended_info(&event).to_vec()).contains(gerrit::ExtendedInfo::Files);
+
+        bot.update(Action::RunCommand {
+            sender: spark::Email::new("watcher@example.com".to_string()),
+            command: Command::Unwatch(None),
+        });
+        assert_that!(select_extended_info(&event).to_vec()).is_empty();
+    }
+
+    #[test]
+    fn ref_watchers_get_messages_for_matching_refs() {
+        let mut bot = new_bot();
+        bot.add_user("release@example.com");
+        bot.run_command(
+            spark::Email::new("release@example.com".to_string()),
+            "watch refs project:firmware refs/tags/v*".parse().unwrap(),
+        );
+        let ref_updated = |ref_name: &str| {
+            Action::RefUpdated(Box::new(gerrit::RefUpdatedEvent {
+                submitter: Some(gerrit::User {
+                    name: Some("Releaser".to_string()),
+                    username: Some("releaser".to_string()),
+                    email: Some("releaser@example.com".to_string()),
+                }),
+                ref_update: gerrit::RefUpdate {
+                    old_rev: "0000000000000000000000000000000000000000".to_string(),
+                    new_rev: "c4f7d43450e366f9c8e4dcb94fbd91573cd40766".to_string(),
+                    ref_name: ref_name.to_string(),
+                    project: "firmware".to_string(),
+                },
+                created_on: 1_553_633_000,
+            }))
+        };
+
+        let tasks = bot.update(ref_updated("refs/tags/v1.0"));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["release@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message.as_str()).is_equal_to(
+                "🏷️ Tag `v1.0` in `firmware` created at `c4f7d43450` by Releaser\n\n\
+                 👀 Watching `refs project:firmware refs/tags/v*`",
+            );
+        });
+
+        assert_that!(bot.update(ref_updated("refs/heads/master"))).is_empty();
+    }
+
+    #[test]
+    fn reviewers_are_told_when_change_is_ready_for_review() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        let reviewer = || spark::Email::new("reviewer@example.com".to_string());
+        bot.run_command(
+            reviewer(),
+            Command::SetFlag(UserFlag::NotifyReadyForReview, true),
+        );
+        let wip_state_changed = |wip, private| {
+            let event = get_event();
+            let mut change = get_stale_change(0);
+            change.wip = wip;
+            change.private = private;
+            Action::WipStateChanged(Box::new(gerrit::WipStateChangedEvent {
+                changer: change.owner.clone(),
+                change,
+                patchset: event.patchset,
+                created_on: event.created_on,
+            }))
+        };
+
+        let tasks = bot.update(wip_state_changed(false, false));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["reviewer@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("🚦 Ready for review");
+        });
+
+        assert_that!(bot.update(wip_state_changed(true, false))).is_empty();
+        assert_that!(bot.update(wip_state_changed(false, true))).is_empty();
+
+        bot.run_command(reviewer(), "mute 49".parse().unwrap());
+        assert_that!(bot.update(wip_state_changed(false, false))).is_empty();
+        bot.run_command(reviewer(), "unmute 49".parse().unwrap());
+
+        bot.run_command(
+            reviewer(),
+            Command::SetFlag(UserFlag::NotifyReadyForReview, false),
+        );
+        assert_that!(bot.update(wip_state_changed(false, false))).is_empty();
+    }
+
+    #[test]
+    fn topic_changes_are_sent_to_involved_users_with_flag() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        bot.add_user("reviewer@example.com");
+        let topic_changed = || {
+            let event = get_event();
+            let mut change = get_stale_change(0);
+            change.topic = Some("fancy-feature".to_string());
+            Action::TopicChanged(Box::new(gerrit::TopicChangedEvent {
+                changer: change.owner.clone(),
+                change,
+                old_topic: None,
+                created_on: event.created_on,
+            }))
+        };
+
+        // opt-in
+        assert_that!(reply_emails(&bot.update(topic_changed()))).is_empty();
+
+        for email in &["author@example.com", "reviewer@example.com"] {
+            bot.run_command(
+                spark::Email::new(email.to_string()),
+                Command::SetFlag(UserFlag::NotifyTopicChanged, true),
+            );
+        }
+        // the owner changed the topic
+        let tasks = bot.update(topic_changed());
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["reviewer@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("Topic set to `fancy-feature`");
+        });
+
+        bot.run_command(
+            spark::Email::new("reviewer@example.com".to_string()),
+            "mute when topic=fancy-feature".parse().unwrap(),
+        );
+        assert_that!(reply_emails(&bot.update(topic_changed()))).is_empty();
+    }
+
+    #[test]
+    fn private_changes_are_not_announced_to_rooms() {
+        let teams = Teams::new(vec![(
+            "Core".to_string(),
+            spark::RoomId::new("core-room".to_string()),
+        )]);
+        let reviewer = gerrit::User {
+            name: None,
+            username: Some("reviewer".to_string()),
+            email: Some("reviewer@example.com".to_string()),
+        };
+        teams.set_group_members("Core", vec![reviewer.clone()]);
+        let mut bot = Builder::new(State::new())
+            .with_teams(teams)
+            .build(TestGerritCommandRunner, TestSparkClient);
+        bot.add_user("reviewer@example.com");
+
+        let event = get_event();
+        let mut change = event.change;
+        change.private = true;
+        let tasks = bot.update(Action::ReviewerAdded(Box::new(
+            gerrit::ReviewerAddedEvent {
+                change,
+                patchset: event.patchset,
+                reviewer,
+                created_on: event.created_on,
+            },
+        )));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["reviewer@example.com"]);
+    }
+
+    fn user(name: &str) -> gerrit::User {
+        gerrit::User {
+            name: Some(name.to_string()),
+            username: Some(name.to_lowercase()),
+            email: Some(format!("{}@example.com", name.to_lowercase())),
+        }
+    }
+
+    #[test]
+    fn owners_are_told_about_removed_votes() {
+        let mut bot = new_bot();
+        bot.add_user("author@example.com");
+        let vote_deleted = |remover| {
+            let event = get_event();
+            Action::VoteDeleted(Box::new(gerrit::VoteDeletedEvent {
+                change: event.change,
+                patchset: event.patchset,
+                reviewer: user("Reviewer"),
+                remover,
+                approvals: vec![gerrit::Approval {
+                    approval_type: "Code-Review".to_string(),
+                    description: None,
+                    value: "0".to_string(),
+                    old_value: Some("-2".to_string()),
+                    by: None,
+                }],
+                comment: None,
+                created_on: event.created_on,
+            }))
+        };
+
+        // opt-in
+        assert_that!(reply_emails(&bot.update(vote_deleted(user("Reviewer"))))).is_empty();
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyVoteDeleted, true),
+        );
+
+        let tasks = bot.update(vote_deleted(user("Reviewer")));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["author@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("🗑️ Vote 👎 -2 (Code-Review) from");
+        });
+
+        // the owner removed the vote themselves
+        assert_that!(bot.update(vote_deleted(user("Author")))).is_empty();
+
+        bot.run_command(
+            spark::Email::new("author@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyVoteDeleted, false),
+        );
+        assert_that!(bot.update(vote_deleted(user("Admin")))).is_empty();
+    }
+
+    #[test]
+    fn reviewers_are_told_when_removed() {
+        let mut bot = new_bot_with_msg_cache(10, Duration::from_secs(1));
+        bot.add_user("reviewer@example.com");
+        let reviewer_deleted = |remover| {
+            let event = get_event();
+            Action::ReviewerDeleted(Box::new(gerrit::ReviewerDeletedEvent {
+                change: event.change,
+                patchset: event.patchset,
+                reviewer: user("Reviewer"),
+                remover,
+                approvals: Vec::new(),
+                comment: None,
+                created_on: event.created_on,
+            }))
+        };
+
+        // opt-in
+        assert_that!(reply_emails(&bot.update(reviewer_deleted(None)))).is_empty();
+        bot.run_command(
+            spark::Email::new("reviewer@example.com".to_string()),
+            Command::SetFlag(UserFlag::NotifyReviewerDeleted, true),
+        );
+
+        let tasks = bot.update(reviewer_deleted(None));
+        assert_that!(reply_emails(&tasks)).is_equal_to(vec!["reviewer@example.com"]);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("🚪 Removed as reviewer by");
+        });
+
+        // rate limited
+        assert_that!(bot.update(reviewer_deleted(Some(user("Admin"))))).is_empty();
+        // the reviewer removed themselves
+        assert_that!(bot.update(reviewer_deleted(Some(user("Reviewer"))))).is_empty();
+    }
+
+    #[test]
+    fn watch_query_reports_parse_error_position() {
+        let mut bot = new_bot();
+        let tasks = bot.run_command(
+            spark::Email::new("some@example.com".to_string()),
+            Command::WatchQuery("project:foo reviewer:bar".to_string()),
+        );
+        assert_that!(tasks).has_length(1);
+        assert_matches!(&tasks[0], Task::Reply(response) => {
+            assert_that!(response.message).contains("unsupported operator `reviewer:`");
+            assert_that!(response.message).contains("project:foo reviewer:bar\n            ^\n");
+        });
+        assert!(bot.state.users().next().is_none());
+    }
+
     #[test]
     fn test_maybe_has_inline_comments() {
         let mut event = get_event();
@@ -1006,31 +3890,38 @@ mod test {
         assert!(!maybe_has_inline_comments(&event));
     }
 
-    #[test]
-    fn dont_exit_on_spark_send_failure() {
-        /// When sending a message fails there was a bug that lead the bot to
-        /// exit at this point. This a regression test for this bug.
-
-        #[derive(Clone, Default)]
-        struct TestSparkClient {
-            message_count: std::rc::Rc<std::cell::Cell<usize>>,
-        };
+    /// Spark client failing to send any message.
+    #[derive(Clone, Default)]
+    struct FailingSparkClient {
+        message_count: std::rc::Rc<std::cell::Cell<usize>>,
+    }
 
-        impl SparkClient for TestSparkClient {
-            type ReplyFuture = future::FutureResult<(), spark::Error>;
-            fn send_message(&self, _email: &EmailRef, _msg: &str) -> Self::ReplyFuture {
-                self.message_count.set(self.message_count.get() + 1);
+    impl SparkClient for FailingSparkClient {
+        type ReplyFuture = future::FutureResult<(), spark::Error>;
+        fn send_message(&self, _email: &EmailRef, _msg: &str) -> Self::ReplyFuture {
+            self.message_count.set(self.message_count.get() + 1);
 
-                future::err(spark::Error::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "it did not work",
-                )))
-            }
+            future::err(spark::Error::IoError(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "it did not work",
+            )))
         }
+        fn send_room_message(&self, _room_id: &spark::RoomIdRef, msg: &str) -> Self::ReplyFuture {
+            self.send_message(EmailRef::new("room"), msg)
+        }
+    }
 
-        let spark_client = TestSparkClient::default();
+    #[test]
+    fn dont_exit_on_spark_send_failure() {
+        // When sending a message fails there was a bug that lead the bot to
+        // exit at this point. This a regression test for this bug.
+        let spark_client = FailingSparkClient::default();
+        let outbox_path =
+            std::env::temp_dir().join(format!("send-failure-outbox-{}.json", std::process::id()));
 
-        let bot = Builder::new(State::new()).build(TestGerritCommandRunner, spark_client.clone());
+        let bot = Builder::new(State::new())
+            .with_outbox(Outbox::load(&outbox_path).unwrap())
+            .build(TestGerritCommandRunner, spark_client.clone());
 
         let spark_messages = stream::repeat(spark::Message {
             person_email: spark::EmailRef::new("some@example.com").to_owned(),
@@ -1040,6 +3931,26 @@ mod test {
         .take(7);
         let gerrit_events = stream::empty();
 
+        assert_eq!(bot.run(gerrit_events, spark_messages).wait(), Ok(()));
+        // the failed message blocks the later ones until it is retried
+        assert_eq!(spark_client.message_count.get(), 1);
+        assert_eq!(Outbox::load(&outbox_path).unwrap().len(), 7);
+        std::fs::remove_file(outbox_path).unwrap();
+    }
+
+    #[test]
+    fn failed_messages_dont_block_other_recipients() {
+        let spark_client = FailingSparkClient::default();
+
+        let bot = Builder::new(State::new()).build(TestGerritCommandRunner, spark_client.clone());
+
+        let spark_messages = stream::iter_ok((0..7).map(|i| spark::Message {
+            person_email: spark::Email::new(format!("some{}@example.com", i)),
+            text: "status".to_string(),
+            ..Default::default()
+        }));
+        let gerrit_events = stream::empty();
+
         assert_eq!(bot.run(gerrit_events, spark_messages).wait(), Ok(()));
         assert_eq!(spark_client.message_count.get(), 7);
     }
@@ -0,0 +1,69 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use gerritbot_gerrit as gerrit;
+
+lazy_static! {
+    static ref MENTION_REGEX: Regex =
+        Regex::new(r"(?:^|[^\w.@])@(\w[\w.+-]*(?:@[\w-]+(?:\.[\w-]+)+)?)").unwrap();
+}
+
+/// Extract the accounts (usernames or emails) mentioned with `@` in a text.
+pub fn extract_mentions(text: &str) -> Vec<&str> {
+    let mut mentions: Vec<&str> = Vec::new();
+    for cap in MENTION_REGEX.captures_iter(text) {
+        let mention = cap
+            .get(1)
+            .map_or("", |m| m.as_str())
+            .trim_end_matches(&['.', '-'][..]);
+        if !mentions.contains(&mention) {
+            mentions.push(mention);
+        }
+    }
+    mentions
+}
+
+/// The texts of a comment event that can contain mentions: the top-level
+/// comment and the inline comments of its author.
+pub fn comment_texts(event: &gerrit::CommentAddedEvent) -> Vec<&str> {
+    let is_author = |user: &gerrit::User| match (&user.email, &event.author.email) {
+        (Some(email), Some(author)) => email.eq_ignore_ascii_case(author),
+        _ => user.username.is_some() && user.username == event.author.username,
+    };
+
+    std::iter::once(&event.comment[..])
+        .chain(
+            event
+                .patchset
+                .comments
+                .iter()
+                .flatten()
+                .filter(|comment| is_author(&comment.reviewer))
+                .map(|comment| &comment.message[..]),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn usernames_and_emails() {
+        assert_eq!(
+            extract_mentions("Thanks @alice! Could @bob@example.com have a look, @alice?"),
+            vec!["alice", "bob@example.com"]
+        );
+    }
+
+    #[test]
+    fn trailing_punctuation() {
+        assert_eq!(extract_mentions("Ask @j.doe."), vec!["j.doe"]);
+    }
+
+    #[test]
+    fn plain_emails_are_no_mentions() {
+        assert!(extract_mentions("Reported by alice@example.com").is_empty());
+        assert!(extract_mentions("Patch set 2: Code-Review+1").is_empty());
+    }
+}
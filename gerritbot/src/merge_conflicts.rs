@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use gerritbot_gerrit as gerrit;
+
+/// Merged changes are collected for this many seconds before the open changes
+/// of their branches are checked, so that a series of merges results in a
+/// single check.
+pub const CHECK_INTERVAL: u64 = 60;
+
+/// Maximum number of open changes checked per branch.
+const MAX_CHANGES: usize = 100;
+
+/// Maximum number of mergeability requests running at the same time.
+pub const MAX_CONCURRENT_REQUESTS: usize = 4;
+
+/// Maximum number of merged changes mentioned in a message.
+const MAX_MERGED: usize = 5;
+
+/// Branches with merged changes whose open changes have to be checked.
+#[derive(Debug, Default)]
+pub struct PendingChecks {
+    branches: BTreeMap<(String, String), Vec<gerrit::Change>>,
+}
+
+impl PendingChecks {
+    pub fn add(&mut self, merged: &gerrit::Change) {
+        let changes = self
+            .branches
+            .entry((merged.project.clone(), merged.branch.clone()))
+            .or_default();
+        if changes.len() < MAX_MERGED {
+            changes.push(merged.clone());
+        }
+    }
+
+    /// Take the pending checks as query for the open changes of each branch
+    /// together with the changes merged into it.
+    pub fn take(&mut self) -> Vec<(String, Vec<gerrit::Change>)> {
+        std::mem::take(&mut self.branches)
+            .into_iter()
+            .map(|((project, branch), merged)| (open_changes_query(&project, &branch), merged))
+            .collect()
+    }
+}
+
+fn open_changes_query(project: &str, branch: &str) -> String {
+    format!(
+        "status:open project:\"{}\" branch:\"{}\" limit:{}",
+        project, branch, MAX_CHANGES
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fixtures::get_event;
+
+    fn get_change(project: &str, branch: &str, number: u32) -> gerrit::Change {
+        let mut change = get_event().change;
+        change.project = project.to_string();
+        change.branch = branch.to_string();
+        change.number = number;
+        change
+    }
+
+    #[test]
+    fn merges_are_batched_per_branch() {
+        let mut checks = PendingChecks::default();
+        checks.add(&get_change("demo-project", "master", 1));
+        checks.add(&get_change("demo-project", "stable", 2));
+        checks.add(&get_change("demo-project", "master", 3));
+
+        let pending: Vec<_> = checks
+            .take()
+            .into_iter()
+            .map(|(query, merged)| {
+                let numbers: Vec<_> = merged.iter().map(|change| change.number).collect();
+                (query, numbers)
+            })
+            .collect();
+        assert_eq!(
+            pending,
+            vec![
+                (
+                    "status:open project:\"demo-project\" branch:\"master\" limit:100".to_string(),
+                    vec![1, 3]
+                ),
+                (
+                    "status:open project:\"demo-project\" branch:\"stable\" limit:100".to_string(),
+                    vec![2]
+                ),
+            ]
+        );
+        assert!(checks.take().is_empty());
+    }
+
+    #[test]
+    fn merged_changes_are_capped() {
+        let mut checks = PendingChecks::default();
+        for number in 1..=10 {
+            checks.add(&get_change("demo-project", "master", number));
+        }
+        assert_eq!(checks.take()[0].1.len(), MAX_MERGED);
+    }
+}
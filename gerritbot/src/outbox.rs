@@ -0,0 +1,375 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+
+use gerritbot_spark as spark;
+
+use crate::BotError;
+
+/// Seconds between checks for messages due for another attempt.
+pub const RETRY_INTERVAL: u64 = 5;
+
+/// Delay before the first retry of a message. It is doubled for every further
+/// attempt.
+const INITIAL_BACKOFF: u64 = 5;
+
+/// Maximum delay between two attempts, before jitter.
+const MAX_BACKOFF: u64 = 60 * 60;
+
+/// Messages that could not be sent after this many attempts are moved to the
+/// dead letters.
+const MAX_ATTEMPTS: u32 = 10;
+
+/// Maximum number of dead letters kept. The oldest ones are dropped first.
+const MAX_DEAD_LETTERS: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Recipient {
+    Person(spark::Email),
+    Room(spark::RoomId),
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recipient::Person(email) => email.fmt(f),
+            Recipient::Room(room_id) => write!(f, "room {}", room_id),
+        }
+    }
+}
+
+/// A message which was not acknowledged by Webex Teams yet.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub id: u64,
+    pub recipient: Recipient,
+    pub message: String,
+    /// failed attempts to send the message
+    pub attempts: u32,
+    /// unix time after which the message is sent (again)
+    pub next_attempt: u64,
+    pub last_error: Option<String>,
+}
+
+/// Outgoing messages, persisted from before they are sent until Webex Teams
+/// acknowledges them, so that they survive outages and restarts.
+///
+/// Failed messages are retried with exponential backoff and end up in the dead
+/// letters after too many attempts. Messages may be delivered more than once,
+/// e.g. when a request times out after Webex Teams received it, or when the
+/// acknowledgement was not saved yet before a restart.
+///
+/// Changes are only written to the file with [`Outbox::flush`], so that a batch
+/// of changes is saved at once.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Outbox {
+    next_id: u64,
+    pending: Vec<Entry>,
+    dead_letters: Vec<Entry>,
+    /// file the outbox is saved to; not persisted if unset
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// changed since it was saved the last time
+    #[serde(skip)]
+    changed: bool,
+    /// messages currently being sent
+    #[serde(skip)]
+    in_flight: HashSet<u64>,
+}
+
+impl Outbox {
+    /// Load the outbox from the file, or start an empty one saved there if the
+    /// file doesn't exist yet.
+    pub fn load<P>(path: P) -> Result<Self, BotError>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let mut outbox = match File::open(&path) {
+            Ok(f) => serde_json::from_reader(f)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(e) => return Err(e.into()),
+        };
+        outbox.path = Some(path);
+        Ok(outbox)
+    }
+
+    fn save_to(&self, path: &Path) -> Result<(), BotError> {
+        // write a new file first, so that a crash doesn't lose all messages
+        let tmp_path = path.with_extension("json.tmp");
+        serde_json::to_writer(File::create(&tmp_path)?, self)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), BotError> {
+        match self.path {
+            Some(ref path) => self.save_to(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Save the outbox if it changed since it was saved the last time.
+    pub fn flush(&mut self) {
+        if !self.changed {
+            return;
+        }
+        match self.save() {
+            Ok(()) => self.changed = false,
+            Err(err) => error!("Could not save outbox: {:?}", err),
+        }
+    }
+
+    /// Number of messages waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Add a new message, which is sent right away.
+    pub fn push(&mut self, recipient: Recipient, message: String, now: u64) -> Entry {
+        let entry = Entry {
+            id: self.next_id,
+            recipient,
+            message,
+            attempts: 0,
+            next_attempt: now,
+            last_error: None,
+        };
+        self.next_id += 1;
+        self.pending.push(entry.clone());
+        self.in_flight.insert(entry.id);
+        self.changed = true;
+        entry
+    }
+
+    /// Take the messages due for another attempt, e.g. after a failure or from
+    /// a previous run.
+    pub fn take_due(&mut self, now: u64) -> Vec<Entry> {
+        let in_flight = &mut self.in_flight;
+        self.pending
+            .iter()
+            .filter(|entry| entry.next_attempt <= now && in_flight.insert(entry.id))
+            .cloned()
+            .collect()
+    }
+
+    /// Remove a message which was sent successfully.
+    pub fn ack(&mut self, id: u64) {
+        self.in_flight.remove(&id);
+        self.pending.retain(|entry| entry.id != id);
+        self.changed = true;
+    }
+
+    /// Schedule another attempt for a message which could not be sent, not
+    /// before the given number of seconds if Webex Teams asked to wait, or move
+    /// it to the dead letters after too many attempts. Returns if the message
+    /// is retried.
+    pub fn fail(&mut self, id: u64, error: String, retry_after: Option<u64>, now: u64) -> bool {
+        self.in_flight.remove(&id);
+        let index = match self.pending.iter().position(|entry| entry.id == id) {
+            Some(index) => index,
+            None => return false,
+        };
+
+        let entry = &mut self.pending[index];
+        entry.attempts += 1;
+        entry.last_error = Some(error);
+        let retried = entry.attempts < MAX_ATTEMPTS;
+        if retried {
+            let delay = with_jitter(backoff(entry.attempts));
+            entry.next_attempt = now + delay.max(retry_after.unwrap_or(0));
+        } else {
+            let entry = self.pending.remove(index);
+            warn!(
+                "Giving up on message {} to {} after {} attempts",
+                entry.id, entry.recipient, entry.attempts
+            );
+            self.add_dead_letter(entry);
+        }
+        self.changed = true;
+        retried
+    }
+
+    /// Move a message which can never be sent to the dead letters.
+    pub fn give_up(&mut self, id: u64, error: String) {
+        self.in_flight.remove(&id);
+        if let Some(index) = self.pending.iter().position(|entry| entry.id == id) {
+            let mut entry = self.pending.remove(index);
+            entry.attempts += 1;
+            warn!(
+                "Giving up on message {} to {}: {}",
+                entry.id, entry.recipient, error
+            );
+            entry.last_error = Some(error);
+            self.add_dead_letter(entry);
+            self.changed = true;
+        }
+    }
+
+    fn add_dead_letter(&mut self, entry: Entry) {
+        if self.dead_letters.len() >= MAX_DEAD_LETTERS {
+            let dropped = self.dead_letters.remove(0);
+            warn!(
+                "Dropping dead letter {} to {}: too many dead letters",
+                dropped.id, dropped.recipient
+            );
+        }
+        self.dead_letters.push(entry);
+    }
+
+    /// Messages which could not be sent.
+    pub fn dead_letters(&self) -> &[Entry] {
+        &self.dead_letters
+    }
+
+    /// Move all dead letters back to the pending messages, to be sent at the
+    /// next retry. Returns the number of replayed messages.
+    pub fn replay_dead_letters(&mut self, now: u64) -> usize {
+        let count = self.dead_letters.len();
+        for mut entry in self.dead_letters.drain(..) {
+            entry.attempts = 0;
+            entry.next_attempt = now;
+            self.pending.push(entry);
+        }
+        self.changed = true;
+        count
+    }
+}
+
+/// Delay before the next attempt after the given number of failed attempts.
+fn backoff(attempts: u32) -> u64 {
+    INITIAL_BACKOFF
+        .checked_shl(attempts.saturating_sub(1))
+        .filter(|delay| *delay <= MAX_BACKOFF)
+        .unwrap_or(MAX_BACKOFF)
+}
+
+/// Add up to half of the delay at random, so that messages failed together
+/// are not retried together.
+fn with_jitter(delay: u64) -> u64 {
+    let random = RandomState::new().build_hasher().finish();
+    delay + random % (delay / 2 + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn person(email: &str) -> Recipient {
+        Recipient::Person(spark::Email::new(email.to_string()))
+    }
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        assert_eq!(backoff(1), 5);
+        assert_eq!(backoff(2), 10);
+        assert_eq!(backoff(3), 20);
+        assert_eq!(backoff(20), MAX_BACKOFF);
+        assert_eq!(backoff(100), MAX_BACKOFF);
+        for _ in 0..100 {
+            let delay = with_jitter(10);
+            assert!((10..=15).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn failed_messages_are_retried() {
+        let mut outbox = Outbox::default();
+        let entry = outbox.push(person("some@example.com"), "hello".to_string(), 100);
+        let other = outbox.push(person("other@example.com"), "hi".to_string(), 100);
+        // in flight
+        assert!(outbox.take_due(100).is_empty());
+
+        outbox.ack(other.id);
+        outbox.fail(entry.id, "timeout".to_string(), None, 100);
+        assert_eq!(outbox.len(), 1);
+        assert!(outbox.take_due(104).is_empty());
+
+        let due = outbox.take_due(120);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].message, "hello");
+        assert_eq!(due[0].attempts, 1);
+        assert_eq!(due[0].last_error.as_deref(), Some("timeout"));
+        assert!(outbox.take_due(120).is_empty());
+
+        // rate limited
+        outbox.fail(entry.id, "rate limited".to_string(), Some(300), 120);
+        assert!(outbox.take_due(400).is_empty());
+        assert_eq!(outbox.take_due(420).len(), 1);
+
+        outbox.ack(entry.id);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn messages_end_up_in_dead_letters() {
+        let mut outbox = Outbox::default();
+        let entry = outbox.push(person("some@example.com"), "hello".to_string(), 0);
+        for _ in 0..MAX_ATTEMPTS {
+            outbox.fail(entry.id, "unauthorized".to_string(), None, 0);
+        }
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.dead_letters().len(), 1);
+
+        let entry = outbox.push(person("unknown@example.com"), "hi".to_string(), 0);
+        outbox.give_up(entry.id, "not found".to_string());
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.dead_letters().len(), 2);
+        assert_eq!(outbox.dead_letters()[1].attempts, 1);
+
+        assert_eq!(outbox.replay_dead_letters(1000), 2);
+        assert!(outbox.dead_letters().is_empty());
+        let due = outbox.take_due(1000);
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].attempts, 0);
+    }
+
+    #[test]
+    fn dead_letters_are_capped() {
+        let mut outbox = Outbox::default();
+        for i in 0..MAX_DEAD_LETTERS + 5 {
+            let entry = outbox.push(person("unknown@example.com"), i.to_string(), 0);
+            outbox.give_up(entry.id, "not found".to_string());
+        }
+        assert_eq!(outbox.dead_letters().len(), MAX_DEAD_LETTERS);
+        assert_eq!(outbox.dead_letters()[0].message, "5");
+    }
+
+    #[test]
+    fn pending_messages_survive_restart() {
+        let path = std::env::temp_dir().join(format!("outbox-test-{}.json", std::process::id()));
+        let mut outbox = Outbox::load(&path).unwrap();
+        outbox.push(person("some@example.com"), "hello".to_string(), 100);
+        outbox.push(
+            Recipient::Room(spark::RoomId::new("room".to_string())),
+            "hi all".to_string(),
+            100,
+        );
+        // changes are saved in batches
+        assert!(!path.exists());
+        outbox.flush();
+
+        // messages in flight before the restart are sent again
+        let mut outbox = Outbox::load(&path).unwrap();
+        let due = outbox.take_due(100);
+        let entry = outbox.push(person("some@example.com"), "again".to_string(), 100);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(due.len(), 2);
+        assert_eq!(
+            due[1].recipient,
+            Recipient::Room(spark::RoomId::new("room".to_string()))
+        );
+        assert_eq!(entry.id, 2);
+    }
+}
@@ -0,0 +1,653 @@
+//! Local evaluation of a subset of the Gerrit search language.
+//!
+//! Supported operators are `project:`, `branch:`, `owner:`, `file:`, `topic:`,
+//! `hashtag:`, `label:` and `is:wip`, combined with `AND`, `OR`, `NOT` (or
+//! `-`) and parentheses. Like in Gerrit, terms next to each other are implicitly
+//! combined with `AND`. Values can be quoted with `"..."` or `{...}`. Values of
+//! `project:`, `branch:` and `file:` starting with `^` are regular expressions.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use gerritbot_gerrit as gerrit;
+
+use crate::user_matches_account;
+
+/// Error while parsing a query. The position is the 1-based character index at
+/// which the error was detected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new<M: Into<String>>(position: usize, message: M) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+/// A parsed query together with its source text.
+#[derive(Debug, Clone)]
+pub struct Query {
+    source: String,
+    expr: Expr,
+}
+
+impl Query {
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        self.expr.matches(event)
+    }
+
+    /// Whether the query looks at the files of patchsets, which are only
+    /// fetched on demand.
+    pub fn uses_files(&self) -> bool {
+        self.expr.uses_files()
+    }
+}
+
+impl PartialEq for Query {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Query {}
+
+impl Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl FromStr for Query {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end: source.chars().count() + 1,
+        };
+        let expr = parser.parse_or()?;
+
+        if let Some(token) = parser.peek() {
+            return Err(ParseError::new(token.position, "unexpected `)`"));
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+}
+
+/// Serialize the query as its source text.
+impl Serialize for Query {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+/// Deserialize the query by parsing its source text.
+impl<'de> Deserialize<'de> for Query {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        source
+            .parse()
+            .map_err(|e| <D::Error as serde::de::Error>::custom(format!("invalid query: {}", e)))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Predicate(Predicate),
+    Not(Box<Expr>),
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+}
+
+impl Expr {
+    fn matches<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        match self {
+            Expr::Predicate(predicate) => predicate.matches(event),
+            Expr::Not(expr) => !expr.matches(event),
+            Expr::And(exprs) => exprs.iter().all(|expr| expr.matches(event)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| expr.matches(event)),
+        }
+    }
+
+    fn uses_files(&self) -> bool {
+        match self {
+            Expr::Predicate(predicate) => matches!(predicate, Predicate::File(_)),
+            Expr::Not(expr) => expr.uses_files(),
+            Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().any(Expr::uses_files),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Exact(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn new(value: &str, position: usize) -> Result<Self, ParseError> {
+        if value.starts_with('^') {
+            Regex::new(value)
+                .map(Matcher::Regex)
+                .map_err(|e| ParseError::new(position, format!("invalid regex: {}", e)))
+        } else {
+            Ok(Matcher::Exact(value.to_string()))
+        }
+    }
+
+    fn is_match(&self, s: &str) -> bool {
+        match self {
+            Matcher::Exact(value) => value == s,
+            Matcher::Regex(regex) => regex.is_match(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn compare(self, actual: i32, expected: i32) -> bool {
+        match self {
+            Comparison::Equal => actual == expected,
+            Comparison::Greater => actual > expected,
+            Comparison::GreaterOrEqual => actual >= expected,
+            Comparison::Less => actual < expected,
+            Comparison::LessOrEqual => actual <= expected,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Predicate {
+    Project(Matcher),
+    Branch(Matcher),
+    Owner(String),
+    File(Matcher),
+    Topic(String),
+    Hashtag(String),
+    Label {
+        name: String,
+        comparison: Comparison,
+        value: i32,
+    },
+    IsWip,
+}
+
+impl Predicate {
+    fn parse(key: &str, value: &str, position: usize) -> Result<Self, ParseError> {
+        // position of the value for error messages
+        let value_position = position + key.chars().count() + 1;
+
+        if value.is_empty() {
+            return Err(ParseError::new(value_position, "missing value"));
+        }
+
+        Ok(match key {
+            "project" => Predicate::Project(Matcher::new(value, value_position)?),
+            "branch" => Predicate::Branch(Matcher::new(
+                value.trim_start_matches("refs/heads/"),
+                value_position,
+            )?),
+            "owner" => Predicate::Owner(value.to_string()),
+            "file" => Predicate::File(Matcher::new(value, value_position)?),
+            "topic" => Predicate::Topic(value.to_string()),
+            "hashtag" => Predicate::Hashtag(value.to_string()),
+            "label" => Self::parse_label(value, value_position)?,
+            "is" if value.eq_ignore_ascii_case("wip") => Predicate::IsWip,
+            "is" => {
+                return Err(ParseError::new(
+                    value_position,
+                    format!("unsupported value `is:{}`", value),
+                ))
+            }
+            _ => {
+                return Err(ParseError::new(
+                    position,
+                    format!("unsupported operator `{}:`", key),
+                ))
+            }
+        })
+    }
+
+    /// Parse `<label><comparison><value>` where comparison is one of `=`, `>`,
+    /// `>=`, `<` and `<=`. The comparison can be left out if the value is
+    /// signed, e.g. `Code-Review-2`.
+    fn parse_label(s: &str, position: usize) -> Result<Self, ParseError> {
+        let (name, comparison, value) = if let Some(i) = s.find(|c| "=<>".contains(c)) {
+            let (name, rest) = s.split_at(i);
+            let (comparison, value) = match rest {
+                _ if rest.starts_with(">=") => (Comparison::GreaterOrEqual, &rest[2..]),
+                _ if rest.starts_with("<=") => (Comparison::LessOrEqual, &rest[2..]),
+                _ if rest.starts_with('>') => (Comparison::Greater, &rest[1..]),
+                _ if rest.starts_with('<') => (Comparison::Less, &rest[1..]),
+                _ => (Comparison::Equal, &rest[1..]),
+            };
+            (name, comparison, value)
+        } else if let Some(i) = s
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .strip_suffix(|c| c == '+' || c == '-')
+            .map(str::len)
+            .filter(|&i| i + 1 < s.len())
+        {
+            let (name, value) = s.split_at(i);
+            (name, Comparison::Equal, value)
+        } else {
+            return Err(ParseError::new(
+                position,
+                "expected label vote like `Code-Review=-2`",
+            ));
+        };
+
+        if name.is_empty() {
+            return Err(ParseError::new(position, "missing label name"));
+        }
+
+        let value = value.parse().map_err(|_| {
+            ParseError::new(
+                position + s.len() - value.len(),
+                format!("invalid label value `{}`", value),
+            )
+        })?;
+
+        Ok(Predicate::Label {
+            name: name.to_string(),
+            comparison,
+            value,
+        })
+    }
+
+    fn matches<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        let change = event.change();
+        let patchset = event.patchset();
+
+        match self {
+            Predicate::Project(matcher) => matcher.is_match(&change.project),
+            Predicate::Branch(matcher) => matcher.is_match(&change.branch),
+            Predicate::Owner(account) => user_matches_account(&change.owner, account),
+            Predicate::File(matcher) => patchset
+                .iter()
+                .flat_map(|patchset| patchset.files.iter().flatten())
+                .any(|f| match matcher {
+                    // like Gerrit, match the full path or any path segment
+                    Matcher::Exact(path) => &f.file == path || f.file.split('/').any(|s| s == path),
+                    Matcher::Regex(_) => matcher.is_match(&f.file),
+                }),
+            Predicate::Topic(topic) => change.topic.as_ref() == Some(topic),
+            Predicate::Hashtag(hashtag) => change
+                .hashtags
+                .iter()
+                .flatten()
+                .any(|h| h.eq_ignore_ascii_case(hashtag)),
+            Predicate::Label {
+                name,
+                comparison,
+                value,
+            } => event
+                .approvals()
+                .iter()
+                .chain(
+                    patchset
+                        .iter()
+                        .flat_map(|patchset| patchset.approvals.iter().flatten()),
+                )
+                .filter(|approval| approval.approval_type.eq_ignore_ascii_case(name))
+                .filter_map(|approval| approval.value.parse().ok())
+                .any(|actual| comparison.compare(actual, *value)),
+            Predicate::IsWip => change.wip,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TokenKind {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Term { key: String, value: String },
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    /// 1-based character position
+    position: usize,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, ParseError> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let is_delimiter = |c: char| c.is_whitespace() || c == '(' || c == ')';
+
+    while i < chars.len() {
+        let c = chars[i];
+        let position = i + 1;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let kind = match c {
+            '(' => {
+                i += 1;
+                TokenKind::LeftParen
+            }
+            ')' => {
+                i += 1;
+                TokenKind::RightParen
+            }
+            '-' => {
+                i += 1;
+                TokenKind::Not
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_delimiter(chars[i]) && chars[i] != ':' {
+                    i += 1;
+                }
+                let key: String = chars[start..i].iter().collect();
+
+                if i < chars.len() && chars[i] == ':' {
+                    i += 1;
+                    let value = match chars.get(i) {
+                        Some(&open) if open == '"' || open == '{' => {
+                            let close = if open == '"' { '"' } else { '}' };
+                            let value_start = i + 1;
+                            let value_end = chars[value_start..]
+                                .iter()
+                                .position(|&c| c == close)
+                                .map(|len| value_start + len)
+                                .ok_or_else(|| {
+                                    ParseError::new(i + 1, format!("missing closing `{}`", close))
+                                })?;
+                            i = value_end + 1;
+                            chars[value_start..value_end].iter().collect()
+                        }
+                        _ => {
+                            let value_start = i;
+                            while i < chars.len() && !is_delimiter(chars[i]) {
+                                i += 1;
+                            }
+                            chars[value_start..i].iter().collect()
+                        }
+                    };
+                    TokenKind::Term {
+                        key: key.to_lowercase(),
+                        value,
+                    }
+                } else {
+                    match &key[..] {
+                        "AND" | "and" => TokenKind::And,
+                        "OR" | "or" => TokenKind::Or,
+                        "NOT" | "not" => TokenKind::Not,
+                        _ => {
+                            return Err(ParseError::new(
+                                position,
+                                format!("expected `operator:value` but got `{}`", key),
+                            ))
+                        }
+                    }
+                }
+            }
+        };
+
+        tokens.push(Token { kind, position });
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    /// position reported for errors at the end of the input
+    end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_if(&mut self, kind: &TokenKind) -> bool {
+        if self.peek().map(|token| &token.kind) == Some(kind) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// or := and ("OR" and)*
+    fn parse_or(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![self.parse_and()?];
+
+        while self.next_if(&TokenKind::Or) {
+            exprs.push(self.parse_and()?);
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::Or(exprs)
+        })
+    }
+
+    /// and := not ("AND"? not)*
+    fn parse_and(&mut self) -> Result<Expr, ParseError> {
+        let mut exprs = vec![self.parse_not()?];
+
+        loop {
+            if self.next_if(&TokenKind::And) {
+                exprs.push(self.parse_not()?);
+                continue;
+            }
+
+            match self.peek().map(|token| &token.kind) {
+                Some(TokenKind::Term { .. })
+                | Some(TokenKind::Not)
+                | Some(TokenKind::LeftParen) => exprs.push(self.parse_not()?),
+                _ => break,
+            }
+        }
+
+        Ok(if exprs.len() == 1 {
+            exprs.pop().unwrap()
+        } else {
+            Expr::And(exprs)
+        })
+    }
+
+    /// not := ("NOT" | "-") not | primary
+    fn parse_not(&mut self) -> Result<Expr, ParseError> {
+        if self.next_if(&TokenKind::Not) {
+            Ok(Expr::Not(Box::new(self.parse_not()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    /// primary := term | "(" or ")"
+    fn parse_primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self
+            .peek()
+            .ok_or_else(|| ParseError::new(self.end, "unexpected end of query"))?;
+        self.pos += 1;
+
+        match &token.kind {
+            TokenKind::Term { key, value } => {
+                Predicate::parse(key, value, token.position).map(Expr::Predicate)
+            }
+            TokenKind::LeftParen => {
+                let expr = self.parse_or()?;
+                if self.next_if(&TokenKind::RightParen) {
+                    Ok(expr)
+                } else {
+                    let position = self.peek().map_or(self.end, |token| token.position);
+                    Err(ParseError::new(position, "expected `)`"))
+                }
+            }
+            TokenKind::RightParen => Err(ParseError::new(token.position, "unexpected `)`")),
+            TokenKind::And | TokenKind::Or | TokenKind::Not => Err(ParseError::new(
+                token.position,
+                "expected search term but got operator",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EVENT_JSON: &str = r#"
+{"author":{"name":"Approver","username":"approver","email":"approver@approvers.com"},"approvals":[{"type":"Code-Review","description":"Code-Review","value":"-2","oldValue":"0"}],"comment":"Patch Set 1: Code-Review-2","patchSet":{"number":1,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":["fb1909b4eda306985d2bbce769310e5a50a98cf5"],"ref":"refs/changes/42/42/1","uploader":{"name":"Author","email":"author@example.com","username":"Author"},"createdOn":1494165142,"author":{"name":"Author","email":"author@example.com","username":"Author"},"isDraft":false,"kind":"REWORK","sizeInsertions":0,"sizeDeletions":0,"files":[{"file":"src/net/socket.rs","type":"MODIFIED","insertions":1,"deletions":1}]},"change":{"project":"demo-project","branch":"master","id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14","number":49,"subject":"Some review.","topic":"sockets","hashtags":["Refactoring"],"owner":{"name":"Author","email":"author@example.com","username":"author"},"url":"http://localhost/42","commitMessage":"Some review.\n\nChange-Id: Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14\n","status":"NEW","wip":true},"project":"demo-project","refName":"refs/heads/master","changeKey":{"id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14"},"type":"comment-added","eventCreatedOn":1499190282}"#;
+
+    fn get_event() -> gerrit::CommentAddedEvent {
+        serde_json::from_str(EVENT_JSON).expect("failed to decode event")
+    }
+
+    fn matches(query: &str) -> bool {
+        query
+            .parse::<Query>()
+            .expect("parse failed")
+            .matches(&get_event())
+    }
+
+    fn parse_error(query: &str) -> ParseError {
+        query.parse::<Query>().expect_err("parse didn't fail")
+    }
+
+    macro_rules! test_matches {
+        ($name:ident, $query:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(matches($query), $expected, "query: {}", $query);
+            }
+        };
+    }
+
+    macro_rules! test_parse_error {
+        ($name:ident, $query:expr, $position:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(parse_error($query).position, $position);
+            }
+        };
+    }
+
+    test_matches!(project, "project:demo-project", true);
+    test_matches!(project_other, "project:other", false);
+    test_matches!(project_regex, "project:^demo-.*", true);
+    test_matches!(branch, "branch:master", true);
+    test_matches!(branch_ref, "branch:refs/heads/master", true);
+    test_matches!(owner_email, "owner:author@example.com", true);
+    test_matches!(owner_username, "owner:author", true);
+    test_matches!(owner_other, "owner:approver", false);
+    test_matches!(file_path, "file:src/net/socket.rs", true);
+    test_matches!(file_segment, "file:net", true);
+    test_matches!(file_regex, r#"file:"^src/net/.*\.rs""#, true);
+    test_matches!(file_other, "file:src/io", false);
+    test_matches!(topic, "topic:sockets", true);
+    test_matches!(hashtag, "hashtag:refactoring", true);
+    test_matches!(label_equal, "label:Code-Review=-2", true);
+    test_matches!(label_signed, "label:Code-Review-2", true);
+    test_matches!(label_less, "label:Code-Review<0", true);
+    test_matches!(label_greater_or_equal, "label:Code-Review>=1", false);
+    test_matches!(label_other, "label:Verified=-1", false);
+    test_matches!(is_wip, "is:wip", true);
+    test_matches!(implicit_and, "project:demo-project branch:master", true);
+    test_matches!(explicit_and, "project:demo-project AND branch:other", false);
+    test_matches!(or, "project:other OR topic:sockets", true);
+    test_matches!(not, "NOT is:wip", false);
+    test_matches!(minus, "-project:other", true);
+    test_matches!(
+        parentheses,
+        "project:demo-project (branch:other OR -label:Code-Review=-2)",
+        false
+    );
+    test_matches!(
+        precedence,
+        "project:other branch:master OR hashtag:refactoring",
+        true
+    );
+    test_matches!(quoted, r#"topic:{sockets}"#, true);
+
+    test_parse_error!(empty, "", 1);
+    test_parse_error!(unknown_operator, "project:foo reviewer:bar", 13);
+    test_parse_error!(bare_word, "project:foo bar", 13);
+    test_parse_error!(missing_value, "project:", 9);
+    test_parse_error!(missing_closing_paren, "(project:foo", 13);
+    test_parse_error!(unexpected_closing_paren, "project:foo)", 12);
+    test_parse_error!(dangling_or, "project:foo OR", 15);
+    test_parse_error!(unterminated_quote, r#"topic:"foo"#, 7);
+    test_parse_error!(invalid_regex, "file:^src/[", 6);
+    test_parse_error!(invalid_label, "label:Code-Review", 7);
+    test_parse_error!(invalid_label_value, "label:Code-Review=x", 19);
+    test_parse_error!(unknown_is, "is:open", 4);
+
+    #[test]
+    fn parse_error_display() {
+        assert_eq!(
+            parse_error("project:foo)").to_string(),
+            "unexpected `)` at position 12"
+        );
+    }
+
+    #[test]
+    fn serialize_as_source() {
+        let query: Query = " project:foo  OR topic:bar ".parse().unwrap();
+        let json = serde_json::to_string(&query).unwrap();
+        assert_eq!(json, r#""project:foo  OR topic:bar""#);
+        let decoded: Query = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, query);
+        serde_json::from_str::<Query>(r#""project:foo)""#).expect_err("decoded invalid query");
+    }
+
+    #[test]
+    fn uses_files() {
+        let uses_files = |query: &str| query.parse::<Query>().unwrap().uses_files();
+        assert!(uses_files("file:src/net"));
+        assert!(uses_files(
+            "project:tools AND NOT (topic:net OR file:^src/)"
+        ));
+        assert!(!uses_files("project:tools -is:wip"));
+    }
+}
@@ -72,6 +72,44 @@ pub enum MsgCacheLine {
         email: Email,
         subject: Subject,
     },
+    VoteDeleted {
+        email: Email,
+        subject: Subject,
+        reviewer: String,
+        approvals: Vec<Approval>,
+    },
+    ReviewerDeleted {
+        email: Email,
+        subject: Subject,
+    },
+}
+
+fn user_key(user: &gerrit::User) -> String {
+    user.email
+        .as_ref()
+        .or_else(|| user.username.as_ref())
+        .map(String::as_str)
+        .unwrap_or("<unknown user>")
+        .to_string()
+}
+
+/// Approvals sorted to get a stable key.
+fn approvals_key(approvals: &[gerrit::Approval]) -> Vec<Approval> {
+    let mut approvals: Vec<_> = approvals
+        .iter()
+        .map(
+            |gerrit::Approval {
+                 ref approval_type,
+                 ref value,
+                 ..
+             }| Approval {
+                approval_type: approval_type.clone(),
+                approval_value: value.clone(),
+            },
+        )
+        .collect();
+    approvals.sort_unstable();
+    approvals
 }
 
 pub trait IntoCacheLine {
@@ -80,40 +118,11 @@ pub trait IntoCacheLine {
 
 impl IntoCacheLine for &gerrit::CommentAddedEvent {
     fn into_cache_line(email: Email, event: &Self) -> MsgCacheLine {
-        let mut approvals: Vec<_> = event
-            .approvals
-            .as_deref()
-            .unwrap_or(&[][..])
-            .iter()
-            .map(
-                |gerrit::Approval {
-                     ref approval_type,
-                     ref value,
-                     ..
-                 }| Approval {
-                    approval_type: approval_type.clone(),
-                    approval_value: value.clone(),
-                },
-            )
-            .collect();
-
-        // sort approvals to get a stable key
-        approvals.sort_unstable();
-
-        let approver = event
-            .author
-            .email
-            .as_ref()
-            .or_else(|| event.author.username.as_ref())
-            .map(String::as_str)
-            .unwrap_or("<unknown user>")
-            .to_string();
-
         MsgCacheLine::Approvals {
             email,
             subject: Subject::from_change(&event.change),
-            approver,
-            approvals,
+            approver: user_key(&event.author),
+            approvals: approvals_key(event.approvals.as_deref().unwrap_or(&[][..])),
         }
     }
 }
@@ -126,3 +135,32 @@ impl IntoCacheLine for &gerrit::ReviewerAddedEvent {
         }
     }
 }
+
+impl IntoCacheLine for &gerrit::VoteDeletedEvent {
+    fn into_cache_line(email: Email, event: &Self) -> MsgCacheLine {
+        // the removed votes are in the old values
+        let approvals: Vec<_> = event
+            .approvals
+            .iter()
+            .map(|approval| gerrit::Approval {
+                value: approval.old_value.clone().unwrap_or_default(),
+                ..approval.clone()
+            })
+            .collect();
+        MsgCacheLine::VoteDeleted {
+            email,
+            subject: Subject::from_change(&event.change),
+            reviewer: user_key(&event.reviewer),
+            approvals: approvals_key(&approvals),
+        }
+    }
+}
+
+impl IntoCacheLine for &gerrit::ReviewerDeletedEvent {
+    fn into_cache_line(email: Email, event: &Self) -> MsgCacheLine {
+        MsgCacheLine::ReviewerDeleted {
+            email,
+            subject: Subject::from_change(&event.change),
+        }
+    }
+}
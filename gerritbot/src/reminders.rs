@@ -0,0 +1,77 @@
+use serde::Serialize;
+
+pub const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Why a reminder about a stale change is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReminderRole {
+    /// The recipient is a reviewer of the change.
+    Reviewer,
+    /// The recipient owns the change.
+    Owner,
+    /// The change is escalated to a room.
+    Escalation,
+    /// The room of a team whose members review the change.
+    Team,
+}
+
+/// Count the working days (Monday to Friday) that passed between two unix
+/// timestamps, not counting the day of `from`.
+pub fn working_days_between(from: u64, to: u64) -> u32 {
+    let from_day = from / SECONDS_PER_DAY;
+    let to_day = to / SECONDS_PER_DAY;
+
+    // 1970-01-01 was a Thursday, so day 2 was a Saturday.
+    (from_day + 1..=to_day)
+        .filter(|day| day % 7 != 2 && day % 7 != 3)
+        .count() as u32
+}
+
+/// Key of the escalation room in the reminder timestamps of a change.
+pub const ESCALATION_RECIPIENT: &str = "#escalation";
+
+/// Key of the room of a team in the reminder timestamps of a change.
+pub fn team_recipient(group: &str) -> String {
+    format!("#team:{}", group)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Monday, 2019-04-01 12:00 UTC
+    const MONDAY: u64 = 1_554_120_000;
+
+    #[test]
+    fn same_day() {
+        assert_eq!(working_days_between(MONDAY, MONDAY + 3600), 0);
+    }
+
+    #[test]
+    fn weekdays() {
+        assert_eq!(
+            working_days_between(MONDAY, MONDAY + 4 * SECONDS_PER_DAY),
+            4
+        );
+    }
+
+    #[test]
+    fn weekends_are_skipped() {
+        // Monday to the following Monday
+        assert_eq!(
+            working_days_between(MONDAY, MONDAY + 7 * SECONDS_PER_DAY),
+            5
+        );
+        // Friday to Sunday
+        assert_eq!(
+            working_days_between(MONDAY + 4 * SECONDS_PER_DAY, MONDAY + 6 * SECONDS_PER_DAY),
+            0
+        );
+    }
+
+    #[test]
+    fn reversed() {
+        assert_eq!(working_days_between(MONDAY + SECONDS_PER_DAY, MONDAY), 0);
+    }
+}
@@ -0,0 +1,75 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref REVERTS_COMMIT_REGEX: Regex =
+        Regex::new(r"(?m)^This reverts commit ([0-9a-f]{7,40})\b").unwrap();
+    static ref FOOTER_REGEX: Regex = Regex::new(r"^[A-Za-z][\w-]*: \S").unwrap();
+}
+
+/// Find the revision reverted by a commit, as written by `git revert` and
+/// Gerrit's revert button.
+pub fn reverted_commit(commit_message: &str) -> Option<&str> {
+    REVERTS_COMMIT_REGEX
+        .captures(commit_message)
+        .and_then(|cap| cap.get(1))
+        .map(|m| m.as_str())
+}
+
+/// Extract the reason of a revert from its commit message, i.e. the body
+/// without the "This reverts commit" line and footers like `Change-Id`.
+pub fn revert_reason(commit_message: &str) -> Option<String> {
+    let lines: Vec<&str> = commit_message
+        .lines()
+        .skip(1)
+        .filter(|line| !REVERTS_COMMIT_REGEX.is_match(line))
+        .collect();
+    let body_len = lines
+        .iter()
+        .rposition(|line| !line.trim().is_empty() && !FOOTER_REGEX.is_match(line))
+        .map_or(0, |pos| pos + 1);
+    let reason = lines[..body_len].join("\n").trim().to_string();
+
+    if reason.is_empty() {
+        None
+    } else {
+        Some(reason)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COMMIT_MESSAGE: &str = "Revert \"Add frobnicator\"\n\nThis reverts commit 49a65998c02eda928559f2d0b586c20bc8e37b10.\n\nReason for revert: breaks the nightly build\n\nChange-Id: Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14\nSigned-off-by: Author <author@example.com>\n";
+
+    #[test]
+    fn find_reverted_commit() {
+        assert_eq!(
+            reverted_commit(COMMIT_MESSAGE),
+            Some("49a65998c02eda928559f2d0b586c20bc8e37b10")
+        );
+        assert_eq!(
+            reverted_commit("Add frobnicator\n\nChange-Id: I123\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn extract_reason() {
+        assert_eq!(
+            revert_reason(COMMIT_MESSAGE),
+            Some("Reason for revert: breaks the nightly build".to_string())
+        );
+    }
+
+    #[test]
+    fn no_reason() {
+        assert_eq!(
+            revert_reason(
+                "Revert \"Add frobnicator\"\n\nThis reverts commit 49a65998.\n\nChange-Id: I123\n"
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use futures::{stream::Fuse, stream::FuturesUnordered, Async, Future, Poll, Stream};
+
+use crate::outbox::{Entry, Recipient};
+
+/// Maximum number of messages sent at the same time, each to a different
+/// recipient.
+const MAX_CONCURRENT_SENDS: usize = 10;
+
+/// Maximum number of messages waiting for earlier messages to the same
+/// recipient. No new messages are taken while more are waiting, which slows
+/// down the handling of events.
+const MAX_QUEUED_MESSAGES: usize = 100;
+
+/// What became of a message after an attempt to send it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// The message was sent, or given up on.
+    Done,
+    /// The message is sent again later, when it is taken from the stream once
+    /// more.
+    Retry,
+}
+
+/// Send messages to different recipients concurrently, but messages to the same
+/// recipient one after another in the order of the stream.
+///
+/// A message which failed and is retried later blocks its recipient: later
+/// messages to the recipient wait until the message is taken from the stream
+/// again and sent or given up on. Messages waiting for a retry don't count
+/// against the limit of queued messages, so that a single failing recipient
+/// doesn't stop the bot.
+pub fn send_ordered<St, F, Fut>(entries: St, send: F) -> OrderedSend<St, F, Fut>
+where
+    St: Stream<Item = Entry, Error = ()>,
+    F: FnMut(Entry) -> Fut,
+    Fut: Future<Item = Delivery, Error = ()>,
+{
+    OrderedSend::new(entries, send, MAX_CONCURRENT_SENDS, MAX_QUEUED_MESSAGES)
+}
+
+/// A send tagged with its recipient and message id.
+struct Send<Fut> {
+    recipient: Option<Recipient>,
+    id: u64,
+    future: Fut,
+}
+
+impl<Fut> Future for Send<Fut>
+where
+    Fut: Future<Item = Delivery, Error = ()>,
+{
+    type Item = (Recipient, u64, Delivery);
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, ()> {
+        let delivery = match self.future.poll() {
+            Ok(Async::NotReady) => return Ok(Async::NotReady),
+            Ok(Async::Ready(delivery)) => delivery,
+            // failed sends are handled by the send function
+            Err(()) => Delivery::Done,
+        };
+        let recipient = self.recipient.take().expect("send polled after completion");
+        Ok(Async::Ready((recipient, self.id, delivery)))
+    }
+}
+
+/// Future returned by [`send_ordered`].
+#[must_use = "futures do nothing unless polled"]
+pub struct OrderedSend<St, F, Fut>
+where
+    St: Stream,
+{
+    entries: Fuse<St>,
+    send: F,
+    max_concurrent: usize,
+    max_queued: usize,
+    /// messages waiting for the previous message to their recipient
+    queues: HashMap<Recipient, VecDeque<Entry>>,
+    /// queued messages, except for the ones of blocked recipients
+    queued: usize,
+    /// recipients with waiting messages and nothing being sent to them, in the
+    /// order they became ready
+    ready: VecDeque<Recipient>,
+    /// recipients with a message being sent to them
+    busy: HashSet<Recipient>,
+    /// recipients blocked by the id of a message waiting for a retry
+    blocked: HashMap<Recipient, u64>,
+    sends: FuturesUnordered<Send<Fut>>,
+}
+
+impl<St, F, Fut> OrderedSend<St, F, Fut>
+where
+    St: Stream<Item = Entry, Error = ()>,
+    F: FnMut(Entry) -> Fut,
+    Fut: Future<Item = Delivery, Error = ()>,
+{
+    fn new(entries: St, send: F, max_concurrent: usize, max_queued: usize) -> Self {
+        Self {
+            entries: entries.fuse(),
+            send,
+            max_concurrent,
+            max_queued,
+            queues: HashMap::new(),
+            queued: 0,
+            ready: VecDeque::new(),
+            busy: HashSet::new(),
+            blocked: HashMap::new(),
+            sends: FuturesUnordered::new(),
+        }
+    }
+
+    fn enqueue(&mut self, entry: Entry) {
+        let recipient = entry.recipient.clone();
+        if self.blocked.get(&recipient) == Some(&entry.id) {
+            // the retry unblocks the recipient and goes first
+            self.blocked.remove(&recipient);
+            let queue = self.queues.entry(recipient.clone()).or_default();
+            queue.push_front(entry);
+            self.queued += queue.len();
+            self.ready.push_back(recipient);
+            return;
+        }
+
+        let queue = self.queues.entry(recipient.clone()).or_default();
+        queue.push_back(entry);
+        if self.blocked.contains_key(&recipient) {
+            return;
+        }
+        self.queued += 1;
+        if queue.len() == 1 && !self.busy.contains(&recipient) {
+            self.ready.push_back(recipient);
+        }
+    }
+
+    /// Start sending to ready recipients. Returns if any send was started.
+    fn start_sends(&mut self) -> bool {
+        let mut started = false;
+        while self.sends.len() < self.max_concurrent {
+            let recipient = match self.ready.pop_front() {
+                Some(recipient) => recipient,
+                None => break,
+            };
+            let entry = match self.queues.get_mut(&recipient) {
+                Some(queue) => queue.pop_front().expect("ready recipient without messages"),
+                None => continue,
+            };
+            if self.queues[&recipient].is_empty() {
+                self.queues.remove(&recipient);
+            }
+            self.queued -= 1;
+            self.busy.insert(recipient.clone());
+            self.sends.push(Send {
+                recipient: Some(recipient),
+                id: entry.id,
+                future: (self.send)(entry),
+            });
+            started = true;
+        }
+        started
+    }
+
+    fn finish_send(&mut self, recipient: Recipient, id: u64, delivery: Delivery) {
+        self.busy.remove(&recipient);
+        match delivery {
+            Delivery::Done => {
+                if self.queues.contains_key(&recipient) {
+                    self.ready.push_back(recipient);
+                }
+            }
+            Delivery::Retry => {
+                if let Some(queue) = self.queues.get(&recipient) {
+                    self.queued -= queue.len();
+                }
+                self.blocked.insert(recipient, id);
+            }
+        }
+    }
+}
+
+impl<St, F, Fut> Future for OrderedSend<St, F, Fut>
+where
+    St: Stream<Item = Entry, Error = ()>,
+    F: FnMut(Entry) -> Fut,
+    Fut: Future<Item = Delivery, Error = ()>,
+{
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(), ()> {
+        loop {
+            let mut progress = false;
+
+            // only take new messages while there is room for them
+            while self.queued < self.max_queued {
+                match self.entries.poll()? {
+                    Async::Ready(Some(entry)) => {
+                        self.enqueue(entry);
+                        progress = true;
+                    }
+                    Async::Ready(None) | Async::NotReady => break,
+                }
+            }
+
+            // sending makes room for new messages
+            progress |= self.start_sends();
+
+            if let Async::Ready(Some((recipient, id, delivery))) = self.sends.poll()? {
+                self.finish_send(recipient, id, delivery);
+                progress = true;
+            }
+
+            // messages waiting for a retry are sent after a restart
+            if self.entries.is_done() && self.queued == 0 && self.sends.is_empty() {
+                return Ok(Async::Ready(()));
+            }
+            if !progress {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use futures::executor::{self, Notify, NotifyHandle};
+    use futures::stream;
+    use futures::sync::{mpsc, oneshot};
+
+    use gerritbot_spark as spark;
+
+    use super::*;
+
+    struct NoopNotify;
+
+    impl Notify for NoopNotify {
+        fn notify(&self, _id: usize) {}
+    }
+
+    fn entry(email: &str, message: &str) -> Entry {
+        entry_with_id(0, email, message)
+    }
+
+    fn entry_with_id(id: u64, email: &str, message: &str) -> Entry {
+        Entry {
+            id,
+            recipient: Recipient::Person(spark::Email::new(email.to_string())),
+            message: message.to_string(),
+            attempts: 0,
+            next_attempt: 0,
+            last_error: None,
+        }
+    }
+
+    /// Sends that are completed by the test, by message.
+    #[derive(Default, Clone)]
+    struct TestSends {
+        started: Rc<RefCell<Vec<String>>>,
+        pending: Rc<RefCell<HashMap<String, oneshot::Sender<Delivery>>>>,
+    }
+
+    impl TestSends {
+        fn send(&self, entry: Entry) -> impl Future<Item = Delivery, Error = ()> {
+            let (tx, rx) = oneshot::channel();
+            self.started.borrow_mut().push(entry.message.clone());
+            self.pending.borrow_mut().insert(entry.message, tx);
+            rx.map_err(|_| ())
+        }
+
+        fn finish(&self, message: &str, delivery: Delivery) {
+            let tx = self.pending.borrow_mut().remove(message).unwrap();
+            tx.send(delivery).unwrap();
+        }
+
+        fn complete(&self, message: &str) {
+            self.finish(message, Delivery::Done);
+        }
+
+        fn started(&self) -> Vec<String> {
+            self.started.borrow().clone()
+        }
+    }
+
+    #[test]
+    fn messages_to_the_same_recipient_are_sent_in_order() {
+        let sends = TestSends::default();
+        let entries = stream::iter_ok(vec![
+            entry("a@example.com", "a1"),
+            entry("a@example.com", "a2"),
+            entry("b@example.com", "b1"),
+            entry("a@example.com", "a3"),
+        ]);
+        let sends_for_future = sends.clone();
+        let mut task = executor::spawn(OrderedSend::new(
+            entries,
+            move |entry| sends_for_future.send(entry),
+            10,
+            10,
+        ));
+        let notify = NotifyHandle::from(Arc::new(NoopNotify));
+        let mut poll = || task.poll_future_notify(&notify, 0).unwrap();
+
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1"]);
+
+        sends.complete("b1");
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1"]);
+
+        sends.complete("a1");
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1", "a2"]);
+
+        sends.complete("a2");
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1", "a2", "a3"]);
+
+        sends.complete("a3");
+        assert_eq!(poll(), Async::Ready(()));
+    }
+
+    #[test]
+    fn new_messages_wait_for_full_queues() {
+        let sends = TestSends::default();
+        let taken = Rc::new(RefCell::new(Vec::new()));
+        let taken_for_stream = taken.clone();
+        let entries = stream::iter_ok(vec![
+            entry("a@example.com", "a1"),
+            entry("a@example.com", "a2"),
+            entry("b@example.com", "b1"),
+        ])
+        .inspect(move |entry: &Entry| taken_for_stream.borrow_mut().push(entry.message.clone()));
+        let sends_for_future = sends.clone();
+        let mut task = executor::spawn(OrderedSend::new(
+            entries,
+            move |entry| sends_for_future.send(entry),
+            10,
+            1,
+        ));
+        let notify = NotifyHandle::from(Arc::new(NoopNotify));
+        let mut poll = || task.poll_future_notify(&notify, 0).unwrap();
+
+        // a2 waits for a1, so b1 is not taken yet
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(*taken.borrow(), vec!["a1", "a2"]);
+        assert_eq!(sends.started(), vec!["a1"]);
+
+        sends.complete("a1");
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(*taken.borrow(), vec!["a1", "a2", "b1"]);
+        assert_eq!(sends.started(), vec!["a1", "a2", "b1"]);
+
+        sends.complete("a2");
+        sends.complete("b1");
+        assert_eq!(poll(), Async::Ready(()));
+    }
+
+    #[test]
+    fn failed_messages_block_their_recipient_until_retried() {
+        let sends = TestSends::default();
+        let (tx, entries) = mpsc::unbounded();
+        let sends_for_future = sends.clone();
+        let mut task = executor::spawn(OrderedSend::new(
+            entries,
+            move |entry| sends_for_future.send(entry),
+            10,
+            1,
+        ));
+        let notify = NotifyHandle::from(Arc::new(NoopNotify));
+        let mut poll = || task.poll_future_notify(&notify, 0).unwrap();
+
+        tx.unbounded_send(entry_with_id(1, "a@example.com", "a1"))
+            .unwrap();
+        assert_eq!(poll(), Async::NotReady);
+        sends.finish("a1", Delivery::Retry);
+        assert_eq!(poll(), Async::NotReady);
+
+        // later messages wait for the retry without filling the queue
+        tx.unbounded_send(entry_with_id(2, "a@example.com", "a2"))
+            .unwrap();
+        tx.unbounded_send(entry_with_id(3, "a@example.com", "a3"))
+            .unwrap();
+        tx.unbounded_send(entry_with_id(4, "b@example.com", "b1"))
+            .unwrap();
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1"]);
+        sends.complete("b1");
+
+        tx.unbounded_send(entry_with_id(1, "a@example.com", "a1"))
+            .unwrap();
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1", "a1"]);
+
+        sends.complete("a1");
+        assert_eq!(poll(), Async::NotReady);
+        sends.complete("a2");
+        assert_eq!(poll(), Async::NotReady);
+        assert_eq!(sends.started(), vec!["a1", "b1", "a1", "a2", "a3"]);
+
+        sends.complete("a3");
+        drop(tx);
+        assert_eq!(poll(), Async::Ready(()));
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::{Arc, RwLock};
+
+use regex::Regex;
+
+use gerritbot_gerrit as gerrit;
+
+use crate::args::ServiceAccountsConfig;
+
+/// Tells human Gerrit accounts apart from service accounts like CI or linters.
+///
+/// Service accounts are configured by username or email, by regexes matching
+/// the username, or by membership of a Gerrit group which is fetched
+/// separately. Clones share the group members, so they can be updated while
+/// the bot is running.
+#[derive(Debug, Clone)]
+pub struct ServiceAccounts {
+    accounts: Arc<Vec<String>>,
+    regexes: Arc<Vec<Regex>>,
+    group_members: Arc<RwLock<Vec<gerrit::User>>>,
+}
+
+impl Default for ServiceAccounts {
+    /// Without configuration every username containing "bot" is considered a
+    /// service account.
+    fn default() -> Self {
+        Self::new(Vec::new(), vec![Regex::new("bot").unwrap()])
+    }
+}
+
+impl ServiceAccounts {
+    pub fn new(accounts: Vec<String>, regexes: Vec<Regex>) -> Self {
+        Self {
+            accounts: Arc::new(accounts),
+            regexes: Arc::new(regexes),
+            group_members: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub fn from_config(config: &ServiceAccountsConfig) -> Result<Self, String> {
+        let regexes = config
+            .regexes
+            .iter()
+            .map(|regex| Regex::new(regex).map_err(|e| format!("invalid regex `{}`: {}", regex, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(config.accounts.clone(), regexes))
+    }
+
+    /// Replace the members of the configured Gerrit group.
+    pub fn set_group_members(&self, members: Vec<gerrit::User>) {
+        *self.group_members.write().unwrap() = members;
+    }
+
+    pub fn is_human(&self, user: &gerrit::User) -> bool {
+        !self.is_service_account(user)
+    }
+
+    fn is_service_account(&self, user: &gerrit::User) -> bool {
+        let matches_account = |account: &str| {
+            user.username.iter().any(|username| username == account)
+                || user
+                    .email
+                    .iter()
+                    .any(|email| email.eq_ignore_ascii_case(account))
+        };
+
+        self.accounts.iter().any(|account| matches_account(account))
+            || self.regexes.iter().any(|regex| {
+                user.username
+                    .iter()
+                    .any(|username| regex.is_match(username))
+            })
+            || self.group_members.read().unwrap().iter().any(|member| {
+                member
+                    .username
+                    .iter()
+                    .any(|username| matches_account(username))
+                    || member.email.iter().any(|email| matches_account(email))
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn user(username: &str, email: &str) -> gerrit::User {
+        gerrit::User {
+            name: None,
+            username: Some(username.to_string()),
+            email: Some(email.to_string()),
+        }
+    }
+
+    #[test]
+    fn default_matches_bot_usernames() {
+        let accounts = ServiceAccounts::default();
+        assert!(!accounts.is_human(&user("ci-bot", "ci@example.com")));
+        assert!(accounts.is_human(&user("jdoe", "bot@example.com")));
+    }
+
+    #[test]
+    fn configured_accounts() {
+        let accounts = ServiceAccounts::new(
+            vec!["jenkins".to_string(), "linter@example.com".to_string()],
+            vec![Regex::new("^ci-").unwrap()],
+        );
+        assert!(accounts.is_human(&user("abbott", "abbott@example.com")));
+        assert!(!accounts.is_human(&user("jenkins", "ci@example.com")));
+        assert!(!accounts.is_human(&user("lint", "Linter@example.com")));
+        assert!(!accounts.is_human(&user("ci-nightly", "nightly@example.com")));
+    }
+
+    #[test]
+    fn group_members_are_shared_between_clones() {
+        let accounts = ServiceAccounts::new(Vec::new(), Vec::new());
+        let other = accounts.clone();
+        assert!(other.is_human(&user("rebaser", "rebaser@example.com")));
+
+        accounts.set_group_members(vec![gerrit::User {
+            name: None,
+            username: None,
+            email: Some("rebaser@example.com".to_string()),
+        }]);
+        assert!(!other.is_human(&user("rebaser", "rebaser@example.com")));
+    }
+}
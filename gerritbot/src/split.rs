@@ -0,0 +1,182 @@
+/// Maximum length in bytes of a message sent to Webex Teams. Longer messages
+/// are rejected, the documented limit being 7439 bytes.
+pub const MAX_MESSAGE_LENGTH: usize = 7000;
+
+/// Room left in every part of a split message for its number.
+const PART_NUMBER_LENGTH: usize = 16;
+
+/// Split a message which is too long into numbered parts.
+///
+/// Parts end at markdown blocks, i.e. empty lines outside of code blocks.
+/// Blocks which don't fit into a part are split at lines, and lines at
+/// characters, which might break their formatting.
+pub fn split_message(message: &str, max_length: usize) -> Vec<String> {
+    if message.len() <= max_length {
+        return vec![message.to_string()];
+    }
+
+    let max_part_length = max_length.saturating_sub(PART_NUMBER_LENGTH).max(1);
+    let pieces: Vec<String> = blocks(message)
+        .into_iter()
+        .flat_map(|block| {
+            if block.len() <= max_part_length {
+                vec![block.to_string()]
+            } else {
+                let lines = block
+                    .split('\n')
+                    .flat_map(|line| split_at_chars(line, max_part_length));
+                pack(lines, "\n", max_part_length)
+            }
+        })
+        .collect();
+    let parts = pack(pieces.iter().map(String::as_str), "\n\n", max_part_length);
+
+    let count = parts.len();
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| format!("({}/{})\n\n{}", i + 1, count, part))
+        .collect()
+}
+
+/// Markdown blocks of the message, keeping code blocks together.
+fn blocks(message: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut code_block_start = None;
+    let mut start = 0;
+
+    for segment in message.split("\n\n") {
+        let end = start + segment.len();
+        let fences = segment
+            .lines()
+            .filter(|line| line.trim_start().starts_with("```"))
+            .count();
+        let block_start = code_block_start.unwrap_or(start);
+        let in_code_block = code_block_start.is_some() != (fences % 2 == 1);
+
+        if in_code_block {
+            code_block_start = Some(block_start);
+        } else {
+            blocks.push(&message[block_start..end]);
+            code_block_start = None;
+        }
+        start = end + 2;
+    }
+
+    // unterminated code block
+    if let Some(block_start) = code_block_start {
+        blocks.push(&message[block_start..]);
+    }
+
+    blocks
+}
+
+/// Split a line which is too long at character boundaries.
+fn split_at_chars(mut line: &str, max_length: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    while line.len() > max_length {
+        let mut end = max_length;
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            // a single character longer than the limit
+            end = line.chars().next().map_or(line.len(), char::len_utf8);
+        }
+        let (piece, rest) = line.split_at(end);
+        pieces.push(piece);
+        line = rest;
+    }
+    pieces.push(line);
+    pieces
+}
+
+/// Join as many pieces as fit into each part.
+fn pack<'a, I>(pieces: I, separator: &str, max_length: usize) -> Vec<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut parts = Vec::new();
+    let mut part: Option<String> = None;
+
+    for piece in pieces {
+        part = Some(match part.take() {
+            Some(mut part) if part.len() + separator.len() + piece.len() <= max_length => {
+                part.push_str(separator);
+                part.push_str(piece);
+                part
+            }
+            Some(full_part) => {
+                parts.push(full_part);
+                piece.to_string()
+            }
+            None => piece.to_string(),
+        });
+    }
+
+    parts.extend(part);
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn short_messages_are_not_split() {
+        assert_eq!(split_message("hello\n\nworld", 100), vec!["hello\n\nworld"]);
+    }
+
+    #[test]
+    fn messages_are_split_at_blocks() {
+        let message = format!(
+            "{}\n\n{}\n\n{}",
+            "a".repeat(20),
+            "b".repeat(20),
+            "c".repeat(20)
+        );
+        assert_eq!(
+            split_message(&message, 60),
+            vec![
+                format!("(1/2)\n\n{}\n\n{}", "a".repeat(20), "b".repeat(20)),
+                format!("(2/2)\n\n{}", "c".repeat(20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_kept_together() {
+        let code = "```\n  1 | x\n\n  3 | y\n```";
+        let message = format!("{}\n\n{}\n\n{}", "a".repeat(10), code, "b".repeat(10));
+        assert_eq!(
+            split_message(&message, 46),
+            vec![
+                format!("(1/3)\n\n{}", "a".repeat(10)),
+                format!("(2/3)\n\n{}", code),
+                format!("(3/3)\n\n{}", "b".repeat(10)),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_blocks_are_split_at_lines_and_chars() {
+        let message = format!(
+            "> {}\n> {}\n\n{}",
+            "a".repeat(20),
+            "b".repeat(20),
+            "ä".repeat(30)
+        );
+        let parts = split_message(&message, 40);
+        assert_eq!(
+            parts,
+            vec![
+                format!("(1/5)\n\n> {}", "a".repeat(20)),
+                format!("(2/5)\n\n> {}", "b".repeat(20)),
+                format!("(3/5)\n\n{}", "ä".repeat(12)),
+                format!("(4/5)\n\n{}", "ä".repeat(12)),
+                format!("(5/5)\n\n{}", "ä".repeat(6)),
+            ]
+        );
+        assert!(parts.iter().all(|part| part.len() <= 40));
+    }
+}
@@ -1,25 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+use gerritbot_gerrit as gerrit;
 use gerritbot_spark as spark;
 
 use super::BotError;
 
+mod away;
 mod filter;
 mod flags;
+mod mute;
 mod user;
+mod watch;
 
-use filter::Filter;
+pub use away::{Away, Date};
+use filter::DEFAULT_FILTER_NAME;
+pub use filter::{Filter, FilterAction};
 pub use flags::{UserFlag, NOTIFICATION_FLAGS, REVIEW_COMMENT_FLAGS};
-pub use user::User;
+pub use mute::MuteRule;
+pub use user::{AccountPreference, User};
+pub use watch::Watch;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct State {
     users: Vec<User>,
+    /// Time of the last reminder per change and recipient.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    reminders: BTreeMap<u32, BTreeMap<String, u64>>,
+    /// Last known mergeability of open changes.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    mergeable: BTreeMap<u32, bool>,
     #[serde(skip_serializing, skip_deserializing)]
     email_index: HashMap<spark::Email, usize>,
 }
@@ -34,15 +49,26 @@ impl State {
         P: AsRef<Path>,
     {
         let f = File::open(filename)?;
+        Self::from_reader(f)
+    }
 
-        serde_json::from_reader(f)
+    fn from_reader<R: Read>(reader: R) -> Result<Self, BotError> {
+        serde_json::from_reader(reader)
             .map(|mut state: Self| {
+                state.migrate_users();
                 state.index_users();
                 state
             })
             .map_err(BotError::from)
     }
 
+    /// Upgrade users loaded from older state files.
+    fn migrate_users(&mut self) {
+        for user in &mut self.users {
+            user.migrate_legacy_filter();
+        }
+    }
+
     fn index_users(&mut self) {
         for (user_pos, user) in self.users.iter().enumerate() {
             self.email_index.insert(user.email().to_owned(), user_pos);
@@ -101,6 +127,40 @@ impl State {
         self.email_index.get(email).map(|pos| &self.users[*pos])
     }
 
+    /// Find a user by a Gerrit account: an email address, or a Gerrit
+    /// username seen together with the email address of the user in events.
+    pub fn find_user_by_account(&self, account: &str) -> Option<&User> {
+        if account.contains('@') {
+            return self
+                .users
+                .iter()
+                .find(|user| user.email().as_str().eq_ignore_ascii_case(account));
+        }
+
+        self.users.iter().find(|user| match user.gerrit_username() {
+            Some(username) => username.eq_ignore_ascii_case(account),
+            None => false,
+        })
+    }
+
+    /// Remember the Gerrit username of an account for mentions, if the user is
+    /// known. Returns true if the state changed.
+    pub fn learn_gerrit_username(&mut self, email: &spark::EmailRef, username: &str) -> bool {
+        if self.find_user(email).is_none() {
+            return false;
+        }
+        let mut changed = false;
+        for user in &mut self.users {
+            if user.email() == email {
+                changed |= user.set_gerrit_username(Some(username));
+            } else if user.gerrit_username() == Some(username) {
+                // the account changed its email
+                changed |= user.set_gerrit_username(None);
+            }
+        }
+        changed
+    }
+
     pub fn reset_flags(&mut self, email: &spark::EmailRef) -> &User {
         let user = self.find_or_add_user_by_email(email);
         user.reset_flags();
@@ -123,15 +183,48 @@ impl State {
         &mut self,
         email: &spark::EmailRef,
         filter: &str,
+    ) -> Result<(), regex::Error> {
+        self.set_named_filter(email, DEFAULT_FILTER_NAME, FilterAction::Deny, filter)
+    }
+
+    /// Add an enabled filter or replace an existing one with the same name.
+    pub fn set_named_filter(
+        &mut self,
+        email: &spark::EmailRef,
+        name: &str,
+        action: FilterAction,
+        filter: &str,
     ) -> Result<(), regex::Error> {
         let user = self.find_or_add_user_by_email(email);
         user.set_filter(Filter {
+            name: name.to_string(),
+            action,
             regex: Regex::new(filter)?,
             enabled: true,
         });
         Ok(())
     }
 
+    /// Remove the filter with the given name. Returns false if there was none.
+    pub fn remove_filter(&mut self, email: &spark::EmailRef, name: &str) -> bool {
+        self.find_user_mut(email)
+            .map(|user| user.remove_filter(name))
+            .unwrap_or(false)
+    }
+
+    /// Enable or disable the filter with the given name. Returns false if
+    /// there is none.
+    pub fn enable_filter(&mut self, email: &spark::EmailRef, name: &str, enabled: bool) -> bool {
+        self.find_user_mut(email)
+            .map(|user| user.enable_filter(name, enabled))
+            .unwrap_or(false)
+    }
+
+    /// Get all filters of the user.
+    pub fn get_filters(&self, email: &spark::EmailRef) -> &[Filter] {
+        self.find_user(email).map_or(&[], |user| user.filters())
+    }
+
     /// Get the filter for the given user given the user exists and has a filter
     /// configured.
     pub fn get_filter(&self, email: &spark::EmailRef) -> Option<(&str, bool)> {
@@ -157,14 +250,182 @@ impl State {
             .ok_or(())
     }
 
+    pub fn add_watch(&mut self, email: &spark::EmailRef, watch: Watch) -> bool {
+        self.find_or_add_user_by_email(email).add_watch(watch)
+    }
+
+    /// Remove the given watch or all watches of the user. Returns the number of
+    /// removed watches.
+    pub fn remove_watches(&mut self, email: &spark::EmailRef, watch: Option<&Watch>) -> usize {
+        self.find_user_mut(email)
+            .map(|user| user.remove_watches(watch))
+            .unwrap_or(0)
+    }
+
+    pub fn add_mute(&mut self, email: &spark::EmailRef, rule: MuteRule) -> bool {
+        self.find_or_add_user_by_email(email).add_mute(rule)
+    }
+
+    /// Remove the given mute rule or all mute rules of the user. Returns the
+    /// number of removed rules.
+    pub fn remove_mutes(&mut self, email: &spark::EmailRef, rule: Option<&MuteRule>) -> usize {
+        self.find_user_mut(email)
+            .map(|user| user.remove_mutes(rule))
+            .unwrap_or(0)
+    }
+
+    /// Set or reset the preference for a Gerrit account. Returns false if
+    /// nothing changed.
+    pub fn set_account_preference(
+        &mut self,
+        email: &spark::EmailRef,
+        account: &str,
+        preference: Option<AccountPreference>,
+    ) -> bool {
+        self.find_or_add_user_by_email(email)
+            .set_account_preference(account, preference)
+    }
+
+    /// Set or reset the maximum number of inline comments shown to the user.
+    /// Returns false if nothing changed.
+    pub fn set_max_inline_comments(&mut self, email: &spark::EmailRef, max: Option<u32>) -> bool {
+        self.find_or_add_user_by_email(email)
+            .set_max_inline_comments(max)
+    }
+
+    /// Set or clear the out of office status of the user. Returns the previous
+    /// status.
+    pub fn set_away(&mut self, email: &spark::EmailRef, away: Option<Away>) -> Option<Away> {
+        match away {
+            Some(away) => self.find_or_add_user_by_email(email).set_away(Some(away)),
+            None => self
+                .find_user_mut(email)
+                .and_then(|user| user.set_away(None)),
+        }
+    }
+
+    /// Keep a notification paused while the user is out of office for the
+    /// summary. Returns false if it was dropped.
+    pub fn add_missed(&mut self, email: &spark::EmailRef, message: String) -> bool {
+        match self.find_user_mut(email).and_then(User::away_mut) {
+            Some(away) => away.add_missed(message),
+            None => false,
+        }
+    }
+
+    /// End the out of office status of all users that are back at the given
+    /// unix timestamp.
+    pub fn end_expired_away(&mut self, now: u64) -> Vec<(spark::Email, Away)> {
+        self.users
+            .iter_mut()
+            .filter(|user| matches!(user.away(), Some(away) if !away.is_active(now)))
+            .filter_map(|user| Some((user.email().to_owned(), user.set_away(None)?)))
+            .collect()
+    }
+
+    /// Check if the last reminder about the change sent to the recipient is at
+    /// least `interval` seconds old.
+    pub fn reminder_due(&self, change: u32, recipient: &str, now: u64, interval: u64) -> bool {
+        let last = self
+            .reminders
+            .get(&change)
+            .and_then(|recipients| recipients.get(recipient));
+        !matches!(last, Some(last) if last + interval > now)
+    }
+
+    pub fn set_reminded(&mut self, change: u32, recipient: &str, now: u64) {
+        self.reminders
+            .entry(change)
+            .or_default()
+            .insert(recipient.to_string(), now);
+    }
+
+    /// Forget the reminders of changes which are not stale anymore. Returns
+    /// false if there was nothing to forget.
+    pub fn retain_reminders(&mut self, stale_changes: &[u32]) -> bool {
+        let count = self.reminders.len();
+        self.reminders
+            .retain(|change, _| stale_changes.contains(change));
+        count != self.reminders.len()
+    }
+
+    /// Remember whether a change can be merged. Returns the previously known
+    /// mergeability.
+    pub fn set_mergeable(&mut self, change: u32, mergeable: bool) -> Option<bool> {
+        self.mergeable.insert(change, mergeable)
+    }
+
+    /// Remove follows, mute rules and the mergeability of a change that was
+    /// merged or abandoned. Returns false if there was nothing to remove.
+    pub fn expire_change(&mut self, number: u32) -> bool {
+        let mut expired = self.mergeable.remove(&number).is_some();
+        for user in &mut self.users {
+            expired |= user.expire_change(number);
+        }
+        expired
+    }
+
+    /// Return enabled users watching the change of the given event together
+    /// with the first matching watch.
+    pub fn watching_users<'a, E: gerrit::ChangeEvent + ?Sized>(
+        &'a self,
+        event: &'a E,
+    ) -> impl Iterator<Item = (&'a User, &'a Watch)> + 'a {
+        self.users
+            .iter()
+            .filter(|user| user.is_enabled())
+            .filter_map(move |user| {
+                user.watches()
+                    .iter()
+                    .find(move |watch| watch.matches(event))
+                    .map(|watch| (user, watch))
+            })
+    }
+
+    /// Return enabled users watching the ref of the given update together
+    /// with the first matching watch.
+    pub fn ref_watching_users<'a>(
+        &'a self,
+        update: &'a gerrit::RefUpdate,
+    ) -> impl Iterator<Item = (&'a User, &'a Watch)> + 'a {
+        self.users
+            .iter()
+            .filter(|user| user.is_enabled())
+            .filter_map(move |user| {
+                user.watches()
+                    .iter()
+                    .find(move |watch| watch.matches_ref(update))
+                    .map(|watch| (user, watch))
+            })
+    }
+
+    /// Whether any enabled user watches file paths, directly or with a query,
+    /// which requires fetching the files of patchsets.
+    pub fn has_file_watches(&self) -> bool {
+        self.users
+            .iter()
+            .filter(|user| user.is_enabled())
+            .any(|user| user.watches().iter().any(Watch::uses_files))
+    }
+
     pub fn users(&self) -> impl Iterator<Item = &User> + Clone {
         self.users.iter()
     }
 
     pub fn is_filtered(&self, user: &User, msg: &str) -> bool {
-        user.filter()
-            .map(|f| f.enabled && f.regex.is_match(msg))
-            .unwrap_or(false)
+        user.is_filtered(msg)
+    }
+
+    /// Check the user's mute rules. Unlike filters they are applied to the
+    /// event before formatting it.
+    pub fn is_muted<E: gerrit::ChangeEvent + ?Sized>(&self, user: &User, event: &E) -> bool {
+        user.is_muted(event)
+    }
+
+    /// Check the user's mute rules for things that are not caused by an
+    /// event, like reminders.
+    pub fn is_change_muted(&self, user: &User, change: &gerrit::Change) -> bool {
+        user.is_change_muted(change)
     }
 }
 
@@ -212,6 +473,46 @@ mod test {
         assert_eq!(user.unwrap().email(), EmailRef::new("some_2@example.com"));
     }
 
+    #[test]
+    fn find_user_by_account() {
+        let mut state = State::new();
+        state.add_user(EmailRef::new("alice@example.com"));
+        state.add_user(EmailRef::new("bob@example.com"));
+        state.add_user(EmailRef::new("bob@example.org"));
+        assert!(state.learn_gerrit_username(EmailRef::new("alice@example.com"), "asmith"));
+        assert!(!state.learn_gerrit_username(EmailRef::new("alice@example.com"), "asmith"));
+        assert!(state.learn_gerrit_username(EmailRef::new("bob@example.org"), "bob"));
+        assert!(!state.learn_gerrit_username(EmailRef::new("carol@example.com"), "carol"));
+
+        let find = |state: &State, account| {
+            state
+                .find_user_by_account(account)
+                .map(|u| u.email().to_owned())
+        };
+        assert_eq!(
+            find(&state, "ASmith"),
+            Some(EmailRef::new("alice@example.com").to_owned())
+        );
+        // the local part of the email is not a username
+        assert_eq!(find(&state, "alice"), None);
+        assert_eq!(
+            find(&state, "ALICE@example.com"),
+            Some(EmailRef::new("alice@example.com").to_owned())
+        );
+        assert_eq!(
+            find(&state, "bob"),
+            Some(EmailRef::new("bob@example.org").to_owned())
+        );
+        assert_eq!(find(&state, "carol"), None);
+
+        // the account of bob changed its email
+        assert!(state.learn_gerrit_username(EmailRef::new("bob@example.com"), "bob"));
+        assert_eq!(
+            find(&state, "bob"),
+            Some(EmailRef::new("bob@example.com").to_owned())
+        );
+    }
+
     #[test]
     fn add_invalid_filter_for_existing_user() {
         let mut state = State::new();
@@ -295,6 +596,62 @@ mod test {
         assert_eq!(res, Ok(".*some_word.*"));
     }
 
+    #[test]
+    fn add_and_remove_watches() {
+        let mut state = State::new();
+        let project: Watch = "project:tools".parse().unwrap();
+        let hashtag: Watch = "hashtag:refactoring".parse().unwrap();
+
+        assert!(state.add_watch(EmailRef::new("some@example.com"), project.clone()));
+        assert!(!state.add_watch(EmailRef::new("some@example.com"), project.clone()));
+        assert!(state.add_watch(EmailRef::new("some@example.com"), hashtag.clone()));
+        assert_eq!(
+            state.users[0].watches(),
+            &[project.clone(), hashtag.clone()]
+        );
+
+        assert_eq!(
+            state.remove_watches(EmailRef::new("some@example.com"), Some(&project)),
+            1
+        );
+        assert_eq!(state.users[0].watches(), &[hashtag]);
+        assert_eq!(
+            state.remove_watches(EmailRef::new("some@example.com"), Some(&project)),
+            0
+        );
+        assert_eq!(
+            state.remove_watches(EmailRef::new("some@example.com"), None),
+            1
+        );
+        assert!(state.users[0].watches().is_empty());
+        assert_eq!(
+            state.remove_watches(EmailRef::new("other@example.com"), None),
+            0
+        );
+    }
+
+    #[test]
+    fn file_watches() {
+        let mut state = State::new();
+        let email = EmailRef::new("some@example.com");
+        state.add_watch(email, "project:tools".parse().unwrap());
+        state.add_watch(email, "query project:tools -is:wip".parse().unwrap());
+        assert!(!state.has_file_watches());
+
+        let query: Watch = "query project:tools (file:src/net OR topic:net)"
+            .parse()
+            .unwrap();
+        state.add_watch(email, query.clone());
+        assert!(state.has_file_watches());
+
+        state.remove_watches(email, Some(&query));
+        state.add_watch(email, "project:tools path:src/net/**".parse().unwrap());
+        assert!(state.has_file_watches());
+
+        state.enable(email, false);
+        assert!(!state.has_file_watches());
+    }
+
     #[test]
     fn enable_non_configured_filter_for_existing_user() {
         let mut state = State::new();
@@ -305,4 +662,97 @@ mod test {
         let res = state.enable_and_get_filter(EmailRef::new("some@example.com"), false);
         assert_eq!(res, Err(()));
     }
+
+    #[test]
+    fn named_filters_with_allow_and_deny() {
+        let mut state = State::new();
+        let email = EmailRef::new("some@example.com");
+        state
+            .set_named_filter(email, "ci-noise", FilterAction::Deny, "CI Bot")
+            .unwrap();
+        state
+            .set_named_filter(email, "tools", FilterAction::Deny, "tools")
+            .unwrap();
+        state
+            .set_named_filter(email, "failures", FilterAction::Allow, "FAILURE")
+            .unwrap();
+        assert_eq!(state.get_filters(email).len(), 3);
+
+        let user = state.find_user(email).unwrap().clone();
+        assert!(state.is_filtered(&user, "Verified+1 from CI Bot"));
+        assert!(state.is_filtered(&user, "Change in tools"));
+        assert!(!state.is_filtered(&user, "Verified-1 from CI Bot: FAILURE"));
+        assert!(!state.is_filtered(&user, "Code-Review+2 from Alice"));
+
+        // replacing a filter keeps its position
+        state
+            .set_named_filter(email, "ci-noise", FilterAction::Deny, "Jenkins")
+            .unwrap();
+        let names: Vec<_> = state
+            .get_filters(email)
+            .iter()
+            .map(|f| (f.name.as_str(), f.regex.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("ci-noise", "Jenkins"),
+                ("tools", "tools"),
+                ("failures", "FAILURE")
+            ]
+        );
+
+        assert!(state.remove_filter(email, "tools"));
+        assert!(!state.remove_filter(email, "tools"));
+        assert!(!state.remove_filter(EmailRef::new("other@example.com"), "tools"));
+        assert_eq!(state.get_filters(email).len(), 2);
+    }
+
+    #[test]
+    fn migrate_legacy_filter() {
+        let json = r#"{"users":[{"email":"some@example.com","enabled":true,"filter":{"regex":"CI Bot","enabled":false}}]}"#;
+        let state = State::from_reader(json.as_bytes()).expect("failed to load state");
+        assert_eq!(
+            state.get_filter(EmailRef::new("some@example.com")),
+            Some(("CI Bot", false))
+        );
+
+        let filters = state.get_filters(EmailRef::new("some@example.com"));
+        assert_eq!(filters.len(), 1);
+        assert_eq!(filters[0].name, "default");
+        assert_eq!(filters[0].action, FilterAction::Deny);
+
+        // the legacy filter is written back as a named filter
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains(r#""filter""#));
+        let state = State::from_reader(json.as_bytes()).expect("failed to reload state");
+        assert_eq!(
+            state.get_filter(EmailRef::new("some@example.com")),
+            Some(("CI Bot", false))
+        );
+    }
+
+    #[test]
+    fn expire_change() {
+        let mut state = State::new();
+        let email = EmailRef::new("some@example.com");
+        let other_email = EmailRef::new("other@example.com");
+        state.add_watch(email, Watch::Change(1));
+        state.add_watch(email, Watch::Change(2));
+        state.add_mute(email, MuteRule::change(1));
+        state.add_mute(email, MuteRule::topic("foo"));
+        state.add_mute(other_email, "actor=ci-bot change=1".parse().unwrap());
+
+        assert!(state.expire_change(1));
+        assert!(!state.expire_change(1));
+        assert_eq!(
+            state.find_user(email).unwrap().watches(),
+            &[Watch::Change(2)]
+        );
+        assert_eq!(
+            state.find_user(email).unwrap().mutes(),
+            &[MuteRule::topic("foo")]
+        );
+        assert!(state.find_user(other_email).unwrap().mutes().is_empty());
+    }
 }
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::reminders::SECONDS_PER_DAY;
+
+/// Maximum number of paused notifications kept for the summary.
+const MAX_MISSED: usize = 50;
+
+/// A calendar date (UTC) written as `YYYY-MM-DD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    year: u32,
+    month: u32,
+    day: u32,
+}
+
+impl Date {
+    /// Unix timestamp of the start of the day.
+    pub fn timestamp(self) -> u64 {
+        // days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+        let year = u64::from(if self.month <= 2 {
+            self.year - 1
+        } else {
+            self.year
+        });
+        let month = u64::from(self.month);
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+            + u64::from(self.day)
+            - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        days * SECONDS_PER_DAY
+    }
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid date `{}`, expected YYYY-MM-DD", s);
+        let mut parts = s.splitn(3, '-').map(|part| part.parse::<u32>());
+        let (year, month, day) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) => (year, month, day),
+            _ => return Err(invalid()),
+        };
+        let leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days_in_month = match month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return Err(invalid()),
+        };
+        if year < 1970 || day == 0 || day > days_in_month {
+            return Err(invalid());
+        }
+        Ok(Self { year, month, day })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Out of office status of a user. Notifications are paused until the given
+/// date.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Away {
+    pub until: Date,
+    /// free text shown to owners adding the user as reviewer, e.g. a backup
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub note: Option<String>,
+    /// collect paused notifications and send them on return
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub summarize: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub missed: Vec<String>,
+}
+
+impl Away {
+    pub fn new(until: Date, note: Option<String>, summarize: bool) -> Self {
+        Self {
+            until,
+            note,
+            summarize,
+            missed: Vec::new(),
+        }
+    }
+
+    /// Keep a paused notification for the summary. Returns false if it was
+    /// dropped.
+    pub fn add_missed(&mut self, message: String) -> bool {
+        if !self.summarize || self.missed.len() >= MAX_MISSED {
+            return false;
+        }
+        self.missed.push(message);
+        true
+    }
+
+    /// Check if the user is still away at the given unix timestamp.
+    pub fn is_active(&self, now: u64) -> bool {
+        now < self.until.timestamp()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let date: Date = "2026-11-02".parse().unwrap();
+        assert_eq!(date.to_string(), "2026-11-02");
+        assert_eq!(
+            "2024-2-29".parse::<Date>().unwrap().to_string(),
+            "2024-02-29"
+        );
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert!("2026-11".parse::<Date>().is_err());
+        assert!("2026-13-01".parse::<Date>().is_err());
+        assert!("2023-02-29".parse::<Date>().is_err());
+        assert!("2026-11-02x".parse::<Date>().is_err());
+        assert!("tomorrow".parse::<Date>().is_err());
+    }
+
+    #[test]
+    fn timestamp() {
+        assert_eq!("1970-01-01".parse::<Date>().unwrap().timestamp(), 0);
+        assert_eq!(
+            "2019-04-01".parse::<Date>().unwrap().timestamp(),
+            1_554_076_800
+        );
+        assert_eq!(
+            "2026-11-02".parse::<Date>().unwrap().timestamp(),
+            1_793_577_600
+        );
+    }
+
+    #[test]
+    fn active_until_start_of_day() {
+        let away = Away::new("2019-04-01".parse().unwrap(), None, false);
+        assert!(away.is_active(1_554_076_799));
+        assert!(!away.is_active(1_554_076_800));
+    }
+}
@@ -1,49 +1,124 @@
+use std::borrow::Cow;
+use std::fmt;
+
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
+/// Name of the filter set with the plain `filter <regex>` command and of the
+/// filter migrated from the single filter of older state files.
+pub const DEFAULT_FILTER_NAME: &str = "default";
+
+/// What to do with a message matched by a filter. Allow filters take
+/// precedence over deny filters, i.e. a message is only filtered if it is
+/// matched by a deny filter and not by any allow filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    #[default]
+    Deny,
+    Allow,
+}
+
+impl fmt::Display for FilterAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterAction::Deny => f.write_str("deny"),
+            FilterAction::Allow => f.write_str("allow"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Filter {
+    pub name: String,
+    pub action: FilterAction,
     pub regex: Regex,
     pub enabled: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 struct FilterForSerialize<'a> {
-    regex: &'a str,
+    // missing for the legacy single filter
+    #[serde(default)]
+    name: Option<Cow<'a, str>>,
+    #[serde(default)]
+    action: FilterAction,
+    regex: Cow<'a, str>,
     enabled: bool,
 }
 
-/// Serialize the filter by storing the regex as a string.
-pub(super) fn serialize_filter<S>(filter: &Option<Filter>, serializer: S) -> Result<S::Ok, S::Error>
+impl<'a> From<&'a Filter> for FilterForSerialize<'a> {
+    fn from(f: &'a Filter) -> Self {
+        Self {
+            name: Some(Cow::Borrowed(&f.name)),
+            action: f.action,
+            regex: Cow::Borrowed(f.regex.as_str()),
+            enabled: f.enabled,
+        }
+    }
+}
+
+impl<'a> FilterForSerialize<'a> {
+    fn into_filter<E: serde::de::Error>(self) -> Result<Filter, E> {
+        let Self {
+            name,
+            action,
+            regex,
+            enabled,
+        } = self;
+
+        Regex::new(&regex)
+            .map(|regex| Filter {
+                name: name.map_or_else(|| DEFAULT_FILTER_NAME.to_string(), Cow::into_owned),
+                action,
+                regex,
+                enabled,
+            })
+            .map_err(|e| E::custom(format!("invalid regex: {}", e)))
+    }
+}
+
+/// Serialize the filters by storing the regexes as strings.
+pub(super) fn serialize_filters<S>(filters: &[Filter], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    filter
-        .as_ref()
-        .map(|f| FilterForSerialize {
-            regex: f.regex.as_str(),
-            enabled: f.enabled,
-        })
+    filters
+        .iter()
+        .map(FilterForSerialize::from)
+        .collect::<Vec<_>>()
         .serialize(serializer)
 }
 
-/// Deserialize the filter by compiling the regex.
+/// Deserialize the filters by compiling the regexes.
+pub(super) fn deserialize_filters<'de, D>(deserializer: D) -> Result<Vec<Filter>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<FilterForSerialize>::deserialize(deserializer)?
+        .into_iter()
+        .map(FilterForSerialize::into_filter)
+        .collect()
+}
+
+/// Deserialize the legacy single filter by compiling the regex.
 pub(super) fn deserialize_filter<'de, D>(deserializer: D) -> Result<Option<Filter>, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let maybe_filter = Option::<FilterForSerialize>::deserialize(deserializer)?;
-
-    maybe_filter
-        .map(|f| {
-            Regex::new(f.regex)
-                .map(|regex| Filter {
-                    regex,
-                    enabled: f.enabled,
-                })
-                .map_err(|e| {
-                    <D::Error as serde::de::Error>::custom(format!("invalid regex: {}", e))
-                })
-        })
+    Option::<FilterForSerialize>::deserialize(deserializer)?
+        .map(FilterForSerialize::into_filter)
         .transpose()
 }
+
+/// Check if a message is filtered, see [`FilterAction`].
+pub(super) fn is_filtered(filters: &[Filter], msg: &str) -> bool {
+    let matches = |action| {
+        filters
+            .iter()
+            .filter(|f| f.enabled && f.action == action)
+            .any(|f| f.regex.is_match(msg))
+    };
+
+    matches(FilterAction::Deny) && !matches(FilterAction::Allow)
+}
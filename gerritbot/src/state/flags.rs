@@ -21,6 +21,36 @@ pub enum UserFlag {
     NotifyChangeMerged,
     /// User wants notification messages for abandoned changes.
     NotifyChangeAbandoned,
+    /// User wants compact notification messages for votes of CI accounts.
+    NotifyCiResults,
+    /// User wants notification messages when their changes are reverted.
+    NotifyReverts,
+    /// User wants notification messages when somebody else uploads a patchset
+    /// to their change.
+    NotifyForeignUpload,
+    /// User wants notification messages when mentioned in a comment.
+    NotifyMentions,
+    /// User wants notification messages for replies to inline comment threads
+    /// they took part in.
+    NotifyThreadReplies,
+    /// User wants notification messages when their changes cannot be merged
+    /// anymore after other changes were merged.
+    NotifyMergeConflicts,
+    /// User wants notification messages when a change they review is not work
+    /// in progress or private anymore.
+    NotifyReadyForReview,
+    /// User wants notification messages when the topic of a change they are
+    /// involved in changes.
+    NotifyTopicChanged,
+    /// User wants notification messages when hashtags of a change they are
+    /// involved in change.
+    NotifyHashtagsChanged,
+    /// User wants notification messages when a vote on their change is
+    /// removed.
+    NotifyVoteDeleted,
+    /// User wants notification messages when removed as reviewer from a
+    /// change.
+    NotifyReviewerDeleted,
 }
 
 impl Display for UserFlag {
@@ -84,6 +114,64 @@ mod test_flag {
         UserFlag::NotifyReviewerAdded,
     );
 
+    test_from_to_string!(
+        notify_ci_results,
+        "notify_ci_results",
+        UserFlag::NotifyCiResults,
+    );
+
+    test_from_to_string!(notify_reverts, "notify_reverts", UserFlag::NotifyReverts);
+
+    test_from_to_string!(
+        notify_foreign_upload,
+        "notify_foreign_upload",
+        UserFlag::NotifyForeignUpload,
+    );
+
+    test_from_to_string!(notify_mentions, "notify_mentions", UserFlag::NotifyMentions);
+
+    test_from_to_string!(
+        notify_thread_replies,
+        "notify_thread_replies",
+        UserFlag::NotifyThreadReplies,
+    );
+
+    test_from_to_string!(
+        notify_merge_conflicts,
+        "notify_merge_conflicts",
+        UserFlag::NotifyMergeConflicts,
+    );
+
+    test_from_to_string!(
+        notify_ready_for_review,
+        "notify_ready_for_review",
+        UserFlag::NotifyReadyForReview,
+    );
+
+    test_from_to_string!(
+        notify_topic_changed,
+        "notify_topic_changed",
+        UserFlag::NotifyTopicChanged,
+    );
+
+    test_from_to_string!(
+        notify_hashtags_changed,
+        "notify_hashtags_changed",
+        UserFlag::NotifyHashtagsChanged,
+    );
+
+    test_from_to_string!(
+        notify_vote_deleted,
+        "notify_vote_deleted",
+        UserFlag::NotifyVoteDeleted,
+    );
+
+    test_from_to_string!(
+        notify_reviewer_deleted,
+        "notify_reviewer_deleted",
+        UserFlag::NotifyReviewerDeleted,
+    );
+
     test_parse_fail!(unknown_flag, "unknown_flag");
     test_parse_fail!(integer, "123");
     test_parse_fail!(quotation_mark, "\"");
@@ -112,6 +200,17 @@ pub const NOTIFICATION_FLAGS: &[UserFlag] = &[
     UserFlag::NotifyReviewResponses,
     UserFlag::NotifyChangeMerged,
     UserFlag::NotifyChangeAbandoned,
+    UserFlag::NotifyCiResults,
+    UserFlag::NotifyReverts,
+    UserFlag::NotifyForeignUpload,
+    UserFlag::NotifyMentions,
+    UserFlag::NotifyThreadReplies,
+    UserFlag::NotifyMergeConflicts,
+    UserFlag::NotifyReadyForReview,
+    UserFlag::NotifyTopicChanged,
+    UserFlag::NotifyHashtagsChanged,
+    UserFlag::NotifyVoteDeleted,
+    UserFlag::NotifyReviewerDeleted,
 ];
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
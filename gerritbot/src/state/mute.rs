@@ -0,0 +1,540 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use gerritbot_gerrit as gerrit;
+
+use crate::user_matches_account;
+
+/// Rule muting events based on their fields, e.g.
+/// `actor=ci-bot and label=Verified value>0`. All conditions of a rule have
+/// to match. Label and value conditions have to match the same approval of the
+/// event.
+#[derive(Debug, Clone)]
+pub struct MuteRule {
+    conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Operator {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "!=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+        }
+    }
+
+    fn compare<T: PartialOrd>(self, actual: T, expected: T) -> bool {
+        match self {
+            Operator::Equal => actual == expected,
+            Operator::NotEqual => actual != expected,
+            Operator::Greater => actual > expected,
+            Operator::GreaterOrEqual => actual >= expected,
+            Operator::Less => actual < expected,
+            Operator::LessOrEqual => actual <= expected,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Project,
+    Branch,
+    /// the user causing the event, e.g. the author of a comment or the added
+    /// reviewer
+    Actor,
+    Event,
+    Label,
+    Value,
+    Size,
+    Wip,
+    Change,
+    Topic,
+}
+
+impl Field {
+    fn as_str(self) -> &'static str {
+        match self {
+            Field::Project => "project",
+            Field::Branch => "branch",
+            Field::Actor => "actor",
+            Field::Event => "event",
+            Field::Label => "label",
+            Field::Value => "value",
+            Field::Size => "size",
+            Field::Wip => "wip",
+            Field::Change => "change",
+            Field::Topic => "topic",
+        }
+    }
+
+    fn is_numeric(self) -> bool {
+        matches!(self, Field::Value | Field::Size | Field::Change)
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match &s.to_lowercase()[..] {
+            "project" => Field::Project,
+            "branch" => Field::Branch,
+            // `author` is the former name, kept for existing rules
+            "actor" | "approver" | "author" => Field::Actor,
+            "event" => Field::Event,
+            "label" => Field::Label,
+            "value" => Field::Value,
+            "size" => Field::Size,
+            "wip" => Field::Wip,
+            "change" => Field::Change,
+            "topic" => Field::Topic,
+            _ => return Err(format!("unknown field `{}`", s)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Text(String),
+    /// wide enough for change numbers, which are unsigned
+    Number(i64),
+    Bool(bool),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Text(s) => s.fmt(f),
+            Value::Number(n) => n.fmt(f),
+            Value::Bool(b) => b.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    field: Field,
+    operator: Operator,
+    value: Value,
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}{}",
+            self.field.as_str(),
+            self.operator.as_str(),
+            self.value
+        )
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        lazy_static! {
+            static ref CONDITION_REGEX: Regex = Regex::new(r"^(\w+)(=|!=|>=|<=|>|<)(.+)$").unwrap();
+        };
+
+        let cap = CONDITION_REGEX
+            .captures(s)
+            .ok_or_else(|| format!("expected `<field><operator><value>` but got `{}`", s))?;
+        let field: Field = cap[1].parse()?;
+        let operator = match &cap[2] {
+            "=" => Operator::Equal,
+            "!=" => Operator::NotEqual,
+            ">" => Operator::Greater,
+            ">=" => Operator::GreaterOrEqual,
+            "<" => Operator::Less,
+            _ => Operator::LessOrEqual,
+        };
+        let raw_value = &cap[3];
+
+        let value = if field.is_numeric() {
+            raw_value
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| format!("`{}` expects a number", field.as_str()))?
+        } else if field == Field::Wip {
+            match &raw_value.to_lowercase()[..] {
+                "true" | "yes" => Value::Bool(true),
+                "false" | "no" => Value::Bool(false),
+                _ => return Err("`wip` expects `true` or `false`".to_string()),
+            }
+        } else {
+            Value::Text(raw_value.to_string())
+        };
+
+        if !field.is_numeric() && !matches!(operator, Operator::Equal | Operator::NotEqual) {
+            return Err(format!("`{}` only supports `=` and `!=`", field.as_str()));
+        }
+
+        Ok(Self {
+            field,
+            operator,
+            value,
+        })
+    }
+}
+
+impl Condition {
+    /// Evaluate conditions on the event itself, i.e. everything except label
+    /// and value.
+    fn matches_event<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        if let Some(matches) = self.matches_change(event.change()) {
+            return matches;
+        }
+
+        let patchset = event.patchset();
+
+        match (self.field, &self.value) {
+            (Field::Actor, Value::Text(actor)) => self
+                .operator
+                .compare(user_matches_account(event.actor(), actor), true),
+            (Field::Event, Value::Text(event_type)) => {
+                self.operator.compare(event.event_type(), &event_type[..])
+            }
+            (Field::Size, Value::Number(size)) => match patchset {
+                Some(patchset) => self.operator.compare(
+                    i64::from(patchset.size_insertions.abs() + patchset.size_deletions.abs()),
+                    *size,
+                ),
+                // the size of events without a patchset is unknown
+                None => false,
+            },
+            _ => true,
+        }
+    }
+
+    /// Evaluate conditions on the change, or return `None` for conditions on
+    /// a particular event.
+    fn matches_change(&self, change: &gerrit::Change) -> Option<bool> {
+        Some(match (self.field, &self.value) {
+            (Field::Project, Value::Text(project)) => {
+                self.operator.compare(&change.project, project)
+            }
+            (Field::Branch, Value::Text(branch)) => self.operator.compare(&change.branch, branch),
+            (Field::Wip, Value::Bool(wip)) => self.operator.compare(change.wip, *wip),
+            (Field::Change, Value::Number(number)) => {
+                self.operator.compare(i64::from(change.number), *number)
+            }
+            (Field::Topic, Value::Text(topic)) => {
+                self.operator.compare(change.topic.as_ref(), Some(topic))
+            }
+            _ => return None,
+        })
+    }
+
+    fn matches_approval(&self, approval: &gerrit::Approval) -> bool {
+        match (self.field, &self.value) {
+            (Field::Label, Value::Text(label)) => self
+                .operator
+                .compare(approval.approval_type.eq_ignore_ascii_case(label), true),
+            (Field::Value, Value::Number(value)) => approval
+                .value
+                .parse::<i64>()
+                .map(|actual| self.operator.compare(actual, *value))
+                .unwrap_or(false),
+            _ => true,
+        }
+    }
+
+    fn is_approval_condition(&self) -> bool {
+        matches!(self.field, Field::Label | Field::Value)
+    }
+}
+
+impl MuteRule {
+    /// Mute all events of a single change.
+    pub fn change(number: u32) -> Self {
+        Self {
+            conditions: vec![Condition {
+                field: Field::Change,
+                operator: Operator::Equal,
+                value: Value::Number(i64::from(number)),
+            }],
+        }
+    }
+
+    /// Mute all events of changes with the given topic.
+    pub fn topic(topic: &str) -> Self {
+        Self {
+            conditions: vec![Condition {
+                field: Field::Topic,
+                operator: Operator::Equal,
+                value: Value::Text(topic.to_string()),
+            }],
+        }
+    }
+
+    /// Whether the rule is restricted to the given change, i.e. it can expire
+    /// together with the change.
+    pub fn is_for_change(&self, number: u32) -> bool {
+        self.conditions.iter().any(|c| {
+            c.field == Field::Change
+                && c.operator == Operator::Equal
+                && c.value == Value::Number(i64::from(number))
+        })
+    }
+
+    pub fn matches<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        let mut approval_conditions = self
+            .conditions
+            .iter()
+            .filter(|c| c.is_approval_condition())
+            .peekable();
+
+        let approvals_match = approval_conditions.peek().is_none()
+            || event.approvals().iter().any(|approval| {
+                approval_conditions
+                    .clone()
+                    .all(|c| c.matches_approval(approval))
+            });
+
+        approvals_match
+            && self
+                .conditions
+                .iter()
+                .filter(|c| !c.is_approval_condition())
+                .all(|c| c.matches_event(event))
+    }
+
+    /// Whether the rule mutes everything about the change, i.e. it only has
+    /// conditions on the change and all of them match.
+    pub fn covers_change(&self, change: &gerrit::Change) -> bool {
+        self.conditions
+            .iter()
+            .all(|c| c.matches_change(change) == Some(true))
+    }
+}
+
+impl PartialEq for MuteRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl Eq for MuteRule {}
+
+impl Display for MuteRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, condition) in self.conditions.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" and ")?;
+            }
+            condition.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for MuteRule {
+    type Err = String;
+
+    /// Parse conditions separated by whitespace or `and`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let conditions = s
+            .split_whitespace()
+            .filter(|word| !word.eq_ignore_ascii_case("and"))
+            .map(str::parse)
+            .collect::<Result<Vec<Condition>, _>>()?;
+
+        if conditions.is_empty() {
+            return Err("no conditions given".to_string());
+        }
+
+        Ok(Self { conditions })
+    }
+}
+
+/// Serialize the rule as its normalized text.
+impl Serialize for MuteRule {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialize the rule by parsing its text.
+impl<'de> Deserialize<'de> for MuteRule {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(<D::Error as serde::de::Error>::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MuteRule;
+
+    const EVENT_JSON: &str = r#"
+{"author":{"name":"CI Bot","username":"ci-bot","email":"ci@example.com"},"approvals":[{"type":"Code-Review","description":"Code-Review","value":"0"},{"type":"Verified","description":"Verified","value":"1","oldValue":"0"}],"comment":"Patch Set 1: Verified+1","patchSet":{"number":1,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":[],"ref":"refs/changes/42/42/1","uploader":{"name":"Author","email":"author@example.com","username":"author"},"createdOn":1494165142,"author":{"name":"Author","email":"author@example.com","username":"author"},"kind":"REWORK","sizeInsertions":12,"sizeDeletions":-3},"change":{"project":"tools","branch":"master","id":"Ic160fa37fca005fec17a2434aadf0d9dcfbb7b14","number":49,"subject":"Some review.","topic":"refactoring","owner":{"name":"Author","email":"author@example.com","username":"author"},"url":"http://localhost/42","commitMessage":"Some review.\n","status":"NEW"},"type":"comment-added","eventCreatedOn":1499190282}"#;
+
+    fn get_event() -> gerritbot_gerrit::CommentAddedEvent {
+        serde_json::from_str(EVENT_JSON).expect("failed to decode event")
+    }
+
+    /// A topic changed event, which has no patchset.
+    fn get_topic_changed_event() -> gerritbot_gerrit::TopicChangedEvent {
+        let event = get_event();
+        gerritbot_gerrit::TopicChangedEvent {
+            change: event.change,
+            changer: event.author,
+            old_topic: None,
+            created_on: event.created_on,
+        }
+    }
+
+    fn matches(rule: &str) -> bool {
+        matches_event(rule, &get_event())
+    }
+
+    fn matches_event<E: gerritbot_gerrit::ChangeEvent>(rule: &str, event: &E) -> bool {
+        rule.parse::<MuteRule>()
+            .expect("parse failed")
+            .matches(event)
+    }
+
+    macro_rules! test_matches {
+        ($name:ident, $rule:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(matches($rule), $expected, "rule: {}", $rule);
+            }
+        };
+        ($name:ident, $rule:expr, $event:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(matches_event($rule, &$event), $expected, "rule: {}", $rule);
+            }
+        };
+    }
+
+    macro_rules! test_parse_fail {
+        ($name:ident, $rule:expr) => {
+            #[test]
+            fn $name() {
+                $rule.parse::<MuteRule>().expect_err("parse didn't fail");
+            }
+        };
+    }
+
+    test_matches!(
+        actor_label_and_value,
+        "actor=ci-bot and label=Verified value>0",
+        true
+    );
+    test_matches!(actor_email, "approver=ci@example.com", true);
+    test_matches!(actor_former_name, "author=ci-bot", true);
+    test_matches!(actor_other, "actor=alice", false);
+    test_matches!(actor_not_equal, "actor!=alice", true);
+    test_matches!(project, "project=tools", true);
+    test_matches!(project_and_branch, "project=tools branch=stable", false);
+    test_matches!(event_type, "event=comment-added", true);
+    // label and value have to match the same approval
+    test_matches!(label_and_value, "label=Code-Review value>0", false);
+    test_matches!(value, "value>=1", true);
+    test_matches!(size, "size<20", true);
+    test_matches!(size_greater, "size>20", false);
+    test_matches!(
+        size_without_patchset,
+        "size<20",
+        get_topic_changed_event(),
+        false
+    );
+    test_matches!(
+        actor_and_size_without_patchset,
+        "actor=ci-bot and size>20",
+        get_topic_changed_event(),
+        false
+    );
+    test_matches!(
+        actor_without_patchset,
+        "actor=ci-bot",
+        get_topic_changed_event(),
+        true
+    );
+    test_matches!(wip, "wip=true", false);
+    test_matches!(not_wip, "wip=no", true);
+
+    test_matches!(change, "change=49", true);
+    test_matches!(other_change, "change=50", false);
+    test_matches!(topic, "topic=refactoring", true);
+    test_matches!(other_topic, "topic=foo", false);
+    test_matches!(not_topic, "topic!=foo", true);
+
+    test_parse_fail!(empty, "");
+    test_parse_fail!(only_and, "and");
+    test_parse_fail!(unknown_field, "reviewer=alice");
+    test_parse_fail!(missing_operator, "actor");
+    test_parse_fail!(text_comparison, "project>tools");
+    test_parse_fail!(invalid_number, "value>x");
+    test_parse_fail!(invalid_bool, "wip=maybe");
+
+    #[test]
+    fn change_and_topic_rules() {
+        assert_eq!(MuteRule::change(49).to_string(), "change=49");
+        assert!(MuteRule::change(49).is_for_change(49));
+        assert!(!MuteRule::change(49).is_for_change(50));
+        // change numbers beyond the range of i32
+        let large = u32::MAX;
+        assert_eq!(MuteRule::change(large).to_string(), "change=4294967295");
+        assert!(MuteRule::change(large).is_for_change(large));
+        assert!(!MuteRule::change(large).is_for_change(1));
+        assert!("actor=ci-bot change=49"
+            .parse::<MuteRule>()
+            .unwrap()
+            .is_for_change(49));
+        assert!(!"change>=49".parse::<MuteRule>().unwrap().is_for_change(49));
+        assert_eq!(MuteRule::topic("foo").to_string(), "topic=foo");
+        assert!(!matches("topic=foo"));
+    }
+
+    #[test]
+    fn rules_covering_changes() {
+        let change = get_event().change;
+        let covers = |rule: &str| rule.parse::<MuteRule>().unwrap().covers_change(&change);
+        assert!(covers("change=49"));
+        assert!(covers("project=tools topic=refactoring"));
+        assert!(!covers("change=50"));
+        // conditions on events don't mute the change as a whole
+        assert!(!covers("change=49 actor=ci-bot"));
+        assert!(!covers("label=Verified"));
+    }
+
+    #[test]
+    fn normalized_display() {
+        let rule: MuteRule = "Approver=ci-bot  label=Verified AND value>0"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "actor=ci-bot and label=Verified and value>0"
+        );
+        assert_eq!(
+            rule,
+            "author=ci-bot and label=Verified and value>0"
+                .parse()
+                .unwrap()
+        );
+    }
+}
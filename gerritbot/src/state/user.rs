@@ -1,11 +1,39 @@
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
+use std::collections::BTreeMap;
+use std::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use gerritbot_gerrit as gerrit;
 use gerritbot_spark as spark;
 
-use super::filter::{deserialize_filter, serialize_filter, Filter};
-use super::flags::{UserFlag, UserFlags};
+use super::away::Away;
+use super::filter::{
+    self, deserialize_filter, deserialize_filters, serialize_filters, Filter, DEFAULT_FILTER_NAME,
+};
+use super::flags::{UserFlag, UserFlags, REVIEW_COMMENT_FLAGS};
+use super::mute::MuteRule;
+use super::watch::Watch;
+
+/// How events caused by a particular Gerrit account are treated for a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountPreference {
+    /// Never notify about events caused by the account.
+    Ignore,
+    /// Notify about events caused by the account even if the corresponding
+    /// flag is disabled.
+    AlwaysNotify,
+}
+
+impl fmt::Display for AccountPreference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AccountPreference::Ignore => f.write_str("ignored"),
+            AccountPreference::AlwaysNotify => f.write_str("always notify"),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -18,13 +46,36 @@ pub struct User {
     #[serde(skip_serializing_if = "UserFlags::is_default", default)]
     flags: UserFlags,
     enabled: bool,
+    // Legacy single filter. Only read and migrated to `filters`.
     #[serde(
-        skip_serializing_if = "Option::is_none",
-        serialize_with = "serialize_filter",
+        rename = "filter",
+        skip_serializing,
         deserialize_with = "deserialize_filter",
         default
     )]
-    filter: Option<Filter>,
+    legacy_filter: Option<Filter>,
+    #[serde(
+        skip_serializing_if = "Vec::is_empty",
+        serialize_with = "serialize_filters",
+        deserialize_with = "deserialize_filters",
+        default
+    )]
+    filters: Vec<Filter>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    watches: Vec<Watch>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    mutes: Vec<MuteRule>,
+    /// preferences keyed by Gerrit email or username
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    accounts: BTreeMap<String, AccountPreference>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    away: Option<Away>,
+    /// inline comments shown per review; all if unset
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    max_inline_comments: Option<u32>,
+    /// Gerrit username last seen with the email of the user
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    gerrit_username: Option<String>,
 }
 
 impl User {
@@ -32,9 +83,16 @@ impl User {
         Self {
             spark_person_id: None,
             email,
-            filter: None,
+            legacy_filter: None,
+            filters: Vec::new(),
             enabled: true,
             flags: UserFlags::Default,
+            watches: Vec::new(),
+            mutes: Vec::new(),
+            accounts: BTreeMap::new(),
+            away: None,
+            max_inline_comments: None,
+            gerrit_username: None,
         }
     }
 
@@ -42,6 +100,10 @@ impl User {
         &self.email
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
     pub fn has_any_flag<I, F>(&self, flags: I) -> bool
     where
         I: IntoIterator<Item = F>,
@@ -69,17 +131,215 @@ impl User {
         self.enabled = enabled;
     }
 
+    /// Move the legacy single filter to the named filters.
+    pub(super) fn migrate_legacy_filter(&mut self) {
+        if let Some(filter) = self.legacy_filter.take() {
+            if self.find_filter(&filter.name).is_none() {
+                self.filters.insert(0, filter);
+            }
+        }
+    }
+
+    fn find_filter(&self, name: &str) -> Option<&Filter> {
+        self.filters.iter().find(|f| f.name == name)
+    }
+
+    /// The default filter, i.e. the one set with the plain `filter <regex>`
+    /// command.
     pub fn filter(&self) -> Option<&Filter> {
-        self.filter.as_ref()
+        self.find_filter(DEFAULT_FILTER_NAME)
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
     }
 
+    /// Enable or disable the default filter.
     pub fn set_filter_enabled(&mut self, enabled: bool) {
-        if let Some(f) = self.filter.as_mut() {
-            f.enabled = enabled;
+        self.enable_filter(DEFAULT_FILTER_NAME, enabled);
+    }
+
+    /// Enable or disable the filter with the given name. Returns false if
+    /// there is none.
+    pub fn enable_filter(&mut self, name: &str, enabled: bool) -> bool {
+        match self.filters.iter_mut().find(|f| f.name == name) {
+            Some(f) => {
+                f.enabled = enabled;
+                true
+            }
+            None => false,
         }
     }
 
+    /// Add a filter, replacing any existing filter with the same name.
     pub fn set_filter(&mut self, filter: Filter) {
-        self.filter = Some(filter);
+        match self.filters.iter_mut().find(|f| f.name == filter.name) {
+            Some(existing) => *existing = filter,
+            None => self.filters.push(filter),
+        }
+    }
+
+    /// Remove the filter with the given name. Returns false if there was none.
+    pub fn remove_filter(&mut self, name: &str) -> bool {
+        let count = self.filters.len();
+        self.filters.retain(|f| f.name != name);
+        count != self.filters.len()
+    }
+
+    pub fn is_filtered(&self, msg: &str) -> bool {
+        filter::is_filtered(&self.filters, msg)
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Add a watch. Returns false if the user was already watching it.
+    pub fn add_watch(&mut self, watch: Watch) -> bool {
+        if self.watches.contains(&watch) {
+            false
+        } else {
+            self.watches.push(watch);
+            true
+        }
+    }
+
+    /// Remove the given watch or all watches if none is given. Returns the
+    /// number of removed watches.
+    pub fn remove_watches(&mut self, watch: Option<&Watch>) -> usize {
+        let count = self.watches.len();
+        match watch {
+            Some(watch) => self.watches.retain(|w| w != watch),
+            None => self.watches.clear(),
+        }
+        count - self.watches.len()
+    }
+
+    pub fn mutes(&self) -> &[MuteRule] {
+        &self.mutes
+    }
+
+    /// Add a mute rule. Returns false if the user already had it.
+    pub fn add_mute(&mut self, rule: MuteRule) -> bool {
+        if self.mutes.contains(&rule) {
+            false
+        } else {
+            self.mutes.push(rule);
+            true
+        }
+    }
+
+    /// Remove the given mute rule or all rules if none is given. Returns the
+    /// number of removed rules.
+    pub fn remove_mutes(&mut self, rule: Option<&MuteRule>) -> usize {
+        let count = self.mutes.len();
+        match rule {
+            Some(rule) => self.mutes.retain(|r| r != rule),
+            None => self.mutes.clear(),
+        }
+        count - self.mutes.len()
+    }
+
+    pub fn is_muted<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        self.mutes.iter().any(|rule| rule.matches(event))
+    }
+
+    /// Whether all events of the change are muted, e.g. by `mute <number>`.
+    pub fn is_change_muted(&self, change: &gerrit::Change) -> bool {
+        self.mutes.iter().any(|rule| rule.covers_change(change))
+    }
+
+    /// Remove follows and mute rules of a change that was merged or
+    /// abandoned. Returns false if there was nothing to remove.
+    pub fn expire_change(&mut self, number: u32) -> bool {
+        let count = self.watches.len() + self.mutes.len();
+        self.watches.retain(|watch| watch != &Watch::Change(number));
+        self.mutes.retain(|rule| !rule.is_for_change(number));
+        count != self.watches.len() + self.mutes.len()
+    }
+
+    pub fn account_preferences(&self) -> &BTreeMap<String, AccountPreference> {
+        &self.accounts
+    }
+
+    /// Set or reset the preference for a Gerrit account. Returns false if
+    /// nothing changed.
+    pub fn set_account_preference(
+        &mut self,
+        account: &str,
+        preference: Option<AccountPreference>,
+    ) -> bool {
+        match preference {
+            Some(preference) => {
+                self.accounts.insert(account.to_string(), preference) != Some(preference)
+            }
+            None => self.accounts.remove(account).is_some(),
+        }
+    }
+
+    fn account_preference(&self, account: &gerrit::User) -> Option<AccountPreference> {
+        self.accounts
+            .iter()
+            .find(|(name, _)| crate::user_matches_account(account, name))
+            .map(|(_, preference)| *preference)
+    }
+
+    /// The user as it should be considered for an event caused by `actor`:
+    /// `None` if the actor is ignored, and with the review comment flags
+    /// enabled if the user always wants to be notified about the actor.
+    pub fn for_actor(&self, actor: &gerrit::User) -> Option<Cow<'_, User>> {
+        match self.account_preference(actor) {
+            Some(AccountPreference::Ignore) => None,
+            Some(AccountPreference::AlwaysNotify) => {
+                let mut user = self.clone();
+                for flag in REVIEW_COMMENT_FLAGS {
+                    user.flags.set(*flag, true);
+                }
+                Some(Cow::Owned(user))
+            }
+            None => Some(Cow::Borrowed(self)),
+        }
+    }
+
+    pub fn away(&self) -> Option<&Away> {
+        self.away.as_ref()
+    }
+
+    pub fn away_mut(&mut self) -> Option<&mut Away> {
+        self.away.as_mut()
+    }
+
+    /// Set or clear the out of office status. Returns the previous one.
+    pub fn set_away(&mut self, away: Option<Away>) -> Option<Away> {
+        std::mem::replace(&mut self.away, away)
+    }
+
+    pub fn max_inline_comments(&self) -> Option<u32> {
+        self.max_inline_comments
+    }
+
+    /// Limit the number of inline comments shown per review, or show all of
+    /// them again. Returns false if nothing changed.
+    pub fn set_max_inline_comments(&mut self, max: Option<u32>) -> bool {
+        std::mem::replace(&mut self.max_inline_comments, max) != max
+    }
+
+    pub fn gerrit_username(&self) -> Option<&str> {
+        self.gerrit_username.as_deref()
+    }
+
+    /// Remember or forget the Gerrit username of the user. Returns false if
+    /// nothing changed.
+    pub fn set_gerrit_username(&mut self, username: Option<&str>) -> bool {
+        if self.gerrit_username.as_deref() == username {
+            return false;
+        }
+        self.gerrit_username = username.map(String::from);
+        true
+    }
+
+    /// Check if the user is out of office at the given unix timestamp.
+    pub fn is_away(&self, now: u64) -> bool {
+        self.away.iter().any(|away| away.is_active(now))
     }
 }
@@ -0,0 +1,445 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use gerritbot_gerrit as gerrit;
+
+use crate::query::Query;
+
+/// Subscription to changes regardless of whether the user is involved in them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Watch {
+    /// All changes in a project, optionally restricted to a single branch.
+    Project {
+        project: String,
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        branch: Option<String>,
+    },
+    /// All changes in a project touching files matching a path glob.
+    Path { project: String, path: PathGlob },
+    /// All changes with the given hashtag.
+    Hashtag(String),
+    /// All changes matching a Gerrit search expression.
+    Query(Query),
+    /// A single followed change. Expires when the change is merged or
+    /// abandoned.
+    Change(u32),
+    /// Updates of refs in a project matching a glob, e.g. new tags.
+    Refs { project: String, refs: PathGlob },
+}
+
+impl Watch {
+    pub fn matches<E: gerrit::ChangeEvent + ?Sized>(&self, event: &E) -> bool {
+        let change = event.change();
+        match self {
+            Watch::Project { project, branch } => {
+                &change.project == project && branch.iter().all(|branch| branch == &change.branch)
+            }
+            Watch::Path { project, .. } => {
+                &change.project == project && !self.matching_files(event).is_empty()
+            }
+            // Gerrit treats hashtags case insensitively.
+            Watch::Hashtag(hashtag) => change
+                .hashtags
+                .iter()
+                .flatten()
+                .any(|h| h.eq_ignore_ascii_case(hashtag)),
+            Watch::Query(query) => query.matches(event),
+            Watch::Change(number) => change.number == *number,
+            Watch::Refs { .. } => false,
+        }
+    }
+
+    pub fn matches_ref(&self, update: &gerrit::RefUpdate) -> bool {
+        match self {
+            Watch::Refs { project, refs } => {
+                &update.project == project && refs.is_match(&update.ref_name)
+            }
+            _ => false,
+        }
+    }
+
+    /// Return the files of the event's patchset matching a path watch. The
+    /// result is empty for other kinds of watches or if the files of the
+    /// patchset are unknown.
+    pub fn matching_files<'a, E: gerrit::ChangeEvent + ?Sized>(
+        &self,
+        event: &'a E,
+    ) -> Vec<&'a str> {
+        match self {
+            Watch::Path { path, .. } => event
+                .patchset()
+                .iter()
+                .flat_map(|patchset| patchset.files.iter().flatten())
+                .map(|file| &file.file[..])
+                .filter(|file| path.is_match(file))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Whether matching the watch needs the files of the patchset.
+    pub fn uses_files(&self) -> bool {
+        match self {
+            Watch::Path { .. } => true,
+            Watch::Query(query) => query.uses_files(),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Watch {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Watch::Project {
+                project,
+                branch: None,
+            } => write!(f, "project:{}", project),
+            Watch::Project {
+                project,
+                branch: Some(branch),
+            } => write!(f, "project:{} branch:{}", project, branch),
+            Watch::Path { project, path } => write!(f, "project:{} path:{}", project, path),
+            Watch::Hashtag(hashtag) => write!(f, "hashtag:{}", hashtag),
+            Watch::Query(query) => write!(f, "query {}", query),
+            Watch::Change(number) => write!(f, "change:{}", number),
+            Watch::Refs { project, refs } => write!(f, "refs project:{} {}", project, refs),
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = ();
+
+    /// Parse `project:<project> [branch:<branch>]`, `project:<project>
+    /// path:<glob>`, `hashtag:<hashtag>`, `change:<number>`, `query
+    /// <expression>` or `refs project:<project> <glob>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(query) = s
+            .get(..6)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("query "))
+            .map(|_| &s[6..])
+        {
+            return query.parse().map(Watch::Query).map_err(|_| ());
+        }
+
+        let non_empty = |s: &str| {
+            if s.is_empty() {
+                Err(())
+            } else {
+                Ok(s.to_string())
+            }
+        };
+
+        let mut words = s.split_whitespace();
+        let mut first = words.next().ok_or(())?;
+        let is_refs = first.eq_ignore_ascii_case("refs");
+        if is_refs {
+            first = words.next().ok_or(())?;
+        }
+        let second = words.next();
+
+        if words.next().is_some() {
+            return Err(());
+        }
+
+        if is_refs {
+            return Ok(Watch::Refs {
+                project: non_empty(first.strip_prefix("project:").ok_or(())?)?,
+                refs: second.ok_or(())?.parse()?,
+            });
+        }
+
+        if let (Some(project), Some(path)) = (
+            first.strip_prefix("project:"),
+            second.and_then(|second| second.strip_prefix("path:")),
+        ) {
+            Ok(Watch::Path {
+                project: non_empty(project)?,
+                path: non_empty(path)?.parse()?,
+            })
+        } else if let Some(project) = first.strip_prefix("project:") {
+            let branch = second
+                .map(|branch| branch.strip_prefix("branch:").ok_or(()).and_then(non_empty))
+                .transpose()?;
+            Ok(Watch::Project {
+                project: non_empty(project)?,
+                branch,
+            })
+        } else if let (Some(hashtag), None) = (first.strip_prefix("hashtag:"), second) {
+            Ok(Watch::Hashtag(non_empty(hashtag)?))
+        } else if let (Some(number), None) = (first.strip_prefix("change:"), second) {
+            number.parse().map(Watch::Change).map_err(|_| ())
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Glob matching file paths. `*` and `?` don't match across directories,
+/// while `**` does.
+#[derive(Debug, Clone)]
+pub struct PathGlob {
+    glob: String,
+    regex: Regex,
+}
+
+impl PathGlob {
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+}
+
+impl PartialEq for PathGlob {
+    fn eq(&self, other: &Self) -> bool {
+        self.glob == other.glob
+    }
+}
+
+impl Eq for PathGlob {}
+
+impl Display for PathGlob {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.glob.fmt(f)
+    }
+}
+
+impl FromStr for PathGlob {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pattern = String::from("^");
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    // `**/` also matches no directory at all
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        pattern.push_str("(?:.*/)?");
+                    } else {
+                        pattern.push_str(".*");
+                    }
+                }
+                '*' => pattern.push_str("[^/]*"),
+                '?' => pattern.push_str("[^/]"),
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        pattern.push('$');
+
+        Regex::new(&pattern)
+            .map(|regex| Self {
+                glob: s.to_string(),
+                regex,
+            })
+            .map_err(|_| ())
+    }
+}
+
+impl Serialize for PathGlob {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.glob)
+    }
+}
+
+impl<'de> Deserialize<'de> for PathGlob {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let glob = String::deserialize(deserializer)?;
+        glob.parse()
+            .map_err(|()| <D::Error as serde::de::Error>::custom("invalid path glob"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PathGlob, Watch};
+
+    const EVENT_JSON: &str = r#"{"change":{"project":"tools","branch":"release","id":"I5e53df227fd2739ddd65c3034b2f9f789200bd89","number":1,"subject":"Some change","owner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"url":"http://localhost:8080/1","commitMessage":"Some change\n","hashtags":["Refactoring"],"status":"NEW"},"patchSet":{"number":1,"revision":"49a65998c02eda928559f2d0b586c20bc8e37b10","parents":[],"ref":"refs/changes/01/1/1","uploader":{"name":"Administrator","email":"admin@example.com","username":"admin"},"createdOn":1494165142,"author":{"name":"Administrator","email":"admin@example.com","username":"admin"},"kind":"REWORK","sizeInsertions":1,"sizeDeletions":0,"files":[{"file":"/COMMIT_MSG","type":"ADDED","insertions":5,"deletions":0},{"file":"src/net/socket.rs","type":"MODIFIED","insertions":1,"deletions":0},{"file":"README.md","type":"MODIFIED","insertions":1,"deletions":0}]},"abandoner":{"name":"Administrator","email":"admin@example.com","username":"admin"},"eventCreatedOn":1494165142}"#;
+
+    fn get_event() -> gerritbot_gerrit::ChangeAbandonedEvent {
+        serde_json::from_str(EVENT_JSON).expect("failed to decode event")
+    }
+
+    macro_rules! test_from_to_string {
+        ($name:ident, $s:expr, $w:expr $( , )?) => {
+            #[test]
+            fn $name() {
+                assert_eq!($s.parse::<Watch>().expect("parse failed"), $w);
+                assert_eq!($w.to_string(), $s);
+            }
+        };
+    }
+
+    macro_rules! test_parse_fail {
+        ($name:ident, $s:expr) => {
+            #[test]
+            fn $name() {
+                $s.parse::<Watch>().expect_err("did not fail");
+            }
+        };
+    }
+
+    test_from_to_string!(
+        project,
+        "project:tools",
+        Watch::Project {
+            project: "tools".to_string(),
+            branch: None
+        },
+    );
+    test_from_to_string!(
+        project_and_branch,
+        "project:tools branch:release",
+        Watch::Project {
+            project: "tools".to_string(),
+            branch: Some("release".to_string())
+        },
+    );
+    test_from_to_string!(
+        project_and_path,
+        "project:tools path:src/net/**",
+        Watch::Path {
+            project: "tools".to_string(),
+            path: "src/net/**".parse().unwrap()
+        },
+    );
+    test_from_to_string!(
+        hashtag,
+        "hashtag:Refactoring",
+        Watch::Hashtag("Refactoring".to_string()),
+    );
+
+    test_from_to_string!(
+        query,
+        "query project:tools -is:wip",
+        Watch::Query("project:tools -is:wip".parse().unwrap()),
+    );
+
+    test_from_to_string!(change, "change:1", Watch::Change(1));
+
+    test_from_to_string!(
+        refs,
+        "refs project:firmware refs/tags/v*",
+        Watch::Refs {
+            project: "firmware".to_string(),
+            refs: "refs/tags/v*".parse().unwrap()
+        },
+    );
+
+    test_parse_fail!(empty, "");
+    test_parse_fail!(invalid_change, "change:abc");
+    test_parse_fail!(empty_project, "project:");
+    test_parse_fail!(empty_branch, "project:tools branch:");
+    test_parse_fail!(unknown_prefix, "owner:admin");
+    test_parse_fail!(hashtag_with_branch, "hashtag:foo branch:master");
+    test_parse_fail!(empty_path, "project:tools path:");
+    test_parse_fail!(trailing_words, "project:tools branch:master foo");
+    test_parse_fail!(invalid_query, "query project:tools)");
+    test_parse_fail!(refs_without_glob, "refs project:firmware");
+    test_parse_fail!(refs_without_project, "refs refs/tags/*");
+
+    #[test]
+    fn matches_ref() {
+        let watch: Watch = "refs project:firmware refs/tags/v*".parse().unwrap();
+        let update = |project: &str, ref_name: &str| gerritbot_gerrit::RefUpdate {
+            old_rev: "0000000000000000000000000000000000000000".to_string(),
+            new_rev: "c4f7d43450e366f9c8e4dcb94fbd91573cd40766".to_string(),
+            ref_name: ref_name.to_string(),
+            project: project.to_string(),
+        };
+        assert!(watch.matches_ref(&update("firmware", "refs/tags/v1.0")));
+        assert!(!watch.matches_ref(&update("firmware", "refs/heads/v1.0")));
+        assert!(!watch.matches_ref(&update("tools", "refs/tags/v1.0")));
+        assert!(!watch.matches(&get_event()));
+    }
+
+    #[test]
+    fn matches_project() {
+        let event = get_event();
+        assert!("project:tools".parse::<Watch>().unwrap().matches(&event));
+        assert!("project:tools branch:release"
+            .parse::<Watch>()
+            .unwrap()
+            .matches(&event));
+        assert!(!"project:tools branch:master"
+            .parse::<Watch>()
+            .unwrap()
+            .matches(&event));
+        assert!(!"project:other".parse::<Watch>().unwrap().matches(&event));
+    }
+
+    #[test]
+    fn matches_change() {
+        let event = get_event();
+        assert!(Watch::Change(1).matches(&event));
+        assert!(!Watch::Change(2).matches(&event));
+    }
+
+    #[test]
+    fn matches_path() {
+        let event = get_event();
+        let watch: Watch = "project:tools path:src/net/**".parse().unwrap();
+        assert!(watch.matches(&event));
+        assert_eq!(watch.matching_files(&event), vec!["src/net/socket.rs"]);
+        assert!(!"project:tools path:src/io/**"
+            .parse::<Watch>()
+            .unwrap()
+            .matches(&event));
+        assert!(!"project:other path:src/net/**"
+            .parse::<Watch>()
+            .unwrap()
+            .matches(&event));
+        assert!("project:tools"
+            .parse::<Watch>()
+            .unwrap()
+            .matching_files(&event)
+            .is_empty());
+    }
+
+    #[test]
+    fn path_globs() {
+        let glob = |s: &str| s.parse::<PathGlob>().unwrap();
+        assert!(glob("src/net/**").is_match("src/net/socket.rs"));
+        assert!(glob("src/net/**").is_match("src/net/tcp/stream.rs"));
+        assert!(!glob("src/net/**").is_match("src/network.rs"));
+        assert!(glob("src/*.rs").is_match("src/lib.rs"));
+        assert!(!glob("src/*.rs").is_match("src/net/socket.rs"));
+        assert!(glob("**/*.md").is_match("README.md"));
+        assert!(glob("**/*.md").is_match("docs/usage.md"));
+        assert!(glob("src/?.rs").is_match("src/a.rs"));
+        assert!(!glob("src/lib.rs").is_match("src/lib_rs"));
+    }
+
+    #[test]
+    fn matches_hashtag() {
+        let event = get_event();
+        assert!("hashtag:refactoring"
+            .parse::<Watch>()
+            .unwrap()
+            .matches(&event));
+        assert!(!"hashtag:other".parse::<Watch>().unwrap().matches(&event));
+    }
+
+    #[test]
+    fn matches_query() {
+        let event = get_event();
+        assert!(
+            "query hashtag:refactoring (branch:release OR branch:master)"
+                .parse::<Watch>()
+                .unwrap()
+                .matches(&event)
+        );
+        assert!(!"query project:tools is:wip"
+            .parse::<Watch>()
+            .unwrap()
+            .matches(&event));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use gerritbot_gerrit as gerrit;
+use gerritbot_spark as spark;
+
+use crate::args::TeamsConfig;
+
+/// Webex Teams rooms of teams, defined by Gerrit groups.
+///
+/// Reviews requested from members of a team are announced once in the room of
+/// the team instead of notifying every member. The group members are fetched
+/// separately, and clones share them, so they can be updated while the bot is
+/// running.
+#[derive(Debug, Clone, Default)]
+pub struct Teams {
+    rooms: Arc<Vec<(String, spark::RoomId)>>,
+    members: Arc<RwLock<HashMap<String, Vec<gerrit::User>>>>,
+}
+
+impl Teams {
+    pub fn new(rooms: Vec<(String, spark::RoomId)>) -> Self {
+        Self {
+            rooms: Arc::new(rooms),
+            members: Default::default(),
+        }
+    }
+
+    pub fn from_config(config: &TeamsConfig) -> Self {
+        Self::new(
+            config
+                .rooms
+                .iter()
+                .map(|(group, room)| (group.clone(), spark::RoomId::new(room.clone())))
+                .collect(),
+        )
+    }
+
+    /// The Gerrit groups of all teams.
+    pub fn groups(&self) -> impl Iterator<Item = &str> {
+        self.rooms.iter().map(|(group, _)| &group[..])
+    }
+
+    /// Replace the members of the Gerrit group of a team.
+    pub fn set_group_members(&self, group: &str, members: Vec<gerrit::User>) {
+        self.members
+            .write()
+            .unwrap()
+            .insert(group.to_string(), members);
+    }
+
+    /// The teams of a user as group name and room.
+    pub fn teams_of(&self, user: &gerrit::User) -> Vec<(&str, &spark::RoomIdRef)> {
+        let members = self.members.read().unwrap();
+        self.rooms
+            .iter()
+            .filter(|(group, _)| {
+                members
+                    .get(group)
+                    .iter()
+                    .flat_map(|members| members.iter())
+                    .any(|member| same_account(member, user))
+            })
+            .map(|(group, room)| (&group[..], &**room))
+            .collect()
+    }
+}
+
+fn same_account(user: &gerrit::User, other: &gerrit::User) -> bool {
+    match (&user.email, &other.email) {
+        (Some(email), Some(other)) => email.eq_ignore_ascii_case(other),
+        _ => user.username.is_some() && user.username == other.username,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn user(username: &str) -> gerrit::User {
+        gerrit::User {
+            name: None,
+            username: Some(username.to_string()),
+            email: Some(format!("{}@example.com", username)),
+        }
+    }
+
+    #[test]
+    fn teams_of_group_members() {
+        let teams = Teams::new(vec![
+            (
+                "Core".to_string(),
+                spark::RoomId::new("core-room".to_string()),
+            ),
+            (
+                "Docs".to_string(),
+                spark::RoomId::new("docs-room".to_string()),
+            ),
+        ]);
+        let other = teams.clone();
+        assert!(other.teams_of(&user("alice")).is_empty());
+
+        teams.set_group_members("Core", vec![user("alice"), user("bob")]);
+        teams.set_group_members("Docs", vec![user("alice")]);
+        let groups = |user| -> Vec<String> {
+            other
+                .teams_of(&user)
+                .into_iter()
+                .map(|(group, room)| format!("{}:{}", group, room))
+                .collect()
+        };
+        assert_eq!(
+            groups(user("alice")),
+            vec!["Core:core-room", "Docs:docs-room"]
+        );
+        assert_eq!(groups(user("bob")), vec!["Core:core-room"]);
+        assert!(groups(user("carol")).is_empty());
+    }
+}
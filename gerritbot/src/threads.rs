@@ -0,0 +1,239 @@
+use serde::Serialize;
+
+use gerritbot_gerrit as gerrit;
+
+use crate::state::Date;
+
+/// Comments updated at most this many seconds before or after a comment event
+/// are considered to be published with it.
+const PUBLISH_WINDOW: u64 = 5;
+
+/// How a reply changed the resolved state of its thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    Resolved,
+    Reopened,
+}
+
+/// A reply to an inline comment thread.
+#[derive(Debug, Serialize)]
+pub struct ThreadReply<'a> {
+    pub comment: &'a gerrit::CommentInfo,
+    /// the comment replied to
+    pub parent: &'a gerrit::CommentInfo,
+    pub resolution: Option<Resolution>,
+    /// authors of all earlier comments of the thread
+    #[serde(skip)]
+    pub participants: Vec<&'a gerrit::User>,
+}
+
+/// Parse a REST API timestamp like `2019-04-01 10:00:00.000000000` into a
+/// unix timestamp.
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let date: Date = timestamp.get(..10)?.parse().ok()?;
+    let mut time = timestamp
+        .get(11..19)?
+        .split(':')
+        .map(|part| part.parse::<u64>());
+    match (time.next(), time.next(), time.next()) {
+        (Some(Ok(hours)), Some(Ok(minutes)), Some(Ok(seconds))) => {
+            Some(date.timestamp() + hours * 3600 + minutes * 60 + seconds)
+        }
+        _ => None,
+    }
+}
+
+fn same_user(user: &gerrit::User, other: &gerrit::User) -> bool {
+    match (&user.email, &other.email) {
+        (Some(email), Some(other)) => email.eq_ignore_ascii_case(other),
+        _ => user.username.is_some() && user.username == other.username,
+    }
+}
+
+/// Find the replies published by the author of a comment event. `comments`
+/// are all comments of the change, sorted by time.
+pub fn find_replies<'a>(
+    comments: &'a [gerrit::CommentInfo],
+    author: &gerrit::User,
+    created_on: u64,
+) -> Vec<ThreadReply<'a>> {
+    let find = |id: &str| comments.iter().find(|comment| comment.id == id);
+
+    comments
+        .iter()
+        .filter(|comment| matches!(&comment.author, Some(a) if same_user(a, author)))
+        .filter(|comment| {
+            matches!(
+                parse_timestamp(&comment.updated),
+                Some(t) if t + PUBLISH_WINDOW >= created_on && t <= created_on + PUBLISH_WINDOW
+            )
+        })
+        .filter_map(|comment| {
+            let parent = find(comment.in_reply_to.as_ref()?)?;
+
+            let mut participants: Vec<&gerrit::User> = Vec::new();
+            let mut ancestor = Some(parent);
+            while let Some(current) = ancestor {
+                if let Some(user) = &current.author {
+                    if !same_user(user, author) && !participants.iter().any(|p| same_user(p, user))
+                    {
+                        participants.push(user);
+                    }
+                }
+                ancestor = current.in_reply_to.as_ref().and_then(|id| find(id));
+            }
+
+            let resolution = match (parent.unresolved, comment.unresolved) {
+                (true, false) => Some(Resolution::Resolved),
+                (false, true) => Some(Resolution::Reopened),
+                _ => None,
+            };
+
+            Some(ThreadReply {
+                comment,
+                parent,
+                resolution,
+                participants,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn user(name: &str) -> gerrit::User {
+        gerrit::User {
+            name: None,
+            username: Some(name.to_string()),
+            email: Some(format!("{}@example.com", name)),
+        }
+    }
+
+    fn comment(
+        id: &str,
+        in_reply_to: Option<&str>,
+        author: &str,
+        updated: &str,
+        unresolved: bool,
+    ) -> gerrit::CommentInfo {
+        gerrit::CommentInfo {
+            id: id.to_string(),
+            path: "src/main.rs".to_string(),
+            patch_set: Some(1),
+            line: Some(3),
+            in_reply_to: in_reply_to.map(String::from),
+            message: format!("comment {}", id),
+            updated: updated.to_string(),
+            author: Some(user(author)),
+            unresolved,
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(
+            parse_timestamp("2019-04-01 10:00:01.000000000"),
+            Some(1_554_112_801)
+        );
+        assert_eq!(parse_timestamp("2019-04-01"), None);
+    }
+
+    #[test]
+    fn replies_notify_earlier_participants() {
+        let comments = vec![
+            comment("1", None, "reviewer", "2019-04-01 10:00:00.000000000", true),
+            comment(
+                "2",
+                Some("1"),
+                "author",
+                "2019-04-01 11:00:00.000000000",
+                true,
+            ),
+            comment(
+                "3",
+                Some("2"),
+                "other",
+                "2019-04-01 12:00:00.000000000",
+                true,
+            ),
+            comment(
+                "4",
+                Some("3"),
+                "author",
+                "2019-04-02 10:00:00.000000000",
+                false,
+            ),
+            comment("5", None, "author", "2019-04-02 10:00:00.000000000", true),
+        ];
+
+        // 2019-04-02 10:00:01
+        let replies = find_replies(&comments, &user("author"), 1_554_199_201);
+        assert_eq!(replies.len(), 1);
+        let reply = &replies[0];
+        assert_eq!(reply.comment.id, "4");
+        assert_eq!(reply.parent.id, "3");
+        assert_eq!(reply.resolution, Some(Resolution::Resolved));
+        let participants: Vec<_> = reply
+            .participants
+            .iter()
+            .filter_map(|user| user.username.as_deref())
+            .collect();
+        assert_eq!(participants, vec!["other", "reviewer"]);
+    }
+
+    #[test]
+    fn reopened_threads() {
+        let comments = vec![
+            comment(
+                "1",
+                None,
+                "reviewer",
+                "2019-04-01 10:00:00.000000000",
+                false,
+            ),
+            comment(
+                "2",
+                Some("1"),
+                "author",
+                "2019-04-01 10:00:00.000000000",
+                true,
+            ),
+        ];
+        let replies = find_replies(&comments, &user("author"), 1_554_112_800);
+        assert_eq!(replies[0].resolution, Some(Resolution::Reopened));
+    }
+
+    #[test]
+    fn consecutive_publishes() {
+        let comments = vec![
+            comment("1", None, "reviewer", "2019-04-01 10:00:00.000000000", true),
+            comment(
+                "2",
+                Some("1"),
+                "author",
+                "2019-04-01 11:00:00.000000000",
+                true,
+            ),
+            comment(
+                "3",
+                Some("2"),
+                "author",
+                "2019-04-01 11:00:20.000000000",
+                false,
+            ),
+        ];
+
+        // 2019-04-01 11:00:00
+        let replies = find_replies(&comments, &user("author"), 1_554_116_400);
+        let ids: Vec<_> = replies.iter().map(|reply| &reply.comment.id[..]).collect();
+        assert_eq!(ids, vec!["2"]);
+
+        // 2019-04-01 11:00:20
+        let replies = find_replies(&comments, &user("author"), 1_554_116_420);
+        let ids: Vec<_> = replies.iter().map(|reply| &reply.comment.id[..]).collect();
+        assert_eq!(ids, vec!["3"]);
+    }
+}
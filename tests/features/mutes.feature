@@ -0,0 +1,30 @@
+Feature: muting events
+
+  Users can mute events based on their fields, independent of how the
+  messages are formatted.
+
+  Background:
+    Given a person named Alice Smith with email address alice@bloom.com
+      And a person named Bob Jones with email address bob@jones.com
+      And everybody sends the enable command to the bot
+      And a Gerrit project named tools
+
+  Scenario: no message for a muted event
+     Given Bob sends the mute when author=alice and label=Code-Review value>0 command to the bot
+       And Bob uploads a new change to the tools project
+       And Alice replies to Bob's change with Code-Review+2
+      When we check for messages by the bot
+      Then there is no message for Bob which includes the text "Code-Review"
+
+  Scenario: message for events not matching the mute rule
+     Given Bob sends the mute when author=alice and label=Code-Review value<0 command to the bot
+       And Bob uploads a new change to the tools project
+       And Alice replies to Bob's change with Code-Review+2
+      When we check for messages by the bot
+      Then there is a message for Bob which includes the text "Code-Review"
+
+  Scenario: mute rules show up in status
+     Given Bob sends the mute when project=tools wip=true command to the bot
+       And Bob sends the status command to the bot
+      When we check for messages by the bot
+      Then there is a message for Bob which includes the text "project=tools and wip=true"
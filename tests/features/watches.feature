@@ -0,0 +1,57 @@
+Feature: watching projects, paths and hashtags
+
+  Users can watch projects, paths or hashtags to get notifications about changes they
+  are not involved in.
+
+  Background:
+    Given a person named Alice Smith with email address alice@bloom.com
+      And a person named Bob Jones with email address bob@jones.com
+      And a person named Eve Harris with email address eve@jones.com
+      And everybody sends the enable command to the bot
+      And a Gerrit project named tools
+
+  Scenario: get a message for a change in a watched project
+     Given Eve sends the watch project:tools command to the bot
+       And Bob uploads a new change to the tools project
+       And Alice replies to Bob's change with Code-Review+2
+      When we check for messages by the bot
+      Then there is a message for Eve which includes the text "Code-Review"
+       And this message includes the text "Watching `project:tools`"
+
+  Scenario: no message after unwatching
+     Given Eve sends the watch project:tools command to the bot
+       And Eve sends the unwatch command to the bot
+       And Bob uploads a new change to the tools project
+       And Alice replies to Bob's change with Code-Review+2
+      When we check for messages by the bot
+      Then there is no message for Eve which includes the text "Code-Review"
+
+  Scenario: watches show up in status
+     Given Eve sends the watch project:tools branch:master command to the bot
+       And Eve sends the status command to the bot
+      When we check for messages by the bot
+      Then there is a message for Eve which includes the text "Notifications for you are **enabled**"
+       And this message includes the text "project:tools branch:master"
+
+  Scenario: get a message for a change matching a watched query
+     Given Eve sends the watch query project:tools label:Code-Review>=1 command to the bot
+       And Bob uploads a new change to the tools project
+       And Alice replies to Bob's change with Code-Review+2
+      When we check for messages by the bot
+      Then there is a message for Eve which includes the text "Watching `query project:tools label:Code-Review>=1`"
+
+  Scenario: invalid queries are reported with the failing position
+     Given Eve sends the watch query project:tools reviewer:alice command to the bot
+      When we check for messages by the bot
+      Then there is a message for Eve which includes the text "unsupported operator `reviewer:`"
+
+  Scenario: get a message listing the matching files of a watched path
+     Given Eve sends the watch project:tools path:src/net/** command to the bot
+       And Bob uploads a new change to the tools project
+       And Bob creates the file "src/net/socket.rs" with the following content in the change:
+         """
+         // sockets
+         """
+      When we check for messages by the bot
+      Then there is a message for Eve which includes the text "`src/net/socket.rs`"
+       And this message includes the text "Watching `project:tools path:src/net/**`"